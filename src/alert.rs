@@ -0,0 +1,340 @@
+//! Alert rules, given with `--alert` (e.g. `'rate > 1M for 3 cooldown 5m'`) or defined by name in
+//! `alerts.toml`, next to the configuration file:
+//!
+//! ```toml
+//! [errors]
+//! rule = "col[3].delta > 0"
+//! for = 3
+//! cooldown = "5m"
+//! severity = "critical"
+//! color = "purple"
+//! blink = true
+//! hook = "notify-oncall.sh"
+//! ```
+
+use std::{
+    path::Path,
+    str::FromStr,
+    time::{Duration, Instant},
+};
+
+use ansi_term::Colour;
+use anyhow::{anyhow, Result};
+
+use crate::condition::Rule;
+use crate::config::{get_config_path, Config, Table, Value};
+use crate::options::parse_duration;
+
+#[derive(Debug, Clone)]
+pub struct Alert {
+    /// name of the section defining the rule, if any
+    pub name: Option<String>,
+    pub rule: Rule,
+    pub severity: Option<String>,
+    /// background of the values breaching the rule (that of the theme by default)
+    pub color: Option<Colour>,
+    /// whether the values breaching the rule blink
+    pub blink: bool,
+    /// shell command run when the rule starts firing
+    pub hook: Option<String>,
+    /// number of consecutive frames the rule must hold for before the alert fires
+    pub sustain: u32,
+    /// time during which an alert that fired is not reported again
+    pub cooldown: Option<Duration>,
+}
+
+impl From<Rule> for Alert {
+    fn from(rule: Rule) -> Self {
+        Self {
+            name: None,
+            rule,
+            severity: None,
+            color: None,
+            blink: false,
+            hook: None,
+            sustain: 1,
+            cooldown: None,
+        }
+    }
+}
+
+impl FromStr for Alert {
+    type Err = String;
+
+    /// Parse a rule, optionally followed by `for N` (frames) and `cooldown DURATION`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let words: Vec<&str> = s.split_whitespace().collect();
+        let end = words
+            .iter()
+            .position(|w| *w == "for" || *w == "cooldown")
+            .unwrap_or(words.len());
+        let mut alert = Alert::from(words[..end].join(" ").parse::<Rule>()?);
+
+        let mut options = words[end..].chunks(2);
+        for option in options.by_ref() {
+            match option {
+                ["for", n] => {
+                    alert.sustain = n
+                        .parse()
+                        .ok()
+                        .filter(|&n| n > 0)
+                        .ok_or_else(|| format!("invalid number of frames '{}'", n))?
+                }
+                ["cooldown", d] => alert.cooldown = Some(parse_duration(d)?),
+                _ => {
+                    return Err(format!(
+                        "invalid alert '{}', expected e.g. 'rate > 1M for 3'",
+                        s
+                    ))
+                }
+            }
+        }
+        Ok(alert)
+    }
+}
+
+impl Alert {
+    /// How the alert is referred to by hooks: its name, or else its rule.
+    pub fn title(&self) -> String {
+        self.name.clone().unwrap_or_else(|| self.rule.to_string())
+    }
+
+    /// How the alert is shown in the banner: its title, and its severity if any.
+    pub fn label(&self) -> String {
+        match &self.severity {
+            Some(severity) => format!("{} ({})", self.title(), severity),
+            None => self.title(),
+        }
+    }
+
+    fn from_table(name: &str, table: &Table) -> Result<Self> {
+        let string = |key: &str| -> Result<Option<String>> {
+            table
+                .get(key)
+                .map(|v| {
+                    v.as_str()
+                        .map(str::to_owned)
+                        .ok_or_else(|| anyhow!("alert '{}': invalid value for '{}'", name, key))
+                })
+                .transpose()
+        };
+        let rule = string("rule")?
+            .ok_or_else(|| anyhow!("alert '{}': missing 'rule'", name))?
+            .parse()
+            .map_err(|e| anyhow!("alert '{}': {}", name, e))?;
+        let color = string("color")?
+            .map(|c| {
+                parse_color(&c).ok_or_else(|| anyhow!("alert '{}': unknown color '{}'", name, c))
+            })
+            .transpose()?;
+        let sustain = match table.get("for") {
+            Some(v) => v
+                .as_integer()
+                .and_then(|n| u32::try_from(n).ok())
+                .filter(|&n| n > 0)
+                .ok_or_else(|| anyhow!("alert '{}': invalid value for 'for'", name))?,
+            None => 1,
+        };
+        let cooldown = table
+            .get("cooldown")
+            .map(|v| {
+                match v {
+                    Value::Integer(i) => parse_duration(&i.to_string()).ok(),
+                    _ => v.as_str().and_then(|s| parse_duration(s).ok()),
+                }
+                .ok_or_else(|| anyhow!("alert '{}': invalid value for 'cooldown'", name))
+            })
+            .transpose()?;
+        Ok(Self {
+            name: Some(name.to_owned()),
+            rule,
+            severity: string("severity")?,
+            color,
+            blink: match table.get("blink") {
+                Some(v) => v
+                    .as_bool()
+                    .ok_or_else(|| anyhow!("alert '{}': invalid value for 'blink'", name))?,
+                None => false,
+            },
+            hook: string("hook")?,
+            sustain,
+            cooldown,
+        })
+    }
+}
+
+/// Change in the state of an alert to report.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Transition {
+    Fired,
+    Resolved,
+}
+
+/// Whether an alert is firing, given how long its rule has held for.
+#[derive(Debug, Default, Clone)]
+pub struct State {
+    /// consecutive frames the rule held for
+    streak: u32,
+    pub active: bool,
+    /// whether the current firing was reported (i.e. not within a cooldown)
+    reported: bool,
+    last_report: Option<Instant>,
+}
+
+impl State {
+    /// Account for a frame, in which the rule held (`breached`) or not, returning the change to
+    /// report if any.
+    pub fn update(&mut self, alert: &Alert, breached: bool, now: Instant) -> Option<Transition> {
+        self.streak = if breached { self.streak + 1 } else { 0 };
+        let active = self.streak >= alert.sustain;
+        match (self.active, active) {
+            (false, true) => {
+                self.active = true;
+                self.reported = !alert.cooldown.is_some_and(|cooldown| {
+                    self.last_report
+                        .is_some_and(|last| now.duration_since(last) < cooldown)
+                });
+                if self.reported {
+                    self.last_report = Some(now);
+                    return Some(Transition::Fired);
+                }
+                None
+            }
+            (true, false) => {
+                self.active = false;
+                std::mem::take(&mut self.reported).then_some(Transition::Resolved)
+            }
+            _ => None,
+        }
+    }
+}
+
+fn parse_color(name: &str) -> Option<Colour> {
+    Some(match name {
+        "black" => Colour::Black,
+        "red" => Colour::Red,
+        "green" => Colour::Green,
+        "yellow" => Colour::Yellow,
+        "blue" => Colour::Blue,
+        "purple" | "magenta" => Colour::Purple,
+        "cyan" => Colour::Cyan,
+        "white" => Colour::White,
+        _ => return None,
+    })
+}
+
+/// Parse the named rules of an alerts file, one section per rule, in the order of their names.
+pub fn parse(config: &Config) -> Result<Vec<Alert>> {
+    let mut sections: Vec<_> = config
+        .sections()
+        .filter(|(name, table)| !name.is_empty() || !table.is_empty())
+        .collect();
+    sections.sort_by_key(|(name, _)| *name);
+    sections
+        .into_iter()
+        .map(|(name, table)| match name {
+            "" => Err(anyhow!("alerts: rules must be defined in named sections")),
+            _ => Alert::from_table(name, table),
+        })
+        .collect()
+}
+
+/// Load `alerts.toml` from the configuration directory, if present.
+pub fn load() -> Result<Vec<Alert>> {
+    match get_config_path("alerts.toml") {
+        Some(path) if path.exists() => from_file(&path),
+        _ => Ok(Vec::new()),
+    }
+}
+
+fn from_file(path: &Path) -> Result<Vec<Alert>> {
+    parse(&Config::from_file(path)?).map_err(|e| anyhow!("{}: {}", path.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let config = Config::parse(
+            r#"
+            [spikes]
+            rule = "rate > 1M"
+
+            [errors]
+            rule = "col[3].delta > 0"
+            severity = "critical"
+            color = "purple"
+            blink = true
+            hook = "page.sh"
+            "#,
+        )
+        .unwrap();
+        let alerts = parse(&config).unwrap();
+        assert_eq!(alerts.len(), 2);
+        assert_eq!(alerts[0].label(), "errors (critical)");
+        assert_eq!(alerts[0].rule.column, Some(3));
+        assert_eq!(alerts[0].color, Some(Colour::Purple));
+        assert!(alerts[0].blink && !alerts[1].blink);
+        assert_eq!(alerts[0].hook.as_deref(), Some("page.sh"));
+        assert_eq!(alerts[1].label(), "spikes");
+        assert_eq!(Alert::from(alerts[1].rule).label(), "rate > 1000000");
+
+        let config = Config::parse("[slow]\nrule = \"value > 1\"\nfor = 3\ncooldown = 60").unwrap();
+        let alerts = parse(&config).unwrap();
+        assert_eq!(alerts[0].sustain, 3);
+        assert_eq!(alerts[0].cooldown, Some(Duration::from_secs(60)));
+
+        let invalid = |text: &str| parse(&Config::parse(text).unwrap()).is_err();
+        assert!(invalid("[a]\nrule = \"rate > 1\"\nfor = 0"));
+        assert!(invalid("rule = \"rate > 1\""));
+        assert!(invalid("[a]\nseverity = \"high\""));
+        assert!(invalid("[a]\nrule = \"speed > 1\""));
+        assert!(invalid("[a]\nrule = \"rate > 1\"\ncolor = \"pink\""));
+    }
+
+    #[test]
+    fn test_from_str() {
+        let alert: Alert = "col[2].rate > 1M for 3 cooldown 5m".parse().unwrap();
+        assert_eq!(alert.rule.to_string(), "col[2].rate > 1000000");
+        assert_eq!(alert.sustain, 3);
+        assert_eq!(alert.cooldown, Some(Duration::from_secs(300)));
+
+        let alert: Alert = "delta != 0".parse().unwrap();
+        assert_eq!((alert.sustain, alert.cooldown), (1, None));
+
+        assert!("rate > 1 for".parse::<Alert>().is_err());
+        assert!("rate > 1 for 0".parse::<Alert>().is_err());
+        assert!("rate > 1 cooldown soon".parse::<Alert>().is_err());
+    }
+
+    #[test]
+    fn test_state() {
+        let alert: Alert = "value > 1 for 2 cooldown 10s".parse().unwrap();
+        let mut state = State::default();
+        let t0 = Instant::now();
+        let at = |secs| t0 + Duration::from_secs(secs);
+
+        // a single sample breaching the rule is not enough
+        assert_eq!(state.update(&alert, true, at(0)), None);
+        assert_eq!(state.update(&alert, false, at(1)), None);
+        assert_eq!(state.update(&alert, true, at(2)), None);
+        assert_eq!(state.update(&alert, true, at(3)), Some(Transition::Fired));
+        assert!(state.active);
+        assert_eq!(state.update(&alert, true, at(4)), None);
+        assert_eq!(
+            state.update(&alert, false, at(5)),
+            Some(Transition::Resolved)
+        );
+
+        // firing again within the cooldown goes unreported, resolving it too
+        state.update(&alert, true, at(6));
+        assert_eq!(state.update(&alert, true, at(7)), None);
+        assert!(state.active);
+        assert_eq!(state.update(&alert, false, at(8)), None);
+
+        state.update(&alert, true, at(20));
+        assert_eq!(state.update(&alert, true, at(21)), Some(Transition::Fired));
+    }
+}
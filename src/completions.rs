@@ -0,0 +1,436 @@
+//! Completion scripts (`dwatch completions SHELL`), generated from the definition of the command
+//! line. Styles, themes and profiles are completed by asking dwatch itself (`dwatch __complete`),
+//! so that the script keeps up with the configuration.
+
+use clap::{builder::PossibleValue, Arg, ArgAction, Command, CommandFactory, ValueHint};
+use itertools::Itertools;
+
+use crate::options::{Cli, Shell};
+
+/// What the value of an option is completed with.
+#[derive(Debug, PartialEq)]
+enum Values {
+    /// the option takes no value
+    None,
+    /// a value that cannot be completed
+    Any,
+    Files,
+    Choices(Vec<String>),
+    /// the names listed by `dwatch __complete NAMES`
+    Listed(&'static str),
+}
+
+/// An option of a command, as completed.
+struct Opt {
+    long: Option<String>,
+    short: Option<char>,
+    help: String,
+    repeated: bool,
+    value_name: String,
+    values: Values,
+}
+
+impl Opt {
+    fn new(arg: &Arg) -> Self {
+        let values = if !arg.get_action().takes_values() {
+            Values::None
+        } else if arg.get_id() == "style" {
+            Values::Listed("styles")
+        } else if arg.get_id() == "theme" {
+            Values::Listed("themes")
+        } else {
+            let choices: Vec<String> = arg
+                .get_possible_values()
+                .iter()
+                .filter(|v| !v.is_hide_set())
+                .map(PossibleValue::get_name)
+                .map(str::to_owned)
+                .collect();
+            match arg.get_value_hint() {
+                _ if !choices.is_empty() => Values::Choices(choices),
+                ValueHint::AnyPath | ValueHint::FilePath | ValueHint::DirPath => Values::Files,
+                _ => Values::Any,
+            }
+        };
+        Self {
+            long: arg.get_long().map(str::to_owned),
+            short: arg.get_short(),
+            help: summary(arg.get_help().map(ToString::to_string)),
+            repeated: matches!(arg.get_action(), ArgAction::Append | ArgAction::Count),
+            value_name: arg
+                .get_value_names()
+                .and_then(|names| names.first())
+                .map_or_else(|| arg.get_id().to_string(), ToString::to_string),
+            values,
+        }
+    }
+
+    /// The option as written on the command line, e.g. `-i` and `--interval`.
+    fn names(&self) -> Vec<String> {
+        let short = self.short.iter().map(|s| format!("-{}", s));
+        short
+            .chain(self.long.iter().map(|l| format!("--{}", l)))
+            .collect()
+    }
+}
+
+/// The first line of a help text, without the default value.
+fn summary(help: Option<String>) -> String {
+    let help = help.unwrap_or_default();
+    let line = help.lines().next().unwrap_or_default();
+    line.split(" [default")
+        .next()
+        .unwrap_or_default()
+        .to_owned()
+}
+
+/// The options of a command, with the help ones.
+fn options(cmd: &Command) -> Vec<Opt> {
+    cmd.get_arguments()
+        .filter(|arg| !arg.is_positional() && !arg.is_hide_set())
+        .map(Opt::new)
+        .collect()
+}
+
+/// The actions, but for the `help` one added by clap (`--help` is completed).
+fn subcommands(cmd: &Command) -> impl Iterator<Item = &Command> {
+    cmd.get_subcommands()
+        .filter(|sub| !sub.is_hide_set() && sub.get_name() != "help")
+}
+
+/// The actions, with their summary.
+fn actions(cmd: &Command) -> Vec<(String, String)> {
+    subcommands(cmd)
+        .map(|sub| {
+            let about = sub.get_about().map(ToString::to_string);
+            (sub.get_name().to_owned(), summary(about))
+        })
+        .collect()
+}
+
+/// The command line as completed: the options of the main command, and each action along with
+/// its options.
+fn command() -> Command {
+    let mut cmd = Cli::command().name("dwatch");
+    cmd.build();
+    cmd
+}
+
+/// The completion script for a shell.
+pub fn script(shell: Shell) -> String {
+    let cmd = command();
+    match shell {
+        Shell::Bash => bash(&cmd),
+        Shell::Zsh => zsh(&cmd),
+        Shell::Fish => fish(&cmd),
+    }
+}
+
+fn bash(cmd: &Command) -> String {
+    // the values of the options (unless completed as files, the default), then the options
+    let completion = |opts: &[Opt]| {
+        let mut out = String::from("            case \"$prev\" in\n");
+        let any: Vec<String> = opts
+            .iter()
+            .filter(|opt| opt.values == Values::Any)
+            .flat_map(Opt::names)
+            .collect();
+        if !any.is_empty() {
+            out += &format!("                {}) return ;;\n", any.join("|"));
+        }
+        for opt in opts {
+            let reply = match &opt.values {
+                Values::None | Values::Any | Values::Files => continue,
+                Values::Choices(choices) => format!(
+                    "COMPREPLY=($(compgen -W \"{}\" -- \"$cur\")); return",
+                    choices.join(" ")
+                ),
+                Values::Listed(names) => format!(
+                    "COMPREPLY=($(compgen -W \"$(dwatch __complete {} 2>/dev/null)\" -- \"$cur\")); return",
+                    names
+                ),
+            };
+            out += &format!("                {}) {} ;;\n", opt.names().join("|"), reply);
+        }
+        let names: Vec<String> = opts.iter().flat_map(Opt::names).collect();
+        out + &format!(
+            "            esac\n            opts=\"{}\"\n",
+            names.join(" ")
+        )
+    };
+
+    let actions = actions(cmd);
+    let names: Vec<&str> = actions.iter().map(|(name, _)| name.as_str()).collect();
+    let mut out = format!(
+        "# bash completion for dwatch, generated by `dwatch completions bash`
+
+_dwatch() {{
+    local cur=\"${{COMP_WORDS[COMP_CWORD]}}\" prev=\"${{COMP_WORDS[COMP_CWORD-1]}}\" action= opts
+    case \"${{COMP_WORDS[1]}}\" in
+        {})
+            [[ $COMP_CWORD -gt 1 ]] && action=\"${{COMP_WORDS[1]}}\" ;;
+    esac
+    case \"$action\" in
+",
+        names.join("|")
+    );
+    for sub in subcommands(cmd) {
+        out += &format!("        {})\n", sub.get_name());
+        out += &completion(&options(sub));
+        // the shell of `completions`
+        let first = sub.get_arguments().find(|arg| arg.is_positional());
+        if let Some(Values::Choices(choices)) = first.map(|arg| Opt::new(arg).values) {
+            out += &format!(
+                "            [[ $COMP_CWORD -eq 2 ]] && COMPREPLY=($(compgen -W \"{}\" -- \"$cur\")) && return\n",
+                choices.join(" ")
+            );
+        }
+        out += "            ;;\n";
+    }
+    out += "        *)\n";
+    out += &completion(&options(cmd));
+    out += &format!(
+        "            ;;
+    esac
+    if [[ $cur == -* ]]; then
+        COMPREPLY=($(compgen -W \"$opts\" -- \"$cur\"))
+    elif [[ -z $action && $COMP_CWORD -eq 1 && $cur == @* ]]; then
+        COMPREPLY=($(compgen -P @ -W \"$(dwatch __complete profiles 2>/dev/null)\" -- \"${{cur#@}}\"))
+    elif [[ -z $action && $COMP_CWORD -eq 1 ]]; then
+        COMPREPLY=($(compgen -W \"{}\" -- \"$cur\") $(compgen -c -- \"$cur\"))
+    fi
+}}
+
+complete -o bashdefault -o default -F _dwatch dwatch
+",
+        names.join(" ")
+    );
+    out
+}
+
+/// Quote a string for a shell, in single quotes.
+fn single_quoted(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "'\\''"))
+}
+
+fn zsh(cmd: &Command) -> String {
+    // the `_arguments` specifications of the options
+    let specs = |opts: &[Opt]| {
+        let mut out = Vec::new();
+        for opt in opts {
+            let help = opt.help.replace('[', "\\[").replace(']', "\\]");
+            let value = match &opt.values {
+                Values::None => String::new(),
+                Values::Any => format!(":{}: ", opt.value_name),
+                Values::Files => format!(":{}:_files", opt.value_name),
+                Values::Choices(choices) => format!(":{}:({})", opt.value_name, choices.join(" ")),
+                Values::Listed(names) => format!(":{}:_dwatch_{}", opt.value_name, names),
+            };
+            let with_value = |name: String| match (&opt.values, name.starts_with("--")) {
+                (Values::None, _) => name,
+                (_, true) => name + "=",
+                (_, false) => name + "+",
+            };
+            let names: Vec<String> = opt.names().into_iter().map(with_value).collect();
+            let spec = format!("[{}]{}", help, value);
+            let spec = match (opt.repeated, &names[..]) {
+                (true, [name]) => single_quoted(&format!("*{}{}", name, spec)),
+                (true, names) => format!("'*'{{{}}}{}", names.join(","), single_quoted(&spec)),
+                (false, [name]) => single_quoted(&format!("{}{}", name, spec)),
+                (false, names) => format!(
+                    "{}{{{}}}{}",
+                    single_quoted(&format!("({})", opt.names().join(" "))),
+                    names.join(","),
+                    single_quoted(&spec)
+                ),
+            };
+            out.push(spec);
+        }
+        out
+    };
+
+    let actions: Vec<String> = actions(cmd)
+        .iter()
+        .map(|(name, about)| single_quoted(&format!("{}:{}", name, about.replace(':', "\\:"))))
+        .collect();
+    let mut out = format!(
+        "#compdef dwatch
+# zsh completion for dwatch, generated by `dwatch completions zsh`
+
+_dwatch_styles() {{
+    local -a names
+    names=(${{(f)\"$(dwatch __complete styles 2>/dev/null)\"}})
+    compadd -a names
+}}
+
+_dwatch_themes() {{
+    local -a names
+    names=(${{(f)\"$(dwatch __complete themes 2>/dev/null)\"}})
+    compadd -a names
+}}
+
+_dwatch_first() {{
+    local -a actions profiles
+    actions=({})
+    profiles=(${{(f)\"$(dwatch __complete profiles 2>/dev/null)\"}})
+    _describe -t actions action actions
+    compadd -P @ -a profiles
+    _command_names -e
+}}
+
+_dwatch() {{
+    local curcontext=\"$curcontext\" state line
+    _arguments -C -s : \\
+        {} && return
+    case $state in
+        first) _dwatch_first ;;
+        rest)
+            case $line[1] in
+",
+        actions.join(" "),
+        specs(&options(cmd))
+            .into_iter()
+            .chain(["'1: :->first'".to_owned(), "'*:: :->rest'".to_owned()])
+            .join(" \\\n        ")
+    );
+    for sub in subcommands(cmd) {
+        // the file of a session, the shell of `completions`, the commands watched
+        let positionals: Vec<String> = sub
+            .get_arguments()
+            .filter(|arg| arg.is_positional())
+            .zip(1..)
+            .map(|(arg, n)| match Opt::new(arg).values {
+                _ if arg.get_id() == "commands" => "'*:: :_normal'".to_owned(),
+                Values::Choices(choices) => {
+                    format!("'{}:{}:({})'", n, arg.get_id(), choices.join(" "))
+                }
+                _ => format!("'{}:{}:_files'", n, arg.get_id()),
+            })
+            .collect();
+        out += &format!(
+            "                {})\n                    _arguments -s : \\\n                        {} ;;\n",
+            sub.get_name(),
+            specs(&options(sub))
+                .into_iter()
+                .chain(positionals)
+                .join(" \\\n                        ")
+        );
+    }
+    out += "                *) _normal ;;
+            esac
+            ;;
+    esac
+}
+
+_dwatch \"$@\"
+";
+    out
+}
+
+fn fish(cmd: &Command) -> String {
+    let actions = actions(cmd);
+    let names: Vec<&str> = actions.iter().map(|(name, _)| name.as_str()).collect();
+    let mut out = format!(
+        "# fish completion for dwatch, generated by `dwatch completions fish`
+
+function __dwatch_action --argument-names action
+    set -l words (commandline -opc)
+    if test -z \"$action\"
+        not contains -- \"$words[2]\" {}
+    else
+        test \"$words[2]\" = \"$action\"
+    end
+end
+
+complete -c dwatch -n '__fish_use_subcommand' -x -a '(__fish_complete_subcommand)'
+complete -c dwatch -n '__fish_use_subcommand' -f -a '(dwatch __complete profiles 2>/dev/null | string replace -r \"^\" @)' -d profile
+",
+        names.join(" ")
+    );
+    for (name, about) in &actions {
+        out += &format!(
+            "complete -c dwatch -n '__fish_use_subcommand' -f -a {} -d {}\n",
+            name,
+            single_quoted(about)
+        );
+    }
+
+    let completions = |condition: &str, opts: &[Opt]| {
+        let mut out = String::new();
+        for opt in opts {
+            out += &format!("complete -c dwatch -n '{}'", condition);
+            if let Some(long) = &opt.long {
+                out += &format!(" -l {}", long);
+            }
+            if let Some(short) = opt.short {
+                out += &format!(" -s {}", short);
+            }
+            match &opt.values {
+                Values::None => {}
+                Values::Any => out += " -r",
+                Values::Files => out += " -r -F",
+                Values::Choices(choices) => {
+                    out += &format!(" -x -a {}", single_quoted(&choices.join(" ")))
+                }
+                Values::Listed(names) => {
+                    out += &format!(" -x -a '(dwatch __complete {} 2>/dev/null)'", names)
+                }
+            }
+            if !opt.help.is_empty() {
+                out += &format!(" -d {}", single_quoted(&opt.help));
+            }
+            out.push('\n');
+        }
+        out
+    };
+    out += &completions("__dwatch_action", &options(cmd));
+    for sub in subcommands(cmd) {
+        let condition = format!("__dwatch_action {}", sub.get_name());
+        out += &completions(&condition, &options(sub));
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_values() {
+        let cmd = command();
+        let opt = |cmd: &Command, id: &str| {
+            Opt::new(cmd.get_arguments().find(|arg| arg.get_id() == id).unwrap())
+        };
+        assert_eq!(opt(&cmd, "style").values, Values::Listed("styles"));
+        assert_eq!(opt(&cmd, "config").values, Values::Files);
+        assert_eq!(opt(&cmd, "interval").values, Values::Any);
+        assert_eq!(opt(&cmd, "no_banner").values, Values::None);
+        assert_eq!(
+            opt(&cmd, "output").values,
+            Values::Choices(vec!["tui".into(), "ndjson".into()])
+        );
+        let export = cmd.find_subcommand("export").unwrap();
+        assert_eq!(opt(export, "output").values, Values::Files);
+        assert_eq!(opt(&cmd, "webhooks").names(), ["--webhook"]);
+    }
+
+    #[test]
+    fn test_script() {
+        let bash = script(Shell::Bash);
+        assert!(bash.contains("        --style) COMPREPLY=($(compgen -W \"$(dwatch __complete styles 2>/dev/null)\" -- \"$cur\")); return ;;\n"));
+        assert!(bash.contains("run|record|replay|styles|export|completions)"));
+        assert!(bash.ends_with("complete -o bashdefault -o default -F _dwatch dwatch\n"));
+
+        let zsh = script(Shell::Zsh);
+        assert!(
+            zsh.contains("        '(-t --no-banner)'{-t,--no-banner}'[Suppress the banner]' \\\n")
+        );
+        assert!(zsh.contains("'--style=[Style (one of: default, "));
+
+        let fish = script(Shell::Fish);
+        assert!(fish.contains(
+            "complete -c dwatch -n '__dwatch_action replay' -l speed -r -d 'Speed relative to the recorded pace, e.g. 2 or 0.5'\n"
+        ));
+        assert!(!fish.contains("__complete -d"));
+    }
+}
@@ -0,0 +1,237 @@
+//! Conditions on the values tracked by dwatch, such as `rate > 1M` or `delta == 0`.
+
+use std::{fmt, str::FromStr};
+
+/// Quantity a condition is evaluated on, for each number of the output.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Metric {
+    /// the number as output by the command
+    Value,
+    /// its change since the previous frame
+    Delta,
+    /// the change per time unit (see `--rate-unit`)
+    Rate,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Op {
+    Gt,
+    Ge,
+    Lt,
+    Le,
+    Eq,
+    Ne,
+}
+
+impl Op {
+    fn apply(self, a: f64, b: f64) -> bool {
+        match self {
+            Op::Gt => a > b,
+            Op::Ge => a >= b,
+            Op::Lt => a < b,
+            Op::Le => a <= b,
+            Op::Eq => a == b,
+            Op::Ne => a != b,
+        }
+    }
+
+    fn symbol(self) -> &'static str {
+        match self {
+            Op::Gt => ">",
+            Op::Ge => ">=",
+            Op::Lt => "<",
+            Op::Le => "<=",
+            Op::Eq => "==",
+            Op::Ne => "!=",
+        }
+    }
+}
+
+/// A comparison `<metric> <op> <threshold>`, the threshold may take a k/M/G suffix.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Condition {
+    pub metric: Metric,
+    pub op: Op,
+    pub threshold: f64,
+}
+
+impl Condition {
+    /// Whether the condition holds for a number, given its delta and rate. Deltas and rates of
+    /// numbers seen for the first time are unknown, and never satisfy a condition.
+    pub fn holds(&self, value: i64, change: Option<(i64, f64)>) -> bool {
+        let x = match (self.metric, change) {
+            (Metric::Value, _) => value as f64,
+            (Metric::Delta, Some((delta, _))) => delta as f64,
+            (Metric::Rate, Some((_, rate))) => rate,
+            (_, None) => return false,
+        };
+        self.op.apply(x, self.threshold)
+    }
+}
+
+/// An alert rule: a condition checked on every number, or only on the n-th number of each line
+/// with a `col[n].` prefix (counting from 1), e.g. `col[3].rate > 1e6`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rule {
+    pub column: Option<usize>,
+    pub condition: Condition,
+}
+
+impl Rule {
+    /// Whether the rule holds for the number at the given position (from 0) of its line.
+    pub fn holds(&self, column: usize, value: i64, change: Option<(i64, f64)>) -> bool {
+        self.column.is_none_or(|n| n == column + 1) && self.condition.holds(value, change)
+    }
+}
+
+impl FromStr for Rule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let Some(rest) = s.strip_prefix("col[") else {
+            return Ok(Rule {
+                column: None,
+                condition: s.parse()?,
+            });
+        };
+        let invalid = || format!("invalid rule '{}', expected e.g. 'col[3].rate > 1M'", s);
+        let (column, condition) = rest.split_once("].").ok_or_else(invalid)?;
+        let column = column
+            .trim()
+            .parse::<usize>()
+            .ok()
+            .filter(|&n| n > 0)
+            .ok_or_else(invalid)?;
+        Ok(Rule {
+            column: Some(column),
+            condition: condition.parse()?,
+        })
+    }
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(n) = self.column {
+            write!(f, "col[{}].", n)?;
+        }
+        write!(f, "{}", self.condition)
+    }
+}
+
+/// Parse a number with an optional SI suffix, e.g. `1.5k` or `1e6`.
+pub fn parse_quantity(s: &str) -> Option<f64> {
+    let (number, scale) = match s.char_indices().last()? {
+        (i, 'k' | 'K') => (&s[..i], 1e3),
+        (i, 'M') => (&s[..i], 1e6),
+        (i, 'G') => (&s[..i], 1e9),
+        (i, 'T') => (&s[..i], 1e12),
+        _ => (s, 1.0),
+    };
+    number.parse::<f64>().ok().map(|n| n * scale)
+}
+
+impl FromStr for Condition {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid condition '{}', expected e.g. 'rate > 1M'", s);
+
+        let s = s.trim();
+        let split = s.find(['<', '>', '=', '!']).ok_or_else(invalid)?;
+        let (metric, rest) = s.split_at(split);
+        let metric = match metric.trim() {
+            "value" => Metric::Value,
+            "delta" => Metric::Delta,
+            "rate" => Metric::Rate,
+            _ => return Err(invalid()),
+        };
+
+        let ops = [
+            (">=", Op::Ge),
+            ("<=", Op::Le),
+            ("==", Op::Eq),
+            ("!=", Op::Ne),
+            (">", Op::Gt),
+            ("<", Op::Lt),
+        ];
+        let (op, threshold) = ops
+            .iter()
+            .find_map(|(sym, op)| rest.strip_prefix(sym).map(|t| (*op, t)))
+            .ok_or_else(invalid)?;
+        let threshold = parse_quantity(threshold.trim()).ok_or_else(invalid)?;
+
+        Ok(Condition {
+            metric,
+            op,
+            threshold,
+        })
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let metric = match self.metric {
+            Metric::Value => "value",
+            Metric::Delta => "delta",
+            Metric::Rate => "rate",
+        };
+        write!(f, "{} {} {}", metric, self.op.symbol(), self.threshold)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            "rate > 1M".parse(),
+            Ok(Condition {
+                metric: Metric::Rate,
+                op: Op::Gt,
+                threshold: 1e6
+            })
+        );
+        assert_eq!(
+            "delta==0".parse::<Condition>().map(|c| c.to_string()),
+            Ok("delta == 0".to_owned())
+        );
+        assert_eq!(
+            "value <= 1.5e3".parse::<Condition>().map(|c| c.threshold),
+            Ok(1500.0)
+        );
+        assert!("speed > 1".parse::<Condition>().is_err());
+        assert!("rate >".parse::<Condition>().is_err());
+        assert!("rate => 1".parse::<Condition>().is_err());
+    }
+
+    #[test]
+    fn test_holds() {
+        let cond: Condition = "rate < 10".parse().unwrap();
+        assert!(cond.holds(100, Some((5, 5.0))));
+        assert!(!cond.holds(100, Some((50, 50.0))));
+        assert!(!cond.holds(100, None));
+
+        let cond: Condition = "value >= 100".parse().unwrap();
+        assert!(cond.holds(100, None));
+    }
+
+    #[test]
+    fn test_rule() {
+        let rule: Rule = "col[3].rate > 1e6".parse().unwrap();
+        assert_eq!(rule.column, Some(3));
+        assert_eq!(rule.to_string(), "col[3].rate > 1000000");
+        assert!(rule.holds(2, 0, Some((1, 2e6))));
+        assert!(!rule.holds(0, 0, Some((1, 2e6))));
+
+        let rule: Rule = "delta != 0".parse().unwrap();
+        assert_eq!(rule.column, None);
+        assert!(rule.holds(7, 0, Some((1, 1.0))));
+
+        assert!("col[0].rate > 1".parse::<Rule>().is_err());
+        assert!("col[x].rate > 1".parse::<Rule>().is_err());
+        assert!("col[2] rate > 1".parse::<Rule>().is_err());
+    }
+}
@@ -0,0 +1,530 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, Context, Result};
+
+use crate::verbose;
+
+/// A value of the configuration file, a small subset of TOML.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    String(String),
+    Integer(i64),
+    Float(f64),
+    Bool(bool),
+    Array(Vec<Value>),
+}
+
+impl Value {
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_integer(&self) -> Option<i64> {
+        match self {
+            Value::Integer(i) => Some(*i),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Value::Bool(b) => Some(*b),
+            _ => None,
+        }
+    }
+}
+
+pub type Table = HashMap<String, Value>;
+
+/// Parsed configuration: the top-level table is stored under the empty section name,
+/// `[a.b]` headers are stored under the dotted name `a.b`.
+#[derive(Debug, Default, Clone)]
+pub struct Config {
+    sections: HashMap<String, Table>,
+}
+
+impl Config {
+    /// Load the configuration file, honoring an explicit path first, then `$XDG_CONFIG_HOME` and
+    /// `$HOME/.config`. A missing default file yields an empty configuration, while a missing
+    /// explicit one is an error.
+    pub fn load(path: Option<&Path>) -> Result<Self> {
+        match path {
+            Some(path) => Self::from_file(path),
+            None => match get_config_path("config.toml") {
+                Some(path) if path.exists() => Self::from_file(&path),
+                _ => Ok(Self::default()),
+            },
+        }
+    }
+
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("cannot read {}", path.display()))?;
+        Self::parse(&text).with_context(|| format!("cannot parse {}", path.display()))
+    }
+
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut sections: HashMap<String, Table> = HashMap::new();
+        let mut current = String::new();
+        let mut lines = text.lines().enumerate();
+
+        sections.insert(current.clone(), Table::new());
+
+        while let Some((n, line)) = lines.next() {
+            let mut line = strip_comment(line).trim().to_owned();
+            if line.is_empty() {
+                continue;
+            }
+
+            if line.starts_with('[') {
+                let header = line
+                    .strip_prefix('[')
+                    .and_then(|l| l.strip_suffix(']'))
+                    .ok_or_else(|| anyhow!("line {}: malformed section header", n + 1))?;
+                current = split_keys(header)
+                    .map_err(|e| anyhow!("line {}: {}", n + 1, e))?
+                    .join(".");
+                sections.entry(current.clone()).or_default();
+                continue;
+            }
+
+            // arrays may span multiple lines
+            while !brackets_balanced(&line) {
+                match lines.next() {
+                    Some((_, next)) => {
+                        line.push(' ');
+                        line.push_str(strip_comment(next).trim());
+                    }
+                    None => return Err(anyhow!("line {}: unterminated array", n + 1)),
+                }
+            }
+
+            let (key, value) = split_assignment(&line)
+                .ok_or_else(|| anyhow!("line {}: expected 'key = value'", n + 1))?;
+            let key = parse_key(key).map_err(|e| anyhow!("line {}: {}", n + 1, e))?;
+            let (value, rest) = parse_value(value).map_err(|e| anyhow!("line {}: {}", n + 1, e))?;
+            if !rest.trim().is_empty() {
+                return Err(anyhow!("line {}: unexpected trailing '{}'", n + 1, rest));
+            }
+
+            sections
+                .entry(current.clone())
+                .or_default()
+                .insert(key, value);
+        }
+
+        Ok(Self { sections })
+    }
+
+    /// Look up a key of the top-level table.
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        self.section("").and_then(|t| t.get(key))
+    }
+
+    pub fn section(&self, name: &str) -> Option<&Table> {
+        self.sections.get(name)
+    }
+
+    /// The `[profiles.NAME]` section, as a configuration of its own.
+    pub fn profile(&self, name: &str) -> Option<Config> {
+        let table = self.section(&format!("profiles.{}", name))?.clone();
+        Some(Config {
+            sections: HashMap::from([(String::new(), table)]),
+        })
+    }
+
+    /// Names of the `[profiles.NAME]` sections, sorted.
+    pub fn profiles(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self
+            .sections
+            .keys()
+            .filter_map(|name| name.strip_prefix("profiles."))
+            .collect();
+        names.sort_unstable();
+        names
+    }
+
+    /// All the sections, including the top-level table (named ""), in no particular order.
+    pub fn sections(&self) -> impl Iterator<Item = (&str, &Table)> {
+        self.sections
+            .iter()
+            .map(|(name, table)| (name.as_str(), table))
+    }
+}
+
+/// Styles selected for the watched commands, kept in `styles.toml` next to the configuration.
+#[derive(Debug, Default)]
+pub struct StyleMap {
+    styles: HashMap<String, String>,
+}
+
+impl StyleMap {
+    pub fn load() -> Result<Self> {
+        let styles = match get_config_path("styles.toml") {
+            Some(path) if path.exists() => Config::from_file(&path)?
+                .section("")
+                .map(|t| {
+                    t.iter()
+                        .filter_map(|(k, v)| v.as_str().map(|s| (k.clone(), s.to_owned())))
+                        .collect()
+                })
+                .unwrap_or_default(),
+            _ => HashMap::new(),
+        };
+        verbose::event!("style", "{} commands with a style recorded", styles.len());
+        Ok(Self { styles })
+    }
+
+    /// Style of a set of commands watched together: entries are stored per command, the first
+    /// command that has one wins.
+    pub fn get(&self, commands: &[String]) -> Option<&str> {
+        commands
+            .iter()
+            .find_map(|cmd| self.styles.get(cmd))
+            .map(String::as_str)
+    }
+
+    /// Record the style used for each of `commands`, saving the map if it changed. Commands shown
+    /// with the default style are not recorded unless they already had an entry.
+    pub fn update(&mut self, commands: &[String], style: &str) -> Result<()> {
+        let mut changed = false;
+        for cmd in commands {
+            match self.styles.get(cmd) {
+                Some(s) if s == style => continue,
+                None if style == "default" => continue,
+                _ => {}
+            }
+            self.styles.insert(cmd.clone(), style.to_owned());
+            changed = true;
+        }
+        if changed {
+            verbose::event!("style", "'{}' saved for {}", style, commands.join(" | "));
+            self.save()
+        } else {
+            verbose::event!("style", "'{}' already recorded", style);
+            Ok(())
+        }
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let path = get_config_path("styles.toml")
+            .ok_or_else(|| anyhow!("cannot determine the configuration directory"))?;
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)
+                .with_context(|| format!("cannot create {}", dir.display()))?;
+        }
+
+        let mut keys: Vec<_> = self.styles.keys().collect();
+        keys.sort();
+
+        let mut text = String::new();
+        for key in keys {
+            text.push_str(&format!("{} = {}\n", quote(key), quote(&self.styles[key])));
+        }
+
+        // write to a temporary file first, so that an interrupted save never truncates the map
+        let tmp = path.with_extension("toml.tmp");
+        std::fs::write(&tmp, text).with_context(|| format!("cannot write {}", tmp.display()))?;
+        std::fs::rename(&tmp, &path).with_context(|| format!("cannot write {}", path.display()))
+    }
+}
+
+/// Resolve the path of a file in the dwatch configuration directory.
+pub fn get_config_path(file: &str) -> Option<PathBuf> {
+    config_dir().map(|dir| dir.join(file))
+}
+
+pub fn config_dir() -> Option<PathBuf> {
+    std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+        .map(|dir| dir.join("dwatch"))
+}
+
+/// Directory of the files dwatch keeps at runtime, such as the control socket.
+pub fn cache_dir() -> Option<PathBuf> {
+    std::env::var_os("XDG_CACHE_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))
+        .map(|dir| dir.join("dwatch"))
+}
+
+/// Quote a string so that it can be written back as a key or a value.
+pub fn quote(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+fn strip_comment(line: &str) -> &str {
+    let mut quote = None;
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        match (quote, c) {
+            (Some('"'), '\\') if !escaped => {
+                escaped = true;
+                continue;
+            }
+            (Some(q), c) if c == q && !escaped => quote = None,
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '#') => return &line[..i],
+            _ => {}
+        }
+        escaped = false;
+    }
+    line
+}
+
+fn brackets_balanced(line: &str) -> bool {
+    let mut depth = 0i32;
+    let mut quote = None;
+    let mut escaped = false;
+    for c in line.chars() {
+        match (quote, c) {
+            (Some('"'), '\\') if !escaped => {
+                escaped = true;
+                continue;
+            }
+            (Some(q), c) if c == q && !escaped => quote = None,
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '[') => depth += 1,
+            (None, ']') => depth -= 1,
+            _ => {}
+        }
+        escaped = false;
+    }
+    depth <= 0
+}
+
+fn split_assignment(line: &str) -> Option<(&str, &str)> {
+    let mut quote = None;
+    for (i, c) in line.char_indices() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '=') => return Some((line[..i].trim(), line[i + 1..].trim())),
+            _ => {}
+        }
+    }
+    None
+}
+
+fn split_keys(s: &str) -> Result<Vec<String>> {
+    let mut keys = Vec::new();
+    let mut rest = s.trim();
+    loop {
+        let (key, tail) = if rest.starts_with('"') || rest.starts_with('\'') {
+            match parse_value(rest)? {
+                (Value::String(k), tail) => (k, tail),
+                _ => unreachable!(),
+            }
+        } else {
+            let end = rest.find('.').unwrap_or(rest.len());
+            (rest[..end].trim().to_owned(), &rest[end..])
+        };
+        if key.is_empty() {
+            return Err(anyhow!("empty key in '{}'", s));
+        }
+        keys.push(key);
+        rest = tail.trim_start();
+        match rest.strip_prefix('.') {
+            Some(tail) => rest = tail.trim_start(),
+            None if rest.is_empty() => return Ok(keys),
+            None => return Err(anyhow!("malformed key '{}'", s)),
+        }
+    }
+}
+
+fn parse_key(s: &str) -> Result<String> {
+    Ok(split_keys(s)?.join("."))
+}
+
+fn parse_value(s: &str) -> Result<(Value, &str)> {
+    let s = s.trim_start();
+    let mut chars = s.char_indices();
+
+    match chars.next() {
+        Some((_, '"')) => {
+            let mut out = String::new();
+            let mut escaped = false;
+            for (i, c) in chars {
+                if escaped {
+                    out.push(match c {
+                        'n' => '\n',
+                        't' => '\t',
+                        'r' => '\r',
+                        c => c,
+                    });
+                    escaped = false;
+                } else if c == '\\' {
+                    escaped = true;
+                } else if c == '"' {
+                    return Ok((Value::String(out), &s[i + 1..]));
+                } else {
+                    out.push(c);
+                }
+            }
+            Err(anyhow!("unterminated string"))
+        }
+        Some((_, '\'')) => {
+            let end = s[1..]
+                .find('\'')
+                .ok_or_else(|| anyhow!("unterminated string"))?;
+            Ok((Value::String(s[1..end + 1].to_owned()), &s[end + 2..]))
+        }
+        Some((_, '[')) => {
+            let mut items = Vec::new();
+            let mut rest = s[1..].trim_start();
+            loop {
+                if let Some(tail) = rest.strip_prefix(']') {
+                    return Ok((Value::Array(items), tail));
+                }
+                let (item, tail) = parse_value(rest)?;
+                items.push(item);
+                rest = tail.trim_start();
+                if let Some(tail) = rest.strip_prefix(',') {
+                    rest = tail.trim_start();
+                } else if !rest.starts_with(']') {
+                    return Err(anyhow!("expected ',' or ']' in array"));
+                }
+            }
+        }
+        Some(_) => {
+            let end = s
+                .find(|c: char| c == ',' || c == ']' || c.is_whitespace())
+                .unwrap_or(s.len());
+            let (token, rest) = s.split_at(end);
+            let value = match token {
+                "true" => Value::Bool(true),
+                "false" => Value::Bool(false),
+                _ => {
+                    let number = token.replace('_', "");
+                    if let Ok(i) = number.parse::<i64>() {
+                        Value::Integer(i)
+                    } else if let Ok(f) = number.parse::<f64>() {
+                        Value::Float(f)
+                    } else {
+                        return Err(anyhow!("invalid value '{}'", token));
+                    }
+                }
+            };
+            Ok((value, rest))
+        }
+        None => Err(anyhow!("missing value")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_config() {
+        let cfg = Config::parse(
+            r#"
+            # general options
+            interval = 2
+            style = "fancy-net" # trailing comment
+            no-banner = true
+
+            [styles]
+            "cat /proc/net/dev" = "stats-net"
+
+            [profiles.net]
+            commands = [
+                "ip -s link",
+                'ss -s',
+            ]
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(cfg.get("interval"), Some(&Value::Integer(2)));
+        assert_eq!(cfg.get("style").and_then(Value::as_str), Some("fancy-net"));
+        assert_eq!(cfg.get("no-banner").and_then(Value::as_bool), Some(true));
+        assert_eq!(
+            cfg.section("styles")
+                .and_then(|t| t.get("cat /proc/net/dev"))
+                .and_then(Value::as_str),
+            Some("stats-net")
+        );
+
+        assert_eq!(
+            cfg.section("profiles.net").and_then(|t| t.get("commands")),
+            Some(&Value::Array(vec![
+                Value::String("ip -s link".to_owned()),
+                Value::String("ss -s".to_owned())
+            ]))
+        );
+    }
+
+    #[test]
+    fn test_profile() {
+        let cfg = Config::parse(
+            r#"
+interval = 2
+
+[profiles.net]
+commands = ["ip -s link", "ss -s"]
+interval = "500ms"
+"#,
+        )
+        .unwrap();
+        let profile = cfg.profile("net").unwrap();
+        assert_eq!(
+            profile.get("interval"),
+            Some(&Value::String("500ms".into()))
+        );
+        assert_eq!(
+            profile.get("commands"),
+            Some(&Value::Array(vec![
+                Value::String("ip -s link".into()),
+                Value::String("ss -s".into())
+            ]))
+        );
+        assert!(cfg.profile("disk").is_none());
+        assert_eq!(cfg.profiles(), ["net"]);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(Config::parse("interval").is_err());
+        assert!(Config::parse("style = \"fancy").is_err());
+        assert!(Config::parse("[styles").is_err());
+    }
+
+    #[test]
+    fn test_quote_roundtrip() {
+        let key = "awk '{print $1}' \"x\"\tdone";
+        let cfg = Config::parse(&format!("{} = 1", quote(key))).unwrap();
+        assert_eq!(cfg.get(key), Some(&Value::Integer(1)));
+    }
+
+    #[test]
+    fn test_style_map_lookup() {
+        let map = StyleMap {
+            styles: HashMap::from([("b".to_owned(), "fancy".to_owned())]),
+        };
+        let commands = |cmds: &[&str]| cmds.iter().map(|c| c.to_string()).collect::<Vec<_>>();
+        assert_eq!(map.get(&commands(&["a", "b"])), Some("fancy"));
+        assert_eq!(map.get(&commands(&["a"])), None);
+    }
+}
@@ -0,0 +1,701 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    ops::Range,
+    sync::{
+        atomic::{AtomicBool, AtomicI64, AtomicU64, AtomicUsize, Ordering},
+        Condvar, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use crate::dwatch::WriterBox;
+
+/// Step by which the interval is adjusted at runtime.
+pub const INTERVAL_STEP: Duration = Duration::from_secs(1);
+
+/// Step by which sub-second intervals are adjusted at runtime, and the lower bound of the
+/// adjustments.
+pub const FINE_INTERVAL_STEP: Duration = Duration::from_millis(100);
+
+/// Bounds of the replay speed.
+const SPEED_RANGE: (f64, f64) = (1.0 / 64.0, 64.0);
+
+/// Number of frames after which an untouched focus is dropped, by default.
+pub const FOCUS_LIFETIME_LIMIT: usize = 5;
+
+#[derive(Debug, Default, Clone)]
+pub struct Focus {
+    /// Ordinal of the value under the cursor, counting the numbers of a frame from the top.
+    pub cursor: Option<usize>,
+    /// Values selected in addition to the cursor, styled together with it.
+    pub selected: BTreeSet<usize>,
+    /// Frames rendered since the last interaction with the focus.
+    pub age: usize,
+    /// Styles assigned to individual values, by ordinal.
+    pub styles: HashMap<usize, usize>,
+    /// Focus whole lines rather than single values.
+    pub by_line: bool,
+    /// Ordinals of the values of each line (with numbers) of the last frame.
+    pub lines: Vec<Range<usize>>,
+}
+
+impl Focus {
+    /// Style index used to render the value with the given ordinal.
+    pub fn style_of(&self, ordinal: usize, global: usize) -> usize {
+        self.styles.get(&ordinal).copied().unwrap_or(global)
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.cursor.is_some() || !self.selected.is_empty()
+    }
+
+    fn line_of(&self, ordinal: usize) -> Option<usize> {
+        self.lines.iter().position(|l| l.contains(&ordinal))
+    }
+
+    /// Values highlighted and affected by style changes: the selection plus the cursor,
+    /// extended to their whole lines in line mode.
+    pub fn targets(&self) -> BTreeSet<usize> {
+        let values = self.selected.iter().copied().chain(self.cursor);
+        if self.by_line {
+            values
+                .filter_map(|idx| self.line_of(idx))
+                .flat_map(|l| self.lines[l].clone())
+                .collect()
+        } else {
+            values.collect()
+        }
+    }
+
+    fn clear(&mut self) {
+        self.cursor = None;
+        self.selected.clear();
+        self.age = 0;
+    }
+}
+
+/// Screen cells (0-based) where a value was rendered in the last frame.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Hit {
+    pub row: usize,
+    pub cols: Range<usize>,
+    pub ordinal: usize,
+}
+
+/// Text typed at the prompt, if open.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Prompt {
+    /// Name of a style to select.
+    Style(String),
+    /// New command line for the command with the given index.
+    Command(usize, String),
+}
+
+impl Prompt {
+    pub fn label(&self) -> &'static str {
+        match self {
+            Prompt::Style(_) => "style",
+            Prompt::Command(..) => "command",
+        }
+    }
+
+    pub fn text(&self) -> &str {
+        match self {
+            Prompt::Style(text) | Prompt::Command(_, text) => text,
+        }
+    }
+
+    pub fn text_mut(&mut self) -> &mut String {
+        match self {
+            Prompt::Style(text) | Prompt::Command(_, text) => text,
+        }
+    }
+}
+
+/// State shared between the rendering loop and the input handlers (signals and keyboard).
+#[derive(Debug, Default)]
+pub struct Control {
+    pub term: AtomicBool,
+    pub style: AtomicUsize,
+    pub focus: Mutex<Focus>,
+    /// Number of values rendered in the last frame.
+    pub values: AtomicUsize,
+    pub prompt: Mutex<Option<Prompt>>,
+    /// Commands being watched, which can be edited at runtime.
+    pub commands: Mutex<Vec<String>>,
+    /// Ordinals of the values output by each command in the last frame.
+    pub sources: Mutex<Vec<Range<usize>>>,
+    /// Position of the values rendered in the last frame.
+    pub hits: Mutex<Vec<Hit>>,
+    /// Commands are not run and the display is frozen while set.
+    pub paused: AtomicBool,
+    /// Frames after which an untouched focus is dropped, 0 to keep it until dismissed.
+    focus_timeout: usize,
+    /// Refresh interval, in milliseconds.
+    interval: AtomicU64,
+    /// Set to forget the previous values, so that deltas restart from the next frame.
+    pub reset: AtomicBool,
+    /// Frames to move a replay by, backward when negative, see `seek`.
+    pub seek: AtomicI64,
+    /// Replay speed factor, as the bits of a f64 (0 before it is set, for 1).
+    speed: AtomicU64,
+    /// Set to request a frame right away, see `wait`.
+    refresh: Mutex<bool>,
+    /// Set when new output is available, see `notify`.
+    updated: AtomicBool,
+    /// Set to render the last frame again, without running the commands, see `repaint`.
+    pub repaint: AtomicBool,
+    wakeup: Condvar,
+}
+
+fn wrap(idx: usize, step: isize, len: usize) -> usize {
+    (idx as isize % len as isize + step).rem_euclid(len as isize) as usize
+}
+
+impl Control {
+    pub fn new(style: usize) -> Self {
+        Self {
+            style: AtomicUsize::new(style),
+            focus_timeout: FOCUS_LIFETIME_LIMIT,
+            ..Default::default()
+        }
+    }
+
+    pub fn with_commands(self, commands: Vec<String>) -> Self {
+        *self.commands.lock().unwrap() = commands;
+        self
+    }
+
+    pub fn with_focus_timeout(mut self, frames: usize) -> Self {
+        self.focus_timeout = frames;
+        self
+    }
+
+    pub fn with_interval(self, interval: Duration) -> Self {
+        self.interval
+            .store(interval.as_millis() as u64, Ordering::Relaxed);
+        self
+    }
+
+    /// Terminate dwatch, waking up the rendering loop however far its deadline. The flag is set
+    /// under the lock `wait` checks it with, so that the wakeup cannot fall between the two.
+    pub fn quit(&self) {
+        let _guard = self.refresh.lock().unwrap();
+        self.term.store(true, Ordering::Relaxed);
+        self.wakeup.notify_all();
+    }
+
+    /// Request a new frame without waiting for the interval to elapse.
+    pub fn refresh(&self) {
+        *self.refresh.lock().unwrap() = true;
+        self.wakeup.notify_all();
+    }
+
+    /// Wake up the rendering loop for new output, which unlike a refresh on demand is not
+    /// rendered while paused.
+    pub fn notify(&self) {
+        let _guard = self.refresh.lock().unwrap();
+        self.updated.store(true, Ordering::Relaxed);
+        self.wakeup.notify_all();
+    }
+
+    /// Wake up the rendering loop to render the last frame again, e.g. for what is typed at the
+    /// prompt to show, without running the commands.
+    pub fn repaint(&self) {
+        let _guard = self.refresh.lock().unwrap();
+        self.repaint.store(true, Ordering::Relaxed);
+        self.wakeup.notify_all();
+    }
+
+    /// Sleep until the deadline, returning early (with true) if a refresh is requested, or
+    /// (with false) if new output is available, a repaint is requested or dwatch is terminating.
+    /// The `repaint` flag is left set only when returning for a repaint alone, as a new frame is
+    /// painted anyway otherwise.
+    pub fn wait(&self, deadline: Instant) -> bool {
+        let mut refresh = self.refresh.lock().unwrap();
+        loop {
+            if std::mem::take(&mut *refresh) {
+                self.repaint.store(false, Ordering::Relaxed);
+                return true;
+            }
+            let now = Instant::now();
+            if now >= deadline
+                || self.updated.swap(false, Ordering::Relaxed)
+                || self.term.load(Ordering::Relaxed)
+            {
+                self.repaint.store(false, Ordering::Relaxed);
+                return false;
+            }
+            if self.repaint.load(Ordering::Relaxed) {
+                return false;
+            }
+            refresh = self.wakeup.wait_timeout(refresh, deadline - now).unwrap().0;
+        }
+    }
+
+    /// Open the prompt to edit the command the focused value comes from (the first command when
+    /// nothing is focused).
+    pub fn edit_command(&self) {
+        let cursor = self.focus.lock().unwrap().cursor;
+        let idx = cursor
+            .and_then(|c| {
+                self.sources
+                    .lock()
+                    .unwrap()
+                    .iter()
+                    .position(|s| s.contains(&c))
+            })
+            .unwrap_or(0);
+        let text = self.commands.lock().unwrap().get(idx).cloned();
+        if let Some(text) = text {
+            *self.prompt.lock().unwrap() = Some(Prompt::Command(idx, text));
+        }
+    }
+
+    /// Replace the command with the given index, and show its output right away.
+    pub fn set_command(&self, idx: usize, command: &str) -> bool {
+        let command = command.trim();
+        let mut commands = self.commands.lock().unwrap();
+        match commands.get_mut(idx) {
+            Some(cmd) if !command.is_empty() => *cmd = command.to_owned(),
+            _ => return false,
+        }
+        drop(commands);
+        self.refresh();
+        true
+    }
+
+    /// Restart all deltas from a fresh frame, e.g. after the watched counters were reset.
+    pub fn reset_deltas(&self) {
+        self.reset.store(true, Ordering::Relaxed);
+        self.refresh();
+    }
+
+    pub fn interval(&self) -> Duration {
+        Duration::from_millis(self.interval.load(Ordering::Relaxed))
+    }
+
+    /// Replace the interval, and render the next frame right away.
+    pub fn set_interval(&self, interval: Duration) {
+        self.interval
+            .store(interval.as_millis() as u64, Ordering::Relaxed);
+        self.refresh();
+    }
+
+    /// Lengthen (or shorten, with a negative step) the interval by `INTERVAL_STEP`, or by
+    /// `FINE_INTERVAL_STEP` below one second. The next frame is rendered right away, and the
+    /// following ones are scheduled with the new interval.
+    pub fn adjust_interval(&self, step: i64) {
+        let coarse = INTERVAL_STEP.as_millis() as i64;
+        let fine = FINE_INTERVAL_STEP.as_millis() as i64;
+        let _ = self
+            .interval
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |ms| {
+                let ms = ms as i64;
+                let unit = if ms > coarse || (ms == coarse && step > 0) {
+                    coarse
+                } else {
+                    fine
+                };
+                // a shorter interval given on the command line is kept as the lower bound
+                Some((ms + step * unit).max(fine.min(ms)) as u64)
+            });
+        self.refresh();
+    }
+
+    /// Lengthen the interval to at least the given duration, rounded up to a multiple of
+    /// `INTERVAL_STEP`. Returns whether it changed.
+    pub fn extend_interval(&self, min: Duration) -> bool {
+        let step = INTERVAL_STEP.as_millis() as u64;
+        let ms = (min.as_millis() as u64).div_ceil(step) * step;
+        self.interval.fetch_max(ms, Ordering::Relaxed) < ms
+    }
+
+    /// Speed of a replay, relative to the recorded pace.
+    pub fn speed(&self) -> f64 {
+        match self.speed.load(Ordering::Relaxed) {
+            0 => 1.0,
+            bits => f64::from_bits(bits),
+        }
+    }
+
+    pub fn with_speed(self, speed: f64) -> Self {
+        let speed = speed.clamp(SPEED_RANGE.0, SPEED_RANGE.1);
+        self.speed.store(speed.to_bits(), Ordering::Relaxed);
+        self
+    }
+
+    /// Multiply the speed of a replay by a factor, from the next frame.
+    pub fn adjust_speed(&self, factor: f64) {
+        let speed = (self.speed() * factor).clamp(SPEED_RANGE.0, SPEED_RANGE.1);
+        self.speed.store(speed.to_bits(), Ordering::Relaxed);
+    }
+
+    /// Move a replay by a number of frames, and show the frame reached right away (even while
+    /// paused).
+    pub fn seek(&self, frames: i64) {
+        self.seek.fetch_add(frames, Ordering::Relaxed);
+        self.refresh();
+    }
+
+    /// Pause or resume, taking effect right away: the banner shows the pause (or the next frame
+    /// is rendered) without waiting for the interval to elapse.
+    pub fn toggle_pause(&self) {
+        self.paused.fetch_xor(true, Ordering::Relaxed);
+        self.notify();
+    }
+
+    pub fn style_name(&self) -> &'static str {
+        WriterBox::name(self.style.load(Ordering::Relaxed))
+    }
+
+    /// Move the focus to the next value or line (or to the previous one, with a negative step).
+    pub fn focus_step(&self, step: isize) {
+        let values = self.values.load(Ordering::Relaxed);
+        let mut focus = self.focus.lock().unwrap();
+        focus.age = 0;
+
+        if focus.by_line {
+            let lines = focus.lines.len();
+            let line = match (focus.cursor.and_then(|idx| focus.line_of(idx)), lines) {
+                (_, 0) => None,
+                (None, _) if step < 0 => Some(lines - 1),
+                (None, _) => Some(0),
+                (Some(line), _) => Some(wrap(line, step, lines)),
+            };
+            focus.cursor = line.map(|l| focus.lines[l].start);
+            return;
+        }
+
+        focus.cursor = match (focus.cursor, values) {
+            (_, 0) => None,
+            (None, _) if step < 0 => Some(values - 1),
+            (None, _) => Some(0),
+            (Some(idx), _) => Some(wrap(idx, step, values)),
+        };
+    }
+
+    /// Focus the value rendered at the given screen cell, if any.
+    pub fn focus_at(&self, row: usize, col: usize) -> bool {
+        let ordinal = self
+            .hits
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|h| h.row == row && h.cols.contains(&col))
+            .map(|h| h.ordinal);
+
+        if ordinal.is_some() {
+            let mut focus = self.focus.lock().unwrap();
+            focus.cursor = ordinal;
+            focus.age = 0;
+        }
+        ordinal.is_some()
+    }
+
+    /// Release the focused and selected values (their styles are kept).
+    pub fn drop_focus(&self) {
+        self.focus.lock().unwrap().clear();
+    }
+
+    /// Switch the focus between single values and whole lines.
+    pub fn toggle_line_mode(&self) {
+        let mut focus = self.focus.lock().unwrap();
+        focus.by_line = !focus.by_line;
+        focus.age = 0;
+    }
+
+    /// Add the value under the cursor to the selection, or remove it if already selected.
+    pub fn toggle_selection(&self) {
+        let mut focus = self.focus.lock().unwrap();
+        if let Some(idx) = focus.cursor {
+            if !focus.selected.remove(&idx) {
+                focus.selected.insert(idx);
+            }
+            focus.age = 0;
+        }
+    }
+
+    /// Cycle the style of the focused values, or the global style when nothing is focused. The
+    /// values of a selection move together, starting from the style of the first one.
+    pub fn cycle_style(&self, step: isize) {
+        let mut focus = self.focus.lock().unwrap();
+        let targets = focus.targets();
+        match targets.first() {
+            Some(&first) => {
+                let style = focus.style_of(first, self.style.load(Ordering::Relaxed));
+                let style = wrap(style, step, WriterBox::count());
+                for idx in targets {
+                    focus.styles.insert(idx, style);
+                }
+                focus.age = 0;
+            }
+            None => {
+                let _ = self
+                    .style
+                    .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |i| {
+                        Some(wrap(i, step, WriterBox::count()))
+                    });
+            }
+        }
+    }
+
+    /// Jump directly to the named style, for the focused values or globally.
+    pub fn select_style(&self, name: &str) -> bool {
+        let Some(style) = WriterBox::index(name) else {
+            return false;
+        };
+        let mut focus = self.focus.lock().unwrap();
+        if focus.is_active() {
+            for idx in focus.targets() {
+                focus.styles.insert(idx, style);
+            }
+            focus.age = 0;
+        } else {
+            self.style.store(style, Ordering::Relaxed);
+        }
+        true
+    }
+
+    /// Account for a rendered frame, dropping the focus once it has aged out.
+    pub fn tick(&self, values: usize, lines: Vec<Range<usize>>) {
+        self.values.store(values, Ordering::Relaxed);
+        let mut focus = self.focus.lock().unwrap();
+        focus.lines = lines;
+        if focus.is_active() {
+            focus.age += 1;
+            if self.focus_timeout > 0 && focus.age >= self.focus_timeout {
+                focus.clear();
+            } else {
+                // values may disappear from one frame to the next
+                focus.selected.retain(|&idx| idx < values);
+                if focus.cursor >= Some(values) {
+                    focus.cursor = None;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cycle_global_style() {
+        let control = Control::new(0);
+        control.cycle_style(-1);
+        assert_eq!(
+            control.style.load(Ordering::Relaxed),
+            WriterBox::count() - 1
+        );
+        control.cycle_style(1);
+        assert_eq!(control.style.load(Ordering::Relaxed), 0);
+        assert!(control.select_style("delta"));
+        assert_eq!(control.style_name(), "delta");
+        assert!(!control.select_style("unknown"));
+        assert_eq!(control.style_name(), "delta");
+    }
+
+    #[test]
+    fn test_focus() {
+        let control = Control::new(0);
+        control.tick(3, vec![0..1, 1..3]);
+        control.focus_step(-1);
+        assert_eq!(control.focus.lock().unwrap().cursor, Some(2));
+        control.focus_step(1);
+        assert_eq!(control.focus.lock().unwrap().cursor, Some(0));
+
+        // styling the focused value leaves the global style untouched
+        control.cycle_style(1);
+        assert_eq!(control.style.load(Ordering::Relaxed), 0);
+        assert_eq!(control.focus.lock().unwrap().style_of(0, 0), 1);
+
+        for _ in 0..FOCUS_LIFETIME_LIMIT {
+            control.tick(3, vec![0..1, 1..3]);
+        }
+        assert_eq!(control.focus.lock().unwrap().cursor, None);
+        assert_eq!(control.focus.lock().unwrap().style_of(0, 0), 1);
+    }
+
+    #[test]
+    fn test_sticky_focus() {
+        let control = Control::new(0).with_focus_timeout(0);
+        control.tick(3, vec![0..1, 1..3]);
+        control.focus_step(1);
+        for _ in 0..2 * FOCUS_LIFETIME_LIMIT {
+            control.tick(3, vec![0..1, 1..3]);
+        }
+        assert_eq!(control.focus.lock().unwrap().cursor, Some(0));
+        control.drop_focus();
+        assert!(!control.focus.lock().unwrap().is_active());
+    }
+
+    #[test]
+    fn test_focus_at() {
+        let control = Control::new(0);
+        *control.hits.lock().unwrap() = vec![
+            Hit {
+                row: 2,
+                cols: 0..4,
+                ordinal: 0,
+            },
+            Hit {
+                row: 2,
+                cols: 10..12,
+                ordinal: 1,
+            },
+        ];
+        assert!(control.focus_at(2, 11));
+        assert_eq!(control.focus.lock().unwrap().cursor, Some(1));
+        assert!(!control.focus_at(2, 6));
+        assert_eq!(control.focus.lock().unwrap().cursor, Some(1));
+    }
+
+    #[test]
+    fn test_selection() {
+        let control = Control::new(0);
+        control.tick(4, vec![0..2, 2..4]);
+        control.focus_step(1);
+        control.toggle_selection();
+        control.focus_step(1);
+        control.focus_step(1);
+        control.cycle_style(1);
+
+        let focus = control.focus.lock().unwrap().clone();
+        assert_eq!(focus.targets(), BTreeSet::from([0, 2]));
+        assert_eq!(focus.style_of(0, 0), 1);
+        assert_eq!(focus.style_of(1, 0), 0);
+        assert_eq!(focus.style_of(2, 0), 1);
+        drop(focus);
+
+        for _ in 0..FOCUS_LIFETIME_LIMIT {
+            control.tick(4, vec![0..2, 2..4]);
+        }
+        assert!(!control.focus.lock().unwrap().is_active());
+    }
+
+    #[test]
+    fn test_line_mode() {
+        let control = Control::new(0);
+        control.tick(5, vec![0..2, 2..5]);
+        control.toggle_line_mode();
+        control.focus_step(-1);
+        assert_eq!(
+            control.focus.lock().unwrap().targets(),
+            BTreeSet::from([2, 3, 4])
+        );
+        control.focus_step(1);
+        assert_eq!(
+            control.focus.lock().unwrap().targets(),
+            BTreeSet::from([0, 1])
+        );
+
+        control.cycle_style(1);
+        let focus = control.focus.lock().unwrap();
+        assert_eq!((focus.style_of(0, 0), focus.style_of(1, 0)), (1, 1));
+        assert_eq!(focus.style_of(2, 0), 0);
+    }
+
+    #[test]
+    fn test_edit_command() {
+        let control = Control::new(0).with_commands(vec!["date".into(), "uptime".into()]);
+        *control.sources.lock().unwrap() = vec![0..2, 2..5];
+        control.tick(5, vec![0..2, 2..5]);
+        control.focus_step(-1);
+        control.edit_command();
+        assert_eq!(
+            *control.prompt.lock().unwrap(),
+            Some(Prompt::Command(1, "uptime".into()))
+        );
+
+        assert!(!control.set_command(1, " "));
+        assert!(control.set_command(1, "uptime -p "));
+        assert!(!control.set_command(2, "ls"));
+        assert_eq!(*control.commands.lock().unwrap(), vec!["date", "uptime -p"]);
+    }
+
+    #[test]
+    fn test_adjust_interval() {
+        let control = Control::new(0).with_interval(Duration::from_secs(2));
+        control.adjust_interval(1);
+        assert_eq!(control.interval(), Duration::from_secs(3));
+        for _ in 0..2 {
+            control.adjust_interval(-1);
+        }
+        assert_eq!(control.interval(), INTERVAL_STEP);
+        control.adjust_interval(-1);
+        assert_eq!(control.interval(), INTERVAL_STEP - FINE_INTERVAL_STEP);
+        for _ in 0..20 {
+            control.adjust_interval(-1);
+        }
+        assert_eq!(control.interval(), FINE_INTERVAL_STEP);
+        assert!(control.wait(Instant::now()));
+    }
+
+    #[test]
+    fn test_replay() {
+        let control = Control::new(0).with_speed(0.5);
+        control.adjust_speed(8.0);
+        assert_eq!(control.speed(), 4.0);
+        control.adjust_speed(1000.0);
+        assert_eq!(control.speed(), SPEED_RANGE.1);
+
+        control.seek(-10);
+        control.seek(1);
+        assert_eq!(control.seek.load(Ordering::Relaxed), -9);
+        assert!(control.wait(Instant::now()));
+    }
+
+    #[test]
+    fn test_extend_interval() {
+        let control = Control::new(0).with_interval(Duration::from_secs(2));
+        assert!(!control.extend_interval(Duration::from_millis(1500)));
+        assert_eq!(control.interval(), Duration::from_secs(2));
+        assert!(control.extend_interval(Duration::from_millis(2100)));
+        assert_eq!(control.interval(), Duration::from_secs(3));
+    }
+
+    #[test]
+    fn test_wait() {
+        let control = Control::new(0);
+        let deadline = Instant::now() + std::time::Duration::from_millis(10);
+        assert!(!control.wait(deadline));
+        assert!(Instant::now() >= deadline);
+
+        control.refresh();
+        assert!(control.wait(Instant::now() + std::time::Duration::from_secs(60)));
+        assert!(!control.wait(Instant::now()));
+
+        let start = Instant::now();
+        control.notify();
+        assert!(!control.wait(start + std::time::Duration::from_secs(60)));
+        assert!(start.elapsed() < std::time::Duration::from_secs(60));
+
+        control.repaint();
+        assert!(!control.wait(start + std::time::Duration::from_secs(60)));
+        assert!(control.repaint.swap(false, Ordering::Relaxed));
+        // a repaint requested along with new output is painted with it
+        control.repaint();
+        control.notify();
+        assert!(!control.wait(start + std::time::Duration::from_secs(60)));
+        assert!(!control.repaint.load(Ordering::Relaxed));
+
+        // resuming does not wait for the interval to elapse
+        control.toggle_pause();
+        control.toggle_pause();
+        assert!(!control.paused.load(Ordering::Relaxed));
+        assert!(!control.wait(start + std::time::Duration::from_secs(60)));
+        assert!(start.elapsed() < std::time::Duration::from_secs(60));
+
+        // quitting from another thread ends a wait with a far-off deadline
+        std::thread::scope(|scope| {
+            scope.spawn(|| {
+                std::thread::sleep(std::time::Duration::from_millis(20));
+                control.quit();
+            });
+            assert!(!control.wait(start + std::time::Duration::from_secs(60)));
+        });
+        assert!(control.term.load(Ordering::Relaxed));
+    }
+}
@@ -0,0 +1,175 @@
+//! Control socket (`--control-socket`), through which scripts drive a running dwatch: each line
+//! received is a command, answered with a line (`ok`, `error: ...` or the requested JSON).
+//!
+//! ```text
+//! $ echo set-interval 5 | nc -U ~/.cache/dwatch/ctl.sock
+//! ok
+//! ```
+
+use std::{
+    io::{BufRead, BufReader, Write},
+    os::unix::net::{UnixListener, UnixStream},
+    path::{Path, PathBuf},
+    sync::{atomic::Ordering, Arc},
+};
+
+use anyhow::{anyhow, Context as _, Result};
+
+use crate::{control::Control, dwatch::WriterBox, json, options::parse_duration};
+
+const USAGE: &str = "commands: pause, resume, toggle-pause, refresh-now, reset-deltas, \
+                     set-interval DURATION, set-style NAME, dump-state, quit";
+
+/// The listening socket, removed when dropped.
+pub struct Server {
+    path: PathBuf,
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// Default location of the socket, in the cache directory.
+pub fn default_path() -> Option<PathBuf> {
+    crate::config::cache_dir().map(|dir| dir.join("ctl.sock"))
+}
+
+/// Listen on the socket at the given path, handing the commands received to the control. A
+/// socket left behind by a dwatch that is gone is replaced, one still answering is not.
+pub fn serve(path: &Path, control: Arc<Control>) -> Result<Server> {
+    if UnixStream::connect(path).is_ok() {
+        return Err(anyhow!(
+            "{} is in use by another dwatch, pass another path to --control-socket",
+            path.display()
+        ));
+    }
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).with_context(|| format!("cannot create {}", dir.display()))?;
+    }
+    let _ = std::fs::remove_file(path);
+    let listener =
+        UnixListener::bind(path).with_context(|| format!("cannot listen on {}", path.display()))?;
+
+    std::thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let control = Arc::clone(&control);
+            // a client going away is no concern of dwatch
+            std::thread::spawn(move || handle(stream, &control));
+        }
+    });
+    Ok(Server {
+        path: path.to_owned(),
+    })
+}
+
+fn handle(stream: UnixStream, control: &Control) -> std::io::Result<()> {
+    let mut out = stream.try_clone()?;
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let reply = match execute(control, &line) {
+            Ok(reply) => reply,
+            Err(e) => format!("error: {}", e),
+        };
+        writeln!(out, "{}", reply)?;
+    }
+    Ok(())
+}
+
+/// Carry out a command line, returning the reply.
+fn execute(control: &Control, line: &str) -> Result<String> {
+    let (command, arg) = match line.trim().split_once(char::is_whitespace) {
+        Some((command, arg)) => (command, Some(arg.trim())),
+        None => (line.trim(), None),
+    };
+    match (command, arg) {
+        ("pause", None) => control.paused.store(true, Ordering::Relaxed),
+        ("resume", None) => {
+            control.paused.store(false, Ordering::Relaxed);
+            control.refresh();
+        }
+        ("toggle-pause", None) => control.toggle_pause(),
+        ("refresh-now", None) => control.refresh(),
+        ("reset-deltas", None) => control.reset_deltas(),
+        ("set-interval", Some(arg)) => {
+            control.set_interval(parse_duration(arg).map_err(|e| anyhow!(e))?);
+        }
+        ("set-style", Some(name)) => {
+            let style =
+                WriterBox::index(name).ok_or_else(|| anyhow!("unknown style '{}'", name))?;
+            control.style.store(style, Ordering::Relaxed);
+            control.refresh();
+        }
+        ("dump-state", None) => return Ok(state(control)),
+        ("quit", None) => control.quit(),
+        ("help", None) => return Ok(USAGE.to_owned()),
+        _ => return Err(anyhow!("invalid command '{}', {}", line.trim(), USAGE)),
+    }
+    Ok("ok".to_owned())
+}
+
+/// The state of the instance, as a JSON object.
+fn state(control: &Control) -> String {
+    let commands = control.commands.lock().unwrap();
+    let cursor = control.focus.lock().unwrap().cursor;
+    json::object([
+        ("paused", control.paused.load(Ordering::Relaxed).to_string()),
+        ("interval", json::number(control.interval().as_secs_f64())),
+        ("style", json::string(control.style_name())),
+        (
+            "commands",
+            json::array(commands.iter().map(|c| json::string(c))),
+        ),
+        ("values", control.values.load(Ordering::Relaxed).to_string()),
+        ("focus", cursor.map_or("null".to_owned(), |c| c.to_string())),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_execute() {
+        let control = Control::new(0)
+            .with_commands(vec!["uptime".into()])
+            .with_interval(Duration::from_secs(1));
+        assert_eq!(execute(&control, "pause").unwrap(), "ok");
+        assert!(control.paused.load(Ordering::Relaxed));
+        execute(&control, " set-interval 250ms ").unwrap();
+        assert_eq!(control.interval(), Duration::from_millis(250));
+        execute(&control, "set-style delta").unwrap();
+        assert_eq!(control.style_name(), "delta");
+
+        assert!(execute(&control, "set-style unknown").is_err());
+        assert!(execute(&control, "set-interval").is_err());
+        assert!(execute(&control, "jump").is_err());
+        assert_eq!(
+            execute(&control, "dump-state").unwrap(),
+            r#"{"paused":true,"interval":0.25,"style":"delta","commands":["uptime"],"values":0,"focus":null}"#
+        );
+    }
+
+    #[test]
+    fn test_serve() {
+        let path = std::env::temp_dir().join(format!("dwatch-test-{}.sock", std::process::id()));
+        let control = Arc::new(Control::new(0));
+        let server = serve(&path, Arc::clone(&control)).unwrap();
+        assert!(serve(&path, Arc::clone(&control)).is_err());
+
+        let mut stream = UnixStream::connect(&path).unwrap();
+        stream.write_all(b"pause\nbogus\n").unwrap();
+        let mut replies = BufReader::new(stream).lines();
+        assert_eq!(replies.next().unwrap().unwrap(), "ok");
+        assert!(replies.next().unwrap().unwrap().starts_with("error: "));
+        assert!(control.paused.load(Ordering::Relaxed));
+
+        drop(server);
+        assert!(!path.exists());
+    }
+}
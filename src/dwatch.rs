@@ -1,27 +1,46 @@
 use std::{
-    collections::hash_map::DefaultHasher,
+    collections::{hash_map::DefaultHasher, BTreeMap, BTreeSet, HashMap, VecDeque},
     hash::Hasher,
     io::Write,
     ops::Range,
-    sync::{
-        atomic::{AtomicBool, AtomicUsize, Ordering},
-        Arc,
-    },
-    thread::{sleep, JoinHandle},
+    os::unix::process::ExitStatusExt,
+    process::{ExitStatus, Output},
+    sync::{atomic::Ordering, Arc, Mutex},
     time::{Duration, Instant},
 };
 
-use ansi_term::Colour;
-
-use anyhow::{anyhow, Result};
+use ansi_term::Style;
+use anyhow::{anyhow, Context as _, Result};
 use itertools::Itertools;
 use itertools::{
     izip, multizip,
     EitherOrBoth::{Both, Left, Right},
 };
 
-use crate::options::Options;
+use crate::alert::{self, Alert, Transition};
+use crate::condition::Condition;
+use crate::config::StyleMap;
+use crate::control::{Control, Focus, Hit};
+use crate::graphite;
+use crate::grpc;
+use crate::http;
+use crate::json;
+use crate::mqtt;
+use crate::notify;
+use crate::options::{ColorMode, Options, OutputFormat, Overlap, RateUnit};
+use crate::pattern::Regex;
+use crate::process::{self, Feed, Job, POLL_INTERVAL};
+use crate::prometheus;
+use crate::protobuf::Message;
+use crate::pty;
 use crate::ranges::RangeParser;
+use crate::remote;
+use crate::session::{Player, Recorder};
+use crate::shell;
+use crate::statsd;
+use crate::theme::Theme;
+use crate::verbose;
+use crate::web;
 
 #[derive(Debug, Clone)]
 struct LineNumbers {
@@ -45,31 +64,258 @@ impl LineNumbers {
 
 type LineMap = std::collections::HashMap<(u64, u64), LineNumbers>;
 
-fn format_number<T: Into<f64>>(v: T, bit: bool) -> String {
+/// Ranges of the numbers of a line.
+fn numeric_ranges(line: &str) -> Vec<Range<usize>> {
+    RangeParser::new(|c| c.is_ascii_whitespace() || ".,:;()[]{}<>'`\"|".contains(c))
+        .get_numeric_ranges(line)
+}
+
+/// Numbers of an output, by line, as if it had been rendered, to compute deltas against it.
+fn line_map(output: &str) -> LineMap {
+    output
+        .lines()
+        .enumerate()
+        .map(|(lineno, line)| {
+            let ranges = numeric_ranges(line);
+            let key = (
+                lineno as u64,
+                chunks_fingerprint(&parse_strings(line, &ranges)),
+            );
+            (key, LineNumbers::new(parse_numbers(line, &ranges)))
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Unit {
+    None,
+    Bits,
+    Bytes,
+}
+
+fn format_number<T: Into<f64>>(v: T, unit: Unit, precision: usize, per: RateUnit) -> String {
     let value = v.into();
 
-    if bit {
-        if value > 1_000_000_000.0 {
-            format!("{:.2}_Gbps", value / 1_000_000_000.0)
-        } else if value > 1_000_000.0 {
-            format!("{:.2}_Mbps", value / 1_000_000.0)
-        } else if value > 1_000.0 {
-            format!("{:.2}_Kbps", value / 1_000.0)
-        } else {
-            format!("{:.2}_bps", value)
-        }
-    } else if value > 1_000_000_000.0 {
-        format!("{:.2}G", value / 1_000_000_000.0)
+    let (value, prefix) = if value > 1_000_000_000.0 {
+        (value / 1_000_000_000.0, "G")
     } else if value > 1_000_000.0 {
-        format!("{:.2}M", value / 1_000_000.0)
+        (value / 1_000_000.0, "M")
     } else if value > 1_000.0 {
-        format!("{:.2}K", value / 1_000.0)
+        (value / 1_000.0, "K")
+    } else {
+        (value, "")
+    };
+
+    match (unit, per) {
+        (Unit::None, RateUnit::S) => format!("{:.*}{}", precision, value, prefix),
+        (Unit::None, _) => format!("{:.*}{}/{}", precision, value, prefix, per.suffix()),
+        (Unit::Bits, RateUnit::S) => format!("{:.*}_{}bps", precision, value, prefix),
+        (Unit::Bits, _) => format!("{:.*}_{}b/{}", precision, value, prefix, per.suffix()),
+        (Unit::Bytes, _) => format!("{:.*}_{}B/{}", precision, value, prefix, per.suffix()),
+    }
+}
+
+/// Render a duration expressed in seconds (or milliseconds) in a compact human form, e.g. `1h02m`.
+fn format_duration(value: i64, millis: bool) -> String {
+    let sign = if value < 0 { "-" } else { "" };
+    let value = value.unsigned_abs();
+
+    if millis && value < 1000 {
+        return format!("{}{}ms", sign, value);
+    }
+
+    let secs = if millis { value / 1000 } else { value };
+    let (d, h, m, s) = (secs / 86400, secs / 3600 % 24, secs / 60 % 60, secs % 60);
+
+    if d > 0 {
+        format!("{}{}d{:02}h", sign, d, h)
+    } else if h > 0 {
+        format!("{}{}h{:02}m", sign, h, m)
+    } else if m > 0 {
+        format!("{}{}m{:02}s", sign, m, s)
+    } else if millis {
+        format!("{}{}.{:03}s", sign, s, value % 1000)
     } else {
-        format!("{:.2}", value)
+        format!("{}{}s", sign, s)
+    }
+}
+
+/// The digits of a number grouped by thousands, e.g. `-12,345,678`.
+fn group_digits(value: i64, sep: char) -> String {
+    let digits = value.unsigned_abs().to_string();
+    let mut out = String::with_capacity(digits.len() * 4 / 3 + 1);
+    if value < 0 {
+        out.push('-');
+    }
+    for (idx, digit) in digits.chars().enumerate() {
+        if idx > 0 && (digits.len() - idx).is_multiple_of(3) {
+            out.push(sep);
+        }
+        out.push(digit);
+    }
+    out
+}
+
+/// Thousands separator of the locale (`LC_ALL`, `LC_NUMERIC` or `LANG`), `,` if it has none (as
+/// the C locale). It is told from the name of the locale rather than through `setlocale`, which
+/// would not be safe with the threads already running.
+fn locale_separator() -> char {
+    let locale = ["LC_ALL", "LC_NUMERIC", "LANG"]
+        .iter()
+        .filter_map(|var| std::env::var(var).ok())
+        .find(|locale| !locale.is_empty())
+        .unwrap_or_default();
+    separator_of(&locale)
+}
+
+/// Thousands separator of a locale, e.g. `de_DE.UTF-8`, as glibc has it for the common ones.
+fn separator_of(locale: &str) -> char {
+    let name = locale.split(['.', '@']).next().unwrap_or_default();
+    let (language, territory) = name.split_once('_').unwrap_or((name, ""));
+    match (language, territory) {
+        ("de" | "it" | "fr" | "rm", "CH" | "LI") => '\'',
+        ("fr", _) => '\u{202f}',
+        (
+            "de" | "it" | "es" | "nl" | "pt" | "da" | "id" | "tr" | "el" | "ro" | "sl" | "hr"
+            | "sr" | "vi",
+            _,
+        ) => '.',
+        (
+            "ru" | "uk" | "be" | "pl" | "cs" | "sk" | "fi" | "sv" | "nb" | "nn" | "no" | "hu"
+            | "bg" | "lt" | "lv" | "et",
+            _,
+        ) => '\u{a0}',
+        _ => ',',
+    }
+}
+
+/// Share of the interval from which a run is reported as slow.
+const SLOW_RUN: f64 = 0.8;
+
+/// Pause in the output of a followed command that ends a block (a frame).
+const FOLLOW_GAP: Duration = Duration::from_millis(50);
+
+/// Rendering parameters shared by all the writers of a frame.
+#[derive(Clone)]
+pub struct Context {
+    pub interval: Duration,
+    pub precision: usize,
+    pub rate_unit: RateUnit,
+    pub theme: Theme,
+    pub symbols: bool,
+    /// Separator of the thousands of the values, if grouped.
+    pub grouping: Option<char>,
+}
+
+impl Context {
+    /// The context of the options, with the theme given, plain if colors are disabled.
+    fn new(opt: &Options, interval: Duration) -> Result<Context> {
+        let no_color = opt.no_color || opt.color == Some(ColorMode::Never);
+        Ok(Context {
+            interval,
+            precision: opt.precision.unwrap_or(2),
+            rate_unit: opt.rate_unit.unwrap_or_default(),
+            theme: match opt.theme {
+                _ if no_color => Theme::by_name("none").unwrap_or_default(),
+                Some(ref name) => Theme::by_name(name).ok_or_else(|| {
+                    anyhow!(
+                        "unknown theme '{}' (available: {})",
+                        name,
+                        Theme::names().join(", ")
+                    )
+                })?,
+                None if opt.color != Some(ColorMode::Always)
+                    && std::env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty()) =>
+                {
+                    Theme::by_name("none").unwrap_or_default()
+                }
+                None => Theme::default(),
+            },
+            symbols: no_color,
+            grouping: opt.grouping.map(|sep| sep.unwrap_or_else(locale_separator)),
+        })
+    }
+
+    /// Convert an amount accumulated over one interval into a rate.
+    fn rate(&self, amount: f64) -> f64 {
+        amount * self.rate_unit.as_secs_f64() / self.interval.as_secs_f64()
+    }
+
+    /// Variant used for the focused value: reverse video, unless colors are disabled.
+    fn focused(&self) -> Context {
+        let mut ctx = self.clone();
+        if !self.symbols {
+            ctx.theme = self.theme.reversed();
+        }
+        ctx
+    }
+
+    /// Variant used for the values breaching an alert, styled as a whole once written: without
+    /// any styling, unless colors are disabled (in which case the values are marked).
+    fn alerted(&self) -> Context {
+        let mut ctx = self.clone();
+        if !self.symbols {
+            ctx.theme = Theme::plain();
+        }
+        ctx
+    }
+
+    /// Style of the values breaching an alert: that of the theme, on the background of the
+    /// alert if it has one.
+    fn alert_style(&self, alert: &Alert, blink: bool) -> Style {
+        let style = match alert.color {
+            Some(color) => self.theme.alert.on(color),
+            None => self.theme.alert,
+        };
+        if blink || alert.blink {
+            style.blink()
+        } else {
+            style
+        }
+    }
+
+    fn format_rate(&self, amount: f64, unit: Unit) -> String {
+        format_number(self.rate(amount), unit, self.precision, self.rate_unit)
+    }
+
+    /// In symbols mode, prefix the text with the direction of the delta it derives from.
+    fn mark(&self, delta: i64, text: String) -> String {
+        if !self.symbols {
+            return text;
+        }
+        match delta.cmp(&0) {
+            std::cmp::Ordering::Greater => format!("\u{2191}{}", text),
+            std::cmp::Ordering::Less => format!("\u{2193}{}", text.trim_start_matches('-')),
+            std::cmp::Ordering::Equal => text,
+        }
+    }
+
+    /// An absolute value, its digits grouped by thousands if requested.
+    fn format_value(&self, value: i64) -> String {
+        match self.grouping {
+            Some(sep) => group_digits(value, sep),
+            None => value.to_string(),
+        }
+    }
+
+    fn format_delta(&self, delta: i64) -> String {
+        self.mark(delta, delta.to_string())
+    }
+
+    /// In symbols mode, enclose secondary information in brackets.
+    fn bracket(&self, text: String) -> String {
+        if self.symbols {
+            format!("[{}]", text)
+        } else {
+            text
+        }
     }
 }
 
-type WriterFn = dyn Fn(&mut dyn Write, (&i64, &i64, &i64, &i64), Duration) -> Result<()> + Send + Sync + 'static;
+type WriterFn = dyn Fn(&mut dyn Write, (&i64, &i64, &i64, &i64), &Context) -> Result<()>
+    + Send
+    + Sync
+    + 'static;
 
 pub struct WriterBox {
     write: Box<WriterFn>,
@@ -79,7 +325,7 @@ pub struct WriterBox {
 impl WriterBox {
     fn new<F>(style: &str, fun: F) -> Self
     where
-        F: Fn(&mut dyn Write, (&i64, &i64, &i64, &i64), Duration) -> Result<()>
+        F: Fn(&mut dyn Write, (&i64, &i64, &i64, &i64), &Context) -> Result<()>
             + Send
             + Sync
             + 'static,
@@ -93,88 +339,97 @@ impl WriterBox {
     pub fn index(s: &str) -> Option<usize> {
         WRITERS.iter().position(|w| w.style == s)
     }
+
+    pub fn name(idx: usize) -> &'static str {
+        &WRITERS[idx % WRITERS.len()].style
+    }
+
+    pub fn count() -> usize {
+        WRITERS.len()
+    }
 }
 
 lazy_static! {
     static ref WRITERS: Vec<WriterBox> = vec![
         WriterBox::new(
             "default",
-            |out: &mut dyn Write, num: (&i64, &i64, &i64, &i64), _: Duration| -> Result<()> {
-                write!(out, "{}", Colour::Blue.paint(format!("{}", num.0)))?;
+            |out: &mut dyn Write, num: (&i64, &i64, &i64, &i64), ctx: &Context| -> Result<()> {
+                write!(out, "{}", ctx.theme.value.paint(ctx.format_value(*num.0)))?;
                 Ok(())
             }
         ),
         WriterBox::new(
             "abs-delta",
-            |out: &mut dyn Write, num: (&i64, &i64, &i64, &i64), _: Duration| -> Result<()> {
-                write!(out, "{}", Colour::Blue.paint(format!("{}", num.0)))?;
+            |out: &mut dyn Write, num: (&i64, &i64, &i64, &i64), ctx: &Context| -> Result<()> {
+                write!(out, "{}", ctx.theme.value.paint(ctx.format_value(*num.0)))?;
                 if num.1 != &0 {
-                    write!(out, "_{}", Colour::Red.paint(format!("{}", num.1)))?;
+                    write!(out, "_{}", ctx.theme.delta.paint(ctx.format_delta(*num.1)))?;
                 }
                 Ok(())
             }
         ),
         WriterBox::new(
             "delta",
-            |out: &mut dyn Write, num: (&i64, &i64, &i64, &i64), _: Duration| -> Result<()> {
-                write!(out, "{}", Colour::Red.bold().paint(format!("{}", num.1)))?;
+            |out: &mut dyn Write, num: (&i64, &i64, &i64, &i64), ctx: &Context| -> Result<()> {
+                write!(
+                    out,
+                    "{}",
+                    ctx.theme.delta.bold().paint(ctx.format_delta(*num.1))
+                )?;
                 Ok(())
             }
         ),
         WriterBox::new(
             "fancy",
-            |out: &mut dyn Write,
-             num: (&i64, &i64, &i64, &i64),
-             interval: Duration|
-             -> Result<()> {
+            |out: &mut dyn Write, num: (&i64, &i64, &i64, &i64), ctx: &Context| -> Result<()> {
                 if *num.1 != 0 {
-                    let delta = *num.1 as f64 / interval.as_secs_f64();
-                    write!(
-                        out,
-                        "{}",
-                        Colour::Red
-                            .bold()
-                            .paint(format_number(delta, false).to_string())
-                    )?;
+                    let delta = ctx.mark(*num.1, ctx.format_rate(*num.1 as f64, Unit::None));
+                    write!(out, "{}", ctx.theme.rate.paint(delta))?;
                     Ok(())
                 } else {
-                    write!(out, "{}", Colour::Blue.paint(format!("{}", num.0)))?;
+                    write!(out, "{}", ctx.theme.value.paint(ctx.format_value(*num.0)))?;
                     Ok(())
                 }
             }
         ),
         WriterBox::new(
             "fancy-net",
-            |out: &mut dyn Write,
-             num: (&i64, &i64, &i64, &i64),
-             interval: Duration|
-             -> Result<()> {
+            |out: &mut dyn Write, num: (&i64, &i64, &i64, &i64), ctx: &Context| -> Result<()> {
                 if *num.1 != 0 {
-                    let delta = (*num.1 * 8) as f64 / interval.as_secs_f64();
-                    write!(
-                        out,
-                        "{}",
-                        Colour::Green
-                            .bold()
-                            .paint(format_number(delta, true).to_string())
-                    )?;
+                    let delta = ctx.mark(*num.1, ctx.format_rate(*num.1 as f64 * 8.0, Unit::Bits));
+                    write!(out, "{}", ctx.theme.throughput.paint(delta))?;
+                    Ok(())
+                } else {
+                    write!(out, "{}", ctx.theme.value.paint(ctx.format_value(*num.0)))?;
+                    Ok(())
+                }
+            }
+        ),
+        WriterBox::new(
+            "fancy-bytes",
+            |out: &mut dyn Write, num: (&i64, &i64, &i64, &i64), ctx: &Context| -> Result<()> {
+                if *num.1 != 0 {
+                    let delta = ctx.mark(*num.1, ctx.format_rate(*num.1 as f64, Unit::Bytes));
+                    write!(out, "{}", ctx.theme.throughput.paint(delta))?;
                     Ok(())
                 } else {
-                    write!(out, "{}", Colour::Blue.paint(format!("{}", num.0)))?;
+                    write!(out, "{}", ctx.theme.value.paint(ctx.format_value(*num.0)))?;
                     Ok(())
                 }
             }
         ),
         WriterBox::new(
             "stats",
-            |out: &mut dyn Write, num: (&i64, &i64, &i64, &i64), _: Duration| -> Result<()> {
-                write!(out, "{}", Colour::Blue.paint(format!("{}", num.0)))?;
+            |out: &mut dyn Write, num: (&i64, &i64, &i64, &i64), ctx: &Context| -> Result<()> {
+                write!(out, "{}", ctx.theme.value.paint(ctx.format_value(*num.0)))?;
                 if num.1 != &0 {
-                    write!(out, "_{}", Colour::Red.paint(format!("{}", num.1)))?;
+                    write!(out, "_{}", ctx.theme.delta.paint(ctx.format_delta(*num.1)))?;
                     write!(
                         out,
                         "_{}",
-                        Colour::Black.bold().paint(format!("{}/{}", num.2, num.3))
+                        ctx.theme
+                            .stats
+                            .paint(ctx.bracket(format!("{}/{}", num.2, num.3)))
                     )?;
                 }
                 Ok(())
@@ -182,222 +437,2414 @@ lazy_static! {
         ),
         WriterBox::new(
             "stats-net",
-            |out: &mut dyn Write,
-             num: (&i64, &i64, &i64, &i64),
-             interval: Duration|
-             -> Result<()> {
+            |out: &mut dyn Write, num: (&i64, &i64, &i64, &i64), ctx: &Context| -> Result<()> {
                 if *num.1 != 0 {
-                    let delta = *num.1 as f64 * 8.0 / interval.as_secs_f64();
+                    let delta = ctx.mark(*num.1, ctx.format_rate(*num.1 as f64 * 8.0, Unit::Bits));
+                    write!(out, "{}", ctx.theme.throughput.paint(delta))?;
                     write!(
                         out,
-                        "{}",
-                        Colour::Green
-                            .bold()
-                            .paint(format_number(delta, true).to_string())
+                        "_{}",
+                        ctx.theme.stats.paint(ctx.bracket(format!(
+                            "{}/{}",
+                            ctx.format_rate(*num.2 as f64 * 8.0, Unit::Bits),
+                            ctx.format_rate(*num.3 as f64 * 8.0, Unit::Bits)
+                        )))
                     )?;
+                    Ok(())
+                } else {
+                    write!(out, "{}", ctx.theme.value.paint(ctx.format_value(*num.0)))?;
+                    Ok(())
+                }
+            }
+        ),
+        WriterBox::new(
+            "stats-bytes",
+            |out: &mut dyn Write, num: (&i64, &i64, &i64, &i64), ctx: &Context| -> Result<()> {
+                if *num.1 != 0 {
+                    let delta = ctx.mark(*num.1, ctx.format_rate(*num.1 as f64, Unit::Bytes));
+                    write!(out, "{}", ctx.theme.throughput.paint(delta))?;
                     write!(
                         out,
                         "_{}",
-                        Colour::Black.bold().paint(format!(
+                        ctx.theme.stats.paint(ctx.bracket(format!(
                             "{}/{}",
-                            format_number(*num.2 as f64 * 8.0 / interval.as_secs_f64(), true),
-                            format_number(*num.3 as f64 * 8.0 / interval.as_secs_f64(), true)
-                        ))
+                            ctx.format_rate(*num.2 as f64, Unit::Bytes),
+                            ctx.format_rate(*num.3 as f64, Unit::Bytes)
+                        )))
                     )?;
                     Ok(())
                 } else {
-                    write!(out, "{}", Colour::Blue.paint(format!("{}", num.0)))?;
+                    write!(out, "{}", ctx.theme.value.paint(ctx.format_value(*num.0)))?;
                     Ok(())
                 }
             }
         ),
+        WriterBox::new(
+            "duration",
+            |out: &mut dyn Write, num: (&i64, &i64, &i64, &i64), ctx: &Context| -> Result<()> {
+                write!(
+                    out,
+                    "{}",
+                    ctx.theme.value.paint(format_duration(*num.0, false))
+                )?;
+                if num.1 != &0 {
+                    let delta = ctx.mark(*num.1, format_duration(*num.1, false));
+                    write!(out, "_{}", ctx.theme.delta.paint(delta))?;
+                }
+                Ok(())
+            }
+        ),
+        WriterBox::new(
+            "duration-ms",
+            |out: &mut dyn Write, num: (&i64, &i64, &i64, &i64), ctx: &Context| -> Result<()> {
+                write!(
+                    out,
+                    "{}",
+                    ctx.theme.value.paint(format_duration(*num.0, true))
+                )?;
+                if num.1 != &0 {
+                    let delta = ctx.mark(*num.1, format_duration(*num.1, true));
+                    write!(out, "_{}", ctx.theme.delta.paint(delta))?;
+                }
+                Ok(())
+            }
+        ),
     ];
 }
 
-pub fn run(opt: Options, term: Arc<AtomicBool>, style_index: Arc<AtomicUsize>) -> Result<()> {
-    let interval = Duration::from_secs(opt.interval.unwrap_or(1));
+/// Watch the commands until dwatch terminates, returning its exit code.
+pub fn run(
+    opt: Options,
+    control: Arc<Control>,
+    style_map: StyleMap,
+    alerts: Vec<Alert>,
+    player: Option<Player>,
+) -> Result<u8> {
+    let style_map = Arc::new(Mutex::new(style_map));
+    // the style of labelled commands is recorded under their label
+    let labels = opt.labels.clone();
+
+    // persist the style map even if a panic brings dwatch down
+    let default_hook = std::panic::take_hook();
+    {
+        let style_map = Arc::clone(&style_map);
+        let control = Arc::clone(&control);
+        let labels = labels.clone();
+        std::panic::set_hook(Box::new(move |info| {
+            if let (Ok(mut map), Ok(commands)) = (style_map.try_lock(), control.commands.try_lock())
+            {
+                let _ = map.update(&command_names(&labels, &commands), control.style_name());
+            }
+            default_hook(info);
+        }));
+    }
+
+    let result = watch(opt, &control, &alerts, player);
+
+    // the style is recorded for the commands as last edited
+    let commands = command_names(&labels, &control.commands.lock().unwrap());
+    let saved = style_map
+        .lock()
+        .map_err(|e| anyhow!("style map lock error: {:?}", e))?
+        .update(&commands, control.style_name());
+
+    result.and_then(|code| saved.map(|_| code))
+}
+
+fn watch(
+    opt: Options,
+    control: &Arc<Control>,
+    alerts: &[Alert],
+    mut player: Option<Player>,
+) -> Result<u8> {
+    let until = opt.until.as_deref().map(Regex::new).transpose()?;
+
+    let mut ctx = Context::new(&opt, control.interval())?;
+
+    // with --output ndjson the frames are still computed, only their rendering is discarded
+    let records_to_stdout = opt.output == Some(OutputFormat::Ndjson) && opt.output_file.is_none();
+    let csv_to_stdout = opt.log_csv.as_deref() == Some(std::path::Path::new("-"));
+    // headless, the frames are only appended to the --log file, as rendered with --no-clear
+    let mut screen: Box<dyn Write> = if opt.headless {
+        match &opt.log {
+            Some(path) => Box::new(Log::new(open_append(path)?)),
+            None => Box::new(std::io::sink()),
+        }
+    } else if records_to_stdout || csv_to_stdout || opt.export || opt.ticker.is_some() {
+        Box::new(std::io::sink())
+    } else if opt.no_clear {
+        Box::new(Log::new(std::io::stdout()))
+    } else {
+        Box::new(std::io::stdout())
+    };
+    let mut records: Option<Box<dyn Write>> = match (opt.output, &opt.output_file) {
+        (Some(OutputFormat::Ndjson), Some(path)) => Some(Box::new(open_append(path)?)),
+        (Some(OutputFormat::Ndjson), None) => Some(Box::new(std::io::stdout())),
+        _ => None,
+    };
+    let mut csv: Option<Box<dyn Write>> = match opt.log_csv.as_deref() {
+        Some(_) if csv_to_stdout => {
+            println!("{}", CSV_HEADER);
+            Some(Box::new(std::io::stdout()))
+        }
+        Some(path) => Some(Box::new(open_csv(path)?)),
+        None => None,
+    };
+    let exporter = opt
+        .prometheus
+        .as_deref()
+        .map(prometheus::serve)
+        .transpose()?;
+    let dashboard = opt.serve.as_deref().map(web::serve).transpose()?;
+    let grpc = opt.grpc.as_deref().map(grpc::serve).transpose()?;
+    let statsd = opt.statsd.as_deref().map(statsd::connect).transpose()?;
+    let graphite = opt.graphite.as_deref().map(graphite::connect).transpose()?;
+    let mqtt = opt.mqtt.as_deref().map(mqtt::connect).transpose()?;
+    let mut recorder = opt.record.as_deref().map(Recorder::create).transpose()?;
+    let mut history = opt.history_csv.as_ref().map(|_| History::default());
+    let mut summary = opt
+        .summary_json
+        .as_ref()
+        .map(|_| Summary::new(alerts.len()));
+    // the numbers of the snapshots of --baseline, by command
+    let baselines = opt
+        .baseline
+        .iter()
+        .map(|path| {
+            std::fs::read(path)
+                .map(|data| line_map(&String::from_utf8_lossy(&data)))
+                .with_context(|| format!("cannot read {}", path.display()))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    // Influx lines are appended to a file, or posted to an http:// endpoint
+    let mut influx = match opt.influx.as_deref() {
+        Some(target) if !http::is_url(target) => Some(open_append(target.as_ref())?),
+        _ => None,
+    };
+    // the numbers of the frames are collected for whichever of the above is set
+    let exporting = records.is_some()
+        || opt.ticker.is_some()
+        || csv.is_some()
+        || exporter.is_some()
+        || dashboard.is_some()
+        || grpc.is_some()
+        || statsd.is_some()
+        || graphite.is_some()
+        || mqtt.is_some()
+        || opt.history_csv.is_some()
+        || opt.summary_json.is_some()
+        || opt.influx.is_some()
+        || opt.remote_write.is_some();
+
+    if !opt.no_clear {
+        write!(screen, "{}", ansi_escapes::ClearScreen)?;
+    }
 
-    print!("{}", ansi_escapes::ClearScreen);
+    // with --jitter, the whole schedule starts late (a refresh or quitting cuts the delay short)
+    if let Some(jitter) = opt.jitter {
+        control.wait(Instant::now() + random_delay(jitter));
+    }
 
     let now = Instant::now();
     let end = now + Duration::from_secs(opt.seconds.unwrap_or(9999999999));
-    let mut next = now + interval;
-    let mut line_map = LineMap::new();
-
-    let opt = Arc::new(opt);
+    let mut next = now + frame_interval(&opt, control);
+    let mut forced = false;
+    let mut last_sample: Option<Instant> = None;
+    // slots skipped by --precise
+    let mut missed = 0u64;
+    // alerts firing, by index
+    let mut states = vec![alert::State::default(); alerts.len()];
+    let mut firing: Vec<usize> = Vec::new();
+    // some alert fired during the session, for --fail-on-alert
+    let mut alerted = false;
+    let mut iteration = 0u64;
+    let mut commands = control.commands.lock().unwrap().clone();
+    let mut runners: Vec<Runner> = Vec::new();
+    let mut exit_code = 0;
+    // output of the last frame, for --chgexit
+    let mut last_output: Option<String> = None;
+    // and of each command, for --on-diff
+    let mut last_outputs: Option<Vec<String>> = None;
+    // and what was rendered of the commands, for --quiet
+    let mut last_rendered: Option<Vec<(String, String, Option<String>)>> = None;
+    // frames are rendered as output comes rather than at every interval
+    let by_event = opt.follow || opt.on_change || opt.stdin;
 
     while Instant::now() < end {
-        if term.load(Ordering::Relaxed) {
+        if control.term.load(Ordering::Relaxed) {
             eprintln!("SIGTERM");
             break;
         }
 
-        let mut thread_handles: Vec<JoinHandle<_>> = Vec::with_capacity(opt.commands.len());
+        // a repaint renders the last outputs again, e.g. for what is typed at the prompt to show
+        // right away: nothing is run, exported or checked, and the schedule is left as it was
+        let repaint = control.repaint.swap(false, Ordering::Relaxed) && !forced;
 
-        for cmd in &opt.commands {
-            let cmd = cmd.clone();
-            let opt = Arc::clone(&opt);
-            thread_handles.push(std::thread::spawn(move || run_command(&cmd, opt).unwrap()));
+        // while paused the last frame stays on screen, only the banner is refreshed (a manual
+        // refresh still renders a new frame)
+        if control.paused.load(Ordering::Relaxed) && !forced && !repaint {
+            if !opt.no_banner && !opt.no_clear {
+                write!(screen, "{}", ansi_escapes::CursorTo::TopLeft)?;
+                write_banner(
+                    &mut screen,
+                    &opt,
+                    control,
+                    &runners,
+                    missed,
+                    &firing.iter().map(|&idx| &alerts[idx]).collect_vec(),
+                    player.as_ref(),
+                )?;
+                screen.flush()?;
+            }
+            forced = wait_next(
+                control,
+                &mut next,
+                frame_interval(&opt, control),
+                (opt.step || by_event).then_some(end),
+                opt.precise.then_some(&mut missed),
+            );
+            continue;
         }
 
-        print!("{}", ansi_escapes::CursorTo::TopLeft);
-
-        if !opt.no_banner {
-            println!(
-                "Every {} ms, delta[{}]: {}{}\n",
-                interval.as_millis(),
-                WRITERS[style_index.load(Ordering::Relaxed) % WRITERS.len()].style,
-                opt.commands.join(" | "),
-                ansi_escapes::EraseEndLine
-            );
+        // rates of a frame refreshed on demand (or rendered as output comes, or scheduled with
+        // --precise) are computed over the time actually elapsed
+        let sampled = Instant::now();
+        if !repaint {
+            ctx.interval = match last_sample {
+                Some(last) if forced || by_event || opt.precise => sampled - last,
+                _ => control.interval(),
+            };
+            last_sample = Some(sampled);
         }
 
-        let mut lineno = 0u64;
-        let writer_idx = style_index.load(Ordering::Relaxed) % WRITERS.len();
+        // a replayed frame takes the place of the runs of the commands, its rates are computed
+        // over the recorded time, and its deltas restart after a seek
+        let replayed = match player.as_mut().filter(|_| !repaint) {
+            Some(player) => {
+                let seek = control.seek.swap(0, Ordering::Relaxed);
+                let (frame, consecutive) = player.advance(seek);
+                let (outputs, timestamp) = (frame.outputs.clone(), frame.timestamp);
+                *control.commands.lock().unwrap() = frame.commands.to_vec();
+                if !consecutive {
+                    runners.iter_mut().for_each(|r| r.line_map.clear());
+                }
+                ctx.interval = player.elapsed().unwrap_or(ctx.interval);
+                Some((outputs, timestamp))
+            }
+            None => None,
+        };
+        let focused_ctx = ctx.focused();
+        let alerted_ctx = ctx.alerted();
+        let alert_styles: Vec<Style> = alerts
+            .iter()
+            .map(|a| ctx.alert_style(a, opt.blink))
+            .collect();
 
-        for th in thread_handles {
-            let output = th
-                .join()
-                .map_err(|e| -> anyhow::Error { anyhow!("Thread Join error: {:?}", e) })?;
+        if control.reset.swap(false, Ordering::Relaxed) {
+            runners.iter_mut().for_each(|r| r.line_map.clear());
+        }
 
-            // transform and print the output, line by line
-            for line in output.lines() {
-                writeln_line(
-                    &mut std::io::stdout(),
-                    writer_idx,
-                    line,
-                    lineno,
-                    &mut line_map,
-                    interval,
-                )?;
-                lineno += 1;
+        // the deltas of an edited command restart from its new output, the others are preserved
+        let current = control.commands.lock().unwrap().clone();
+        runners.resize_with(current.len(), Runner::default);
+        for (idx, runner) in runners.iter_mut().enumerate() {
+            if commands.get(idx) != current.get(idx) {
+                runner.abort();
+                *runner = Runner::default();
             }
         }
+        commands = current;
 
-        write!(&mut std::io::stdout(), "{}", ansi_escapes::EraseDown)?;
-
-        let nap = next - Instant::now();
-        next += interval;
-        sleep(nap);
-    }
-
-    Ok(())
-}
+        iteration += u64::from(!repaint);
+        let env = child_env(&opt, iteration, control.interval());
+        let overlap = opt.overlap.unwrap_or_default();
+        verbose::event!(
+            "schedule",
+            "frame {} ({}), rates over {}{}",
+            iteration,
+            match () {
+                _ if repaint => "repainted",
+                _ if replayed.is_some() => "replayed",
+                _ if forced => "refreshed on demand",
+                _ if by_event => "new output",
+                _ => "scheduled",
+            },
+            format_duration(ctx.interval.as_millis() as i64, true),
+            if missed > 0 {
+                format!(", {} slots missed so far", missed)
+            } else {
+                String::new()
+            }
+        );
 
-fn writeln_line(
-    out: &mut dyn Write,
-    writer_idx: usize,
-    line: &str,
-    lineno: u64,
-    lmap: &mut LineMap,
-    interval: Duration,
-) -> Result<()> {
-    let rp = RangeParser::new(|c| c.is_ascii_whitespace() || ".,:;()[]{}<>'`\"|".contains(c));
+        if let Some((outputs, _)) = &replayed {
+            for (runner, output) in runners.iter_mut().zip(outputs) {
+                runner.fresh |= *output != runner.output;
+                runner.output.clone_from(output);
+            }
+        } else if !repaint {
+            let scheduled = opt.intervals.iter().any(Option::is_some) && !opt.follow;
+            let tick = frame_interval(&opt, control);
+            for (idx, (cmd, runner)) in commands.iter().zip(&mut runners).enumerate() {
+                // followed commands (and the standard input) are started once, and whatever they
+                // output since is shown
+                if cmd == STDIN || (opt.follow && !is_builtin(cmd)) {
+                    if runner.feed.is_none() {
+                        let control = Arc::clone(control);
+                        runner.follow(cmd, &opt, &env, move || control.notify());
+                    }
+                    runner.take_block(&opt);
+                    runner.collect(&opt);
+                    continue;
+                }
 
-    let ranges = rp.get_numeric_ranges(line);
-    let strings = parse_strings(line, &ranges);
-    let numbers = parse_numbers(line, &ranges);
-    let key = (lineno, chunks_fingerprint(&strings));
+                runner.collect(&opt);
+                // with commands on their own intervals, each is run when due (the others at the
+                // interval of the commands), and its rates computed over the time between its runs
+                if scheduled {
+                    let interval = opt.intervals.get(idx).copied().flatten();
+                    let interval = interval.unwrap_or_else(|| control.interval());
+                    // frames do not wake up exactly on time, half of one is close enough
+                    let due = runner.due.is_none_or(|due| due <= sampled + tick / 2);
+                    runner.idle = !forced && !due;
+                    if runner.idle {
+                        verbose::event!("schedule", "'{}' not due yet", cmd);
+                        continue;
+                    }
+                    runner.due = Some(sampled + interval);
+                    runner.elapsed = runner.last_run.map(|last| sampled - last);
+                    runner.last_run = Some(sampled);
+                }
+                if !runner.jobs.is_empty() {
+                    verbose::event!("schedule", "'{}' still running, {:?}", cmd, overlap);
+                    match overlap {
+                        Overlap::Queue => runner.finish(&opt),
+                        Overlap::Skip => continue,
+                        Overlap::Kill => runner.kill(&opt),
+                        Overlap::Concurrent => {}
+                    }
+                }
+                runner.start(cmd, &opt, &env);
+                // so that commands do not compete with each other for the resources they measure
+                if opt.sequential {
+                    runner.finish(&opt);
+                }
+            }
 
-    let line_stat = lmap.entry(key).or_insert(LineNumbers::new(numbers.clone()));
+            // runs still in flight at the next refresh are dealt with according to the overlap
+            // policy, unless queued (followed commands are never waited for)
+            if overlap == Overlap::Queue {
+                runners
+                    .iter_mut()
+                    .filter(|r| r.feed.is_none())
+                    .for_each(|r| r.finish(&opt));
+            } else {
+                while Instant::now() < next
+                    && !control.term.load(Ordering::Relaxed)
+                    && runners
+                        .iter()
+                        .any(|r| r.feed.is_none() && !r.jobs.is_empty())
+                {
+                    std::thread::sleep(POLL_INTERVAL);
+                    runners.iter_mut().for_each(|r| r.collect(&opt));
+                }
+            }
 
-    let stat = {
-        if numbers.len() == line_stat.num.len() {
-            let mut deltas = Vec::with_capacity(numbers.len());
+            if verbose::enabled() {
+                for (cmd, runner) in commands.iter().zip(&runners) {
+                    if runner.feed.is_some() || runner.idle {
+                        continue;
+                    }
+                    match (&runner.status, runner.runtime) {
+                        _ if !runner.jobs.is_empty() => {
+                            verbose::event!("command", "'{}' still running", cmd)
+                        }
+                        (Some(status), Some(runtime)) => verbose::event!(
+                            "command",
+                            "'{}' {} in {}{}",
+                            cmd,
+                            status,
+                            format_duration(runtime.as_millis() as i64, true),
+                            match &runner.stale {
+                                Some(reason) => format!(", last output kept ({})", reason),
+                                None => String::new(),
+                            }
+                        ),
+                        _ => {}
+                    }
+                }
+            }
+        }
 
-            for (a, b) in numbers.iter().zip(line_stat.num.iter()) {
-                deltas.push(a - b);
+        // the interval is lengthened to what the slowest command takes
+        if opt.auto_interval && !by_event && player.is_none() {
+            if let Some(runtime) = runners.iter().filter_map(|r| r.runtime).max() {
+                if control.extend_interval(runtime) {
+                    verbose::event!(
+                        "schedule",
+                        "interval lengthened to {}, as a run took {}",
+                        format_duration(control.interval().as_millis() as i64, true),
+                        format_duration(runtime.as_millis() as i64, true)
+                    );
+                    next = sampled + frame_interval(&opt, control);
+                }
             }
-            line_stat.num = numbers.clone();
-            line_stat.delta = deltas;
+        }
 
-            for (min, max, value) in
-                multizip((&mut line_stat.min, &mut line_stat.max, &line_stat.delta))
-            {
-                *min = std::cmp::min(*min, *value);
-                *max = std::cmp::max(*max, *value);
+        if let Some(recorder) = recorder.as_mut().filter(|_| !repaint) {
+            let outputs = runners.iter().map(|r| r.output.as_str());
+            recorder.frame(unix_time(), &commands, outputs)?;
+        }
+
+        // with --quiet, a frame identical to the previous one is rendered to nowhere (deltas,
+        // alerts and exports are still taken care of), unless refreshed on demand
+        let mut muted = None;
+        if opt.quiet && !repaint {
+            let rendered: Vec<_> = runners
+                .iter()
+                .map(|r| (r.output.clone(), r.stderr.clone(), r.status.clone()))
+                .collect();
+            if !forced && last_rendered.as_ref() == Some(&rendered) {
+                muted = Some(std::mem::replace(&mut screen, Box::new(std::io::sink())));
             }
+            last_rendered = Some(rendered);
+        }
 
-            line_stat.clone()
+        if opt.no_clear {
+            writeln!(
+                screen,
+                "--- {} ---",
+                local_time(std::time::SystemTime::now())
+            )?;
         } else {
-            line_stat.num = numbers.clone();
-            line_stat.delta = vec![0; numbers.len()];
-            line_stat.min = vec![0; numbers.len()];
-            line_stat.max = vec![0; numbers.len()];
-            line_stat.clone()
+            write!(screen, "{}", ansi_escapes::CursorTo::TopLeft)?;
         }
-    };
 
-    writeln_data(out, writer_idx, &strings, &stat, &ranges, interval)
-}
+        if !opt.no_banner {
+            write_banner(
+                &mut screen,
+                &opt,
+                control,
+                &runners,
+                missed,
+                &firing.iter().map(|&idx| &alerts[idx]).collect_vec(),
+                player.as_ref(),
+            )?;
+        }
 
-fn writeln_data(
-    out: &mut dyn Write,
-    writer_idx: usize,
-    strings: &[&str],
-    stat: &LineNumbers,
-    ranges: &[Range<usize>],
-    interval: Duration,
-) -> Result<()> {
-    let s = strings.iter();
-    let first_is_number = !ranges.is_empty() && ranges[0].start == 0;
+        let focus = control.focus.lock().unwrap().clone();
+        let mut sel = Selection {
+            style: control.style.load(Ordering::Relaxed),
+            targets: focus.targets(),
+            focus,
+            ordinal: 0,
+            lines: Vec::new(),
+            focused: &focused_ctx,
+            row: if opt.no_banner { 0 } else { 2 },
+            col: 0,
+            hits: Vec::new(),
+            exit_if: &opt.exit_if,
+            triggered: false,
+            alerts,
+            alerted: &alerted_ctx,
+            alert_styles: &alert_styles,
+            alerting: BTreeMap::new(),
+            firing: BTreeMap::new(),
+            changed: false,
+            records: exporting.then(Vec::new),
+        };
 
-    for chunk in izip!(&stat.num, &stat.delta, &stat.min, &stat.max).zip_longest(s) {
-        match chunk {
-            Both(numbers, string) => {
-                if first_is_number {
-                    write_number(out, writer_idx, numbers, interval)?;
-                    write!(out, "{}", string)?;
-                } else {
-                    write!(out, "{}", string)?;
-                    write_number(out, writer_idx, numbers, interval)?;
+        let mut sources = Vec::with_capacity(commands.len());
+
+        for (idx, (cmd, runner)) in commands.iter().zip(&mut runners).enumerate() {
+            let first_value = sel.ordinal;
+            // deltas against a baseline are always computed from the snapshot
+            if let Some(baseline) = baselines.get(idx) {
+                runner.line_map = baseline.clone();
+            }
+            // a followed command that output nothing new keeps its deltas, as does one not due
+            let update = !repaint
+                && (!(opt.follow || opt.stdin) || std::mem::take(&mut runner.fresh))
+                && !std::mem::take(&mut runner.idle);
+            let runner_ctx;
+            let ctx = match runner.elapsed {
+                Some(elapsed) => {
+                    runner_ctx = Context {
+                        interval: elapsed,
+                        ..ctx.clone()
+                    };
+                    &runner_ctx
                 }
+                None => &ctx,
+            };
+
+            // the output of commands run on different hosts is told apart by a header, as is
+            // that of labelled commands
+            let header = match opt.labels.get(idx) {
+                Some(label) => Some(label.clone()),
+                None if commands.len() > 1 => remote::target(cmd),
+                None => None,
+            };
+            if let Some(header) = header {
+                let runtime = runner.runtime_text(control.interval());
+                write_section(&mut screen, &mut sel, &header, runtime, ctx)?;
             }
-            Left(numbers) => {
-                write_number(out, writer_idx, numbers, interval)?;
+
+            // transform and print the output, line by line
+            for (lineno, line) in runner.output.lines().enumerate() {
+                writeln_line(
+                    &mut screen,
+                    &mut sel,
+                    line,
+                    lineno as u64,
+                    &mut runner.line_map,
+                    ctx,
+                    update,
+                )?;
             }
-            Right(string) => {
-                write!(out, "{}", string)?;
+            sources.push(first_value..sel.ordinal);
+
+            if opt.show_stderr {
+                write_stderr(&mut screen, &mut sel, &runner.stderr, ctx)?;
             }
         }
-    }
-
-    writeln!(out, "{}", ansi_escapes::EraseEndLine)?;
-    Ok(())
-}
-
-fn write_number(
-    out: &mut dyn Write,
-    writer_idx: usize,
-    numbers: (&i64, &i64, &i64, &i64),
-    interval: Duration,
-) -> Result<()> {
-    (WRITERS[writer_idx].write)(out, numbers, interval)
-}
 
-fn run_command(cmd: &str, _opt: Arc<Options>) -> Result<String> {
-    let output = std::process::Command::new("sh")
-        .arg("-c")
-        .arg(cmd)
-        .output()
-        .expect("failed to execute process");
+        if let Some(prompt) = control.prompt.lock().unwrap().as_ref() {
+            write!(&mut screen, "\n{}: {}_", prompt.label(), prompt.text())?;
+        }
 
-    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
-}
+        if !opt.no_clear {
+            write!(&mut screen, "{}", ansi_escapes::EraseDown)?;
+        }
+        screen.flush()?;
+        if let Some(muted) = muted {
+            screen = muted;
+        }
 
-#[inline]
-pub fn parse_numbers(line: &str, ranges: &[Range<usize>]) -> Vec<i64> {
-    ranges
-        .iter()
-        .map(|r| line[r.clone()].parse::<i64>().unwrap())
-        .collect()
-}
+        if repaint {
+            *control.hits.lock().unwrap() = sel.hits;
+            forced = wait_next(
+                control,
+                &mut next,
+                frame_interval(&opt, control),
+                (opt.step || by_event).then_some(end),
+                None,
+            );
+            continue;
+        }
 
-#[inline]
-pub fn parse_strings<'a>(line: &'a str, ranges: &[Range<usize>]) -> Vec<&'a str> {
-    complement_ranges(ranges, line.len())
-        .iter()
-        .map(|r| &line[r.clone()])
+        let triggered = sel.triggered;
+        if let Some(frame) = sel.records.take() {
+            // a replayed frame is exported as of when it was recorded
+            let timestamp = replayed.as_ref().map_or_else(unix_time, |(_, t)| *t);
+            // labelled commands are exported under their label
+            let names = command_names(&opt.labels, &commands);
+            if let Some(history) = history.as_mut() {
+                history.push(timestamp, &frame, &sources, &names, &sel.targets);
+            }
+            if let Some(summary) = summary.as_mut() {
+                summary.push(&frame, &sources, &names);
+            }
+            if let Some(out) = records.as_mut() {
+                write_records(out, &frame, &sources, &names, timestamp)?;
+            }
+            if let Some(out) = csv.as_mut() {
+                write_csv(out, &frame, &sources, &names, timestamp)?;
+            }
+            if let Some(exporter) = &exporter {
+                exporter.publish(exposition(&frame, &sources, &names));
+            }
+            if let Some(dashboard) = &dashboard {
+                let outputs = runners.iter().map(|r| r.output.as_str());
+                dashboard.publish(dashboard_update(
+                    &frame, &sources, &names, outputs, timestamp,
+                ));
+            }
+            if let Some(server) = &grpc {
+                let outputs = runners.iter().map(|r| r.output.as_str());
+                server.publish(frame_message(&frame, &sources, &names, outputs, timestamp));
+            }
+            if let Some(client) = &statsd {
+                client.send(&statsd_metrics(&frame, &sources, &names));
+            }
+            if let Some(selected) = &opt.ticker {
+                let time = local_time(std::time::UNIX_EPOCH + timestamp);
+                let line = ticker_line(&frame, &sources, selected, &ctx, &time[11..19]);
+                println!("{}", line);
+            }
+            if let Some(client) = &graphite {
+                let prefix = opt.graphite_prefix.as_deref().unwrap_or("dwatch");
+                client.send(graphite_lines(&frame, &sources, &names, prefix, timestamp));
+            }
+            if let Some(client) = &mqtt {
+                client.publish(
+                    frame
+                        .iter()
+                        .map(|r| record_json(r, source(r, &sources, &names), timestamp))
+                        .collect(),
+                );
+            }
+            if let Some(url) = &opt.remote_write {
+                let items = frame.iter().map(|r| {
+                    let cmd = source(r, &sources, &names);
+                    match &opt.remote_write_template {
+                        Some(template) => render_template(template, r, cmd, timestamp),
+                        None => record_json(r, cmd, timestamp),
+                    }
+                });
+                let body = items.map(|item| item + "\n").collect();
+                remote_write(&opt, url, body);
+            }
+            if let Some(target) = &opt.influx {
+                let lines = line_protocol(&frame, &sources, &names, timestamp);
+                match influx.as_mut() {
+                    Some(out) => out.write_all(lines.as_bytes())?,
+                    None => post_influx(&opt, target, lines),
+                }
+            }
+        }
+        // alerts change state once their rule held (or not) for long enough, and each change is
+        // reported once
+        let now = Instant::now();
+        let mut fired = false;
+        for (idx, alert) in alerts.iter().enumerate() {
+            let breach = sel.firing.get(&idx);
+            let was_active = states[idx].active;
+            let transition = states[idx].update(alert, breach.is_some(), now);
+            // firings within a cooldown count, even though they are not reported
+            if let Some(summary) = summary.as_mut() {
+                summary.fired[idx] += u64::from(!was_active && states[idx].active);
+            }
+            match transition {
+                Some(Transition::Fired) => {
+                    fired = true;
+                    if let Some(breach) = breach {
+                        let cmd = sources
+                            .iter()
+                            .position(|r| r.contains(&breach.ordinal))
+                            .map_or("", |i| opt.labels.get(i).unwrap_or(&commands[i]).as_str());
+                        let env = child_env(&opt, iteration, control.interval());
+                        report_fired(&opt, alert, breach, cmd, &ctx, env);
+                    }
+                }
+                Some(Transition::Resolved) if !opt.webhooks.is_empty() => {
+                    let text = format!("dwatch: {} resolved", alert.label());
+                    send_webhooks(&opt, json::object(alert_payload(alert, "resolved", text)));
+                }
+                _ => {}
+            }
+        }
+        if opt.beep && !opt.headless && (sel.changed || fired) {
+            write!(&mut screen, "\x07")?;
+            screen.flush()?;
+        }
+        firing = (0..alerts.len()).filter(|&i| states[i].active).collect();
+        if let Some(dashboard) = &dashboard {
+            dashboard.publish_alerts(alerts_json(alerts, &states));
+        }
+        alerted |= !firing.is_empty();
+        control.tick(sel.ordinal, sel.lines);
+        *control.hits.lock().unwrap() = sel.hits;
+        *control.sources.lock().unwrap() = sources;
+
+        if let Some(hook) = &opt.on_diff {
+            let outputs: Vec<String> = runners.iter().map(|r| r.output.clone()).collect();
+            if let Some(last) = &last_outputs {
+                let changed: Vec<usize> = (0..outputs.len())
+                    .filter(|&i| last.get(i) != Some(&outputs[i]))
+                    .collect();
+                if !changed.is_empty() {
+                    let lines: usize = changed
+                        .iter()
+                        .map(|&i| {
+                            changed_lines(last.get(i).map_or("", String::as_str), &outputs[i])
+                        })
+                        .sum();
+                    let mut env = child_env(&opt, iteration, control.interval());
+                    env.push(("DWATCH_CHANGED", changed.iter().map(|i| i + 1).join(" ")));
+                    env.push(("DWATCH_CHANGED_LINES", lines.to_string()));
+                    env.push(("DWATCH_COMMAND", commands[changed[0]].clone()));
+                    process::hook(hook, env);
+                }
+            }
+            last_outputs = Some(outputs);
+        }
+
+        // the failed frame is left on screen
+        if opt.errexit {
+            if let Some(code) = runners.iter().find_map(Runner::failure_code) {
+                exit_code = code;
+                break;
+            }
+        }
+
+        // as is the first one with a line matching --until
+        if let Some(re) = &until {
+            if runners
+                .iter()
+                .any(|r| r.output.lines().any(|l| re.is_match(l)))
+            {
+                break;
+            }
+        }
+
+        // or the first one where a condition held for some number
+        if triggered {
+            break;
+        }
+
+        // or the last one requested
+        if opt.count.is_some_and(|count| iteration >= count) {
+            break;
+        }
+
+        // or the first one that changed
+        if opt.chgexit {
+            let output: String = runners.iter().map(|r| r.output.as_str()).collect();
+            if last_output.is_some_and(|last| last != output) {
+                break;
+            }
+            last_output = Some(output);
+        }
+
+        // replayed frames follow each other as recorded (paused at the end of the session), and
+        // exported ones without delay until the end
+        if let Some(player) = &player {
+            match player.gap() {
+                Some(_) if opt.export => next = sampled,
+                Some(gap) => next = sampled + gap.div_f64(control.speed()),
+                None if opt.export => break,
+                None => control.paused.store(true, Ordering::Relaxed),
+            }
+        }
+
+        forced = wait_next(
+            control,
+            &mut next,
+            frame_interval(&opt, control),
+            (opt.step || by_event).then_some(end),
+            opt.precise.then_some(&mut missed),
+        );
+    }
+
+    runners.iter_mut().for_each(Runner::abort);
+    if let (Some(path), Some(history)) = (&opt.history_csv, &history) {
+        let mut file = std::fs::File::create(path)
+            .with_context(|| format!("cannot create {}", path.display()))?;
+        history.write(&mut file)?;
+    }
+    if let (Some(path), Some(summary)) = (&opt.summary_json, &summary) {
+        let json = summary.to_json(iteration, now.elapsed(), alerts);
+        std::fs::write(path, json + "\n")
+            .with_context(|| format!("cannot write {}", path.display()))?;
+    }
+    if exit_code == 0
+        && ((opt.fail_on_firing && !firing.is_empty()) || (opt.fail_on_alert && alerted))
+    {
+        exit_code = 2;
+    }
+    Ok(exit_code)
+}
+
+/// Wait for the next scheduled frame, or for a refresh on demand (in which case the schedule
+/// restarts from now). Returns whether the refresh was requested. In step, follow and on-change
+/// modes (given the end of the session), only refreshes on demand and new output advance the
+/// display. With `missed` (`--precise`), the slots already past are skipped and counted rather
+/// than run back to back.
+fn wait_next(
+    control: &Control,
+    next: &mut Instant,
+    interval: Duration,
+    step_until: Option<Instant>,
+    missed: Option<&mut u64>,
+) -> bool {
+    if let Some(missed) = missed {
+        *missed += skip_missed(next, interval, Instant::now());
+    }
+    if control.wait(step_until.unwrap_or(*next)) {
+        *next = Instant::now() + interval;
+        true
+    } else {
+        // a repaint comes before the next frame is due
+        if !control.repaint.load(Ordering::Relaxed) {
+            *next += interval;
+        }
+        false
+    }
+}
+
+/// Run the commands once and print their output with the numbers found underlined, each line
+/// followed by the key (as in `--ticker` and the `--serve` API), name and value of its numbers, for
+/// `--dry-run`.
+pub fn dry_run(opt: &Options) -> Result<()> {
+    let interval = opt.interval.unwrap_or(Duration::from_secs(1));
+    let ctx = Context::new(opt, interval)?;
+    let env = child_env(opt, 1, interval);
+    let names = command_names(&opt.labels, &opt.commands);
+    let mut out = std::io::stdout().lock();
+    for (idx, (cmd, name)) in opt.commands.iter().zip(&names).enumerate() {
+        let mut runner = Runner::default();
+        if cmd == STDIN {
+            runner.output = std::io::read_to_string(std::io::stdin())?;
+        } else {
+            runner.start(cmd, opt, &env);
+            runner.finish(opt);
+        }
+        writeln!(
+            out,
+            "{} {}",
+            ctx.theme.section.paint(format!("[{}]", name)),
+            runner.status.as_deref().unwrap_or_default()
+        )?;
+        for (lineno, line) in runner.output.lines().enumerate() {
+            let (marked, numbers) = dry_run_line(line, lineno as u64, idx, &ctx);
+            writeln!(out, "{}", marked)?;
+            if !numbers.is_empty() {
+                writeln!(out, "    {}", ctx.theme.stats.paint(numbers))?;
+            }
+        }
+        for line in runner.stderr.lines() {
+            writeln!(out, "{}", ctx.theme.stderr.paint(line))?;
+        }
+    }
+    Ok(())
+}
+
+/// A line of `--dry-run`: the line with its numbers underlined (bracketed without colors), and
+/// the key, name and value of each of them, e.g. `0.3.0 rx=1234`.
+fn dry_run_line(line: &str, lineno: u64, source: usize, ctx: &Context) -> (String, String) {
+    let ranges = numeric_ranges(line);
+    let mut marked = String::with_capacity(line.len());
+    let mut last = 0;
+    for range in &ranges {
+        marked += &line[last..range.start];
+        let number = &line[range.clone()];
+        if ctx.symbols {
+            marked += &format!("[{}]", number);
+        } else {
+            marked += &ctx.theme.value.underline().paint(number).to_string();
+        }
+        last = range.end;
+    }
+    marked += &line[last..];
+
+    let key = line_key(line, &ranges);
+    let numbers = parse_numbers(line, &ranges)
+        .into_iter()
+        .enumerate()
+        .map(|(index, value)| {
+            let record = Record {
+                ordinal: 0,
+                line: lineno,
+                key: key.clone(),
+                index,
+                value,
+                change: None,
+            };
+            let name = number_name(&record);
+            format!("{}.{}.{} {}={}", source, lineno, index, name, value)
+        })
+        .join("  ");
+    (marked, numbers)
+}
+
+/// Names of the commands: their label, if labelled, their command line otherwise.
+pub fn command_names(labels: &[String], commands: &[String]) -> Vec<String> {
+    commands
+        .iter()
+        .enumerate()
+        .map(|(idx, cmd)| labels.get(idx).unwrap_or(cmd).clone())
+        .collect()
+}
+
+/// Interval between frames: that of the commands, or the shortest of the intervals of the
+/// commands with their own.
+fn frame_interval(opt: &Options, control: &Control) -> Duration {
+    opt.intervals
+        .iter()
+        .flatten()
+        .fold(control.interval(), |interval, &own| interval.min(own))
+}
+
+/// Report an alert that started firing, through the notifications, hooks and webhooks requested.
+fn report_fired(
+    opt: &Options,
+    alert: &Alert,
+    breach: &Breach,
+    cmd: &str,
+    ctx: &Context,
+    env: Vec<(&'static str, String)>,
+) {
+    if opt.notify {
+        notify::send(
+            &format!("dwatch: {}", alert.label()),
+            &format!("{}: {}", cmd, breach.describe(ctx)),
+        );
+    }
+    for hook in opt.on_alert.iter().chain(&alert.hook) {
+        let mut env = env.clone();
+        env.push(("DWATCH_ALERT", alert.title()));
+        env.push(("DWATCH_RULE", alert.rule.to_string()));
+        if let Some(severity) = &alert.severity {
+            env.push(("DWATCH_SEVERITY", severity.clone()));
+        }
+        env.push(("DWATCH_VALUE", breach.value.to_string()));
+        if let Some(rate) = breach.rate {
+            env.push(("DWATCH_RATE", rate.to_string()));
+        }
+        env.push(("DWATCH_COMMAND", cmd.to_owned()));
+        process::hook(hook, env);
+    }
+    if !opt.webhooks.is_empty() {
+        let text = format!(
+            "dwatch: {} firing, {}: {}",
+            alert.label(),
+            cmd,
+            breach.describe(ctx)
+        );
+        let mut payload = alert_payload(alert, "firing", text);
+        payload.push(("command", json::string(cmd)));
+        payload.push(("value", breach.value.to_string()));
+        if let Some(rate) = breach.rate {
+            payload.push(("rate", json::number(rate)));
+        }
+        send_webhooks(opt, json::object(payload));
+    }
+}
+
+/// Members of the JSON document posted to the webhooks when an alert changes state. `text` makes
+/// it a valid Slack message.
+fn alert_payload(alert: &Alert, state: &str, text: String) -> Vec<(&'static str, String)> {
+    let timestamp = unix_time();
+    let mut payload = vec![
+        ("text", json::string(&text)),
+        ("alert", json::string(&alert.title())),
+        ("rule", json::string(&alert.rule.to_string())),
+        ("state", json::string(state)),
+        ("timestamp", timestamp.as_secs().to_string()),
+    ];
+    if let Some(severity) = &alert.severity {
+        payload.push(("severity", json::string(severity)));
+    }
+    payload
+}
+
+/// Post a document to every `--webhook` in the background. Failures are ignored, the frames go on.
+fn send_webhooks(opt: &Options, json: String) {
+    let timeout = opt.timeout.filter(|t| !t.is_zero());
+    for url in opt.webhooks.clone() {
+        let json = json.clone();
+        std::thread::spawn(move || http::post_json(&url, &json, timeout));
+    }
+}
+
+/// Number of lines that differ between two outputs, compared line by line.
+fn changed_lines(old: &str, new: &str) -> usize {
+    old.lines()
+        .zip_longest(new.lines())
+        .filter(|pair| !matches!(pair, Both(a, b) if a == b))
+        .count()
+}
+
+/// A delay chosen uniformly at random in `[0, max)`.
+fn random_delay(max: Duration) -> Duration {
+    use std::hash::{BuildHasher, RandomState};
+    // the hasher keys are random for each instance
+    let r = RandomState::new().hash_one(std::process::id());
+    max.mul_f64((r >> 11) as f64 / (1u64 << 53) as f64)
+}
+
+/// Move a schedule slot past `now`, by whole intervals, returning the number of slots skipped.
+fn skip_missed(next: &mut Instant, interval: Duration, now: Instant) -> u64 {
+    if *next > now || interval.is_zero() {
+        return 0;
+    }
+    let missed = ((now - *next).as_nanos() / interval.as_nanos()) as u64 + 1;
+    *next += interval * missed as u32;
+    missed
+}
+
+fn write_banner(
+    out: &mut dyn Write,
+    opt: &Options,
+    control: &Control,
+    runners: &[Runner],
+    missed: u64,
+    firing: &[&Alert],
+    player: Option<&Player>,
+) -> Result<()> {
+    let every = if let Some(player) = player {
+        let (frame, frames) = player.position();
+        format!("Replay {}/{} x{}", frame, frames, control.speed())
+    } else if opt.follow || opt.stdin {
+        "Following".to_owned()
+    } else if opt.on_change {
+        "On change".to_owned()
+    } else if opt.step {
+        "Step (r: next)".to_owned()
+    } else {
+        format!("Every {} ms", control.interval().as_millis())
+    };
+    // each command (or its label) is followed by the outcome of its last run, a title by those of
+    // all of them
+    let commands = command_names(&opt.labels, &control.commands.lock().unwrap());
+    let outcomes = commands.iter().enumerate().map(|(idx, cmd)| {
+        let runner = runners.get(idx);
+        let runtime = runner
+            .and_then(|r| r.runtime_text(control.interval()))
+            .map(|text| format!(" {}", text))
+            .unwrap_or_default();
+        let outcome = runner
+            .and_then(|r| r.status.as_ref())
+            .map(|status| format!("[{}{}]", status, runtime));
+        (cmd, outcome)
+    });
+    let commands = match &opt.title {
+        Some(title) => std::iter::once(title.clone())
+            .chain(outcomes.filter_map(|(_, outcome)| outcome))
+            .join(" "),
+        None => outcomes
+            .map(|(cmd, outcome)| match outcome {
+                Some(outcome) => format!("{} {}", cmd, outcome),
+                None => cmd.clone(),
+            })
+            .join(" | "),
+    };
+    // the output of a failed run is not displayed with --keep-last, the reason is
+    let stale = runners
+        .iter()
+        .find_map(|r| r.stale.as_ref())
+        .map(|reason| format!(" [stale: {}]", reason))
+        .unwrap_or_default();
+    let missed = if missed > 0 {
+        format!(" [missed: {}]", missed)
+    } else {
+        String::new()
+    };
+    let alert = if firing.is_empty() {
+        String::new()
+    } else {
+        format!(
+            " [alert: {}]",
+            firing.iter().map(|alert| alert.label()).join(", ")
+        )
+    };
+    writeln!(
+        out,
+        "{}, delta[{}]: {}{}{}{}{}{}\n",
+        every,
+        control.style_name(),
+        commands,
+        if control.paused.load(Ordering::Relaxed) {
+            " [paused]"
+        } else {
+            ""
+        },
+        stale,
+        missed,
+        alert,
+        ansi_escapes::EraseEndLine
+    )?;
+    Ok(())
+}
+
+/// Render the header of the output of a command run elsewhere.
+fn write_section(
+    out: &mut dyn Write,
+    sel: &mut Selection,
+    target: &str,
+    runtime: Option<String>,
+    ctx: &Context,
+) -> Result<()> {
+    writeln!(
+        out,
+        "{}{}{}",
+        ctx.theme.section.paint(format!("[{}]", target)),
+        runtime.map(|r| format!(" {}", r)).unwrap_or_default(),
+        ansi_escapes::EraseEndLine
+    )?;
+    sel.row += 1;
+    Ok(())
+}
+
+/// Render the standard error of a command as is, in its own color.
+fn write_stderr(out: &mut dyn Write, sel: &mut Selection, text: &str, ctx: &Context) -> Result<()> {
+    for line in text.lines() {
+        writeln!(
+            out,
+            "{}{}",
+            ctx.theme.stderr.paint(line),
+            ansi_escapes::EraseEndLine
+        )?;
+        sel.row += 1;
+    }
+    Ok(())
+}
+
+fn writeln_line(
+    out: &mut dyn Write,
+    sel: &mut Selection,
+    line: &str,
+    lineno: u64,
+    lmap: &mut LineMap,
+    ctx: &Context,
+    update: bool,
+) -> Result<()> {
+    let ranges = numeric_ranges(line);
+    let strings = parse_strings(line, &ranges);
+    let numbers = parse_numbers(line, &ranges);
+    let key = (lineno, chunks_fingerprint(&strings));
+
+    // numbers without a previous sample have no meaningful delta yet
+    let fresh = lmap.get(&key).is_none_or(|l| l.num.len() != numbers.len());
+    if fresh && update && !lmap.is_empty() && verbose::enabled() {
+        let reason = match lmap.get(&key) {
+            Some(previous) => format!("{} numbers, {} before", numbers.len(), previous.num.len()),
+            None if lmap.keys().any(|&(n, _)| n == lineno) => {
+                "the text around its numbers changed".to_owned()
+            }
+            None => "new line".to_owned(),
+        };
+        verbose::event!(
+            "parse",
+            "line {} restarts its deltas ({}): {}",
+            lineno + 1,
+            reason,
+            line_key(line, &ranges)
+        );
+    }
+    let line_stat = lmap.entry(key).or_insert(LineNumbers::new(numbers.clone()));
+
+    let stat = {
+        if !update && !fresh {
+            line_stat.clone()
+        } else if numbers.len() == line_stat.num.len() {
+            let mut deltas = Vec::with_capacity(numbers.len());
+
+            for (a, b) in numbers.iter().zip(line_stat.num.iter()) {
+                deltas.push(a - b);
+            }
+            line_stat.num = numbers.clone();
+            line_stat.delta = deltas;
+
+            for (min, max, value) in
+                multizip((&mut line_stat.min, &mut line_stat.max, &line_stat.delta))
+            {
+                *min = std::cmp::min(*min, *value);
+                *max = std::cmp::max(*max, *value);
+            }
+
+            line_stat.clone()
+        } else {
+            line_stat.num = numbers.clone();
+            line_stat.delta = vec![0; numbers.len()];
+            line_stat.min = vec![0; numbers.len()];
+            line_stat.max = vec![0; numbers.len()];
+            line_stat.clone()
+        }
+    };
+
+    let key = sel.records.is_some().then(|| line_key(line, &ranges));
+    for (column, (&value, &delta)) in stat.num.iter().zip(&stat.delta).enumerate() {
+        let change = (!fresh).then(|| (delta, ctx.rate(delta as f64)));
+        if let (Some(records), Some(key)) = (sel.records.as_mut(), &key) {
+            records.push(Record {
+                ordinal: sel.ordinal + column,
+                line: lineno,
+                key: key.clone(),
+                index: column,
+                value,
+                change,
+            });
+        }
+        sel.changed |= !fresh && update && delta != 0;
+        sel.triggered |= sel.exit_if.iter().any(|c| c.holds(value, change));
+        for (idx, alert) in sel.alerts.iter().enumerate() {
+            if alert.rule.holds(column, value, change) {
+                let ordinal = sel.ordinal + column;
+                // the first alert breached styles the value
+                sel.alerting.entry(ordinal).or_insert(idx);
+                sel.firing.entry(idx).or_insert(Breach {
+                    ordinal,
+                    value,
+                    rate: change.map(|(_, rate)| rate),
+                });
+            }
+        }
+    }
+
+    writeln_data(out, sel, &strings, &stat, &ranges, ctx)
+}
+
+fn writeln_data(
+    out: &mut dyn Write,
+    sel: &mut Selection,
+    strings: &[&str],
+    stat: &LineNumbers,
+    ranges: &[Range<usize>],
+    ctx: &Context,
+) -> Result<()> {
+    let s = strings.iter();
+    let line_start = sel.ordinal;
+    let first_is_number = !ranges.is_empty() && ranges[0].start == 0;
+
+    for chunk in izip!(&stat.num, &stat.delta, &stat.min, &stat.max).zip_longest(s) {
+        match chunk {
+            Both(numbers, string) => {
+                if first_is_number {
+                    write_number(out, sel, numbers, ctx)?;
+                    write_string(out, sel, string)?;
+                } else {
+                    write_string(out, sel, string)?;
+                    write_number(out, sel, numbers, ctx)?;
+                }
+            }
+            Left(numbers) => {
+                write_number(out, sel, numbers, ctx)?;
+            }
+            Right(string) => {
+                write_string(out, sel, string)?;
+            }
+        }
+    }
+
+    if sel.ordinal > line_start {
+        sel.lines.push(line_start..sel.ordinal);
+    }
+    sel.row += 1;
+    sel.col = 0;
+
+    writeln!(out, "{}", ansi_escapes::EraseEndLine)?;
+    Ok(())
+}
+
+/// Writers used for the values of a frame, according to the global style and the focus.
+struct Selection<'a> {
+    style: usize,
+    focus: Focus,
+    /// values rendered highlighted
+    targets: BTreeSet<usize>,
+    /// ordinal of the next value to render
+    ordinal: usize,
+    /// ordinals of the values of each line
+    lines: Vec<Range<usize>>,
+    focused: &'a Context,
+    /// screen position of the next chunk, used to map mouse clicks back to values
+    row: usize,
+    col: usize,
+    hits: Vec<Hit>,
+    /// conditions checked on every number (--exit-if)
+    exit_if: &'a [Condition],
+    /// some condition held for a number of the frame
+    triggered: bool,
+    /// alert rules checked on every number (--alert)
+    alerts: &'a [Alert],
+    /// variant of the context used for the values breaching an alert
+    alerted: &'a Context,
+    /// styles of the values breaching each alert
+    alert_styles: &'a [Style],
+    /// values breaching an alert, with the alert styling them
+    alerting: BTreeMap<usize, usize>,
+    /// alerts breached by some number of the frame, by index, with the first number breaching them
+    firing: BTreeMap<usize, Breach>,
+    /// some number changed since the previous sample
+    changed: bool,
+    /// the numbers of the frame, for --output, --log-csv and the metrics sinks
+    records: Option<Vec<Record>>,
+}
+
+/// A number of a frame, as exported by `--output ndjson`, `--log-csv` and the metrics sinks.
+#[derive(Clone)]
+struct Record {
+    ordinal: usize,
+    line: u64,
+    /// the line, with its numbers replaced by `#`
+    key: String,
+    /// position of the number in its line
+    index: usize,
+    value: i64,
+    change: Option<(i64, f64)>,
+}
+
+/// Replace the numbers of a line with `#`, so that its samples share a key.
+fn line_key(line: &str, ranges: &[Range<usize>]) -> String {
+    let mut key = String::with_capacity(line.len());
+    let mut last = 0;
+    for range in ranges {
+        key.push_str(&line[last..range.start]);
+        key.push('#');
+        last = range.end;
+    }
+    key.push_str(&line[last..]);
+    key
+}
+
+/// Command a number of a frame was output by.
+fn source<'a>(record: &Record, sources: &[Range<usize>], commands: &'a [String]) -> &'a str {
+    sources
+        .iter()
+        .position(|r| r.contains(&record.ordinal))
+        .map_or("", |i| commands[i].as_str())
+}
+
+/// Open a file the frames are appended to, creating it if needed.
+fn open_append(path: &std::path::Path) -> Result<std::fs::File> {
+    std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("cannot open {}", path.display()))
+}
+
+/// Time elapsed since the Unix epoch, to timestamp exported frames.
+fn unix_time() -> Duration {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+}
+
+/// Statistics of a number over the session, for `--summary-json`.
+struct Stats {
+    /// key of the line in the last frame
+    key: String,
+    samples: u64,
+    min: i64,
+    max: i64,
+    sum: i128,
+    last: i64,
+    /// sum of the deltas
+    total_delta: i64,
+}
+
+/// End of run statistics of the numbers, and number of times each alert fired.
+struct Summary {
+    numbers: BTreeMap<NumberKey, Stats>,
+    fired: Vec<u64>,
+}
+
+impl Summary {
+    fn new(alerts: usize) -> Self {
+        Self {
+            numbers: BTreeMap::new(),
+            fired: vec![0; alerts],
+        }
+    }
+
+    fn push(&mut self, frame: &[Record], sources: &[Range<usize>], commands: &[String]) {
+        for record in frame {
+            let key = (
+                source(record, sources, commands).to_owned(),
+                record.line,
+                record.index,
+            );
+            let stats = self.numbers.entry(key).or_insert(Stats {
+                key: String::new(),
+                samples: 0,
+                min: record.value,
+                max: record.value,
+                sum: 0,
+                last: 0,
+                total_delta: 0,
+            });
+            stats.key.clone_from(&record.key);
+            stats.samples += 1;
+            stats.min = stats.min.min(record.value);
+            stats.max = stats.max.max(record.value);
+            stats.sum += i128::from(record.value);
+            stats.last = record.value;
+            stats.total_delta += record.change.map_or(0, |(delta, _)| delta);
+        }
+    }
+
+    fn to_json(&self, iterations: u64, elapsed: Duration, alerts: &[Alert]) -> String {
+        let alerts = alerts.iter().zip(&self.fired).map(|(alert, fired)| {
+            json::object([
+                ("alert", json::string(&alert.title())),
+                ("rule", json::string(&alert.rule.to_string())),
+                ("fired", fired.to_string()),
+            ])
+        });
+        let values = self.numbers.iter().map(|((cmd, line, index), stats)| {
+            json::object([
+                ("command", json::string(cmd)),
+                ("line", line.to_string()),
+                ("line_key", json::string(&stats.key)),
+                ("index", index.to_string()),
+                ("samples", stats.samples.to_string()),
+                ("min", stats.min.to_string()),
+                ("max", stats.max.to_string()),
+                ("avg", json::number(stats.sum as f64 / stats.samples as f64)),
+                ("last", stats.last.to_string()),
+                ("total_delta", stats.total_delta.to_string()),
+            ])
+        });
+        json::object([
+            ("iterations", iterations.to_string()),
+            ("duration", json::number(elapsed.as_secs_f64())),
+            ("alerts", json::array(alerts)),
+            ("values", json::array(values)),
+        ])
+    }
+}
+
+/// Local date and time, to the millisecond, e.g. `2024-05-01 12:30:05.250`.
+fn local_time(time: std::time::SystemTime) -> String {
+    let since_epoch = time
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default();
+    let secs = since_epoch.as_secs() as libc::time_t;
+    // SAFETY: localtime_r only writes the tm structure passed in.
+    let tm = unsafe {
+        let mut tm: libc::tm = std::mem::zeroed();
+        libc::localtime_r(&secs, &mut tm);
+        tm
+    };
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}.{:03}",
+        tm.tm_year + 1900,
+        tm.tm_mon + 1,
+        tm.tm_mday,
+        tm.tm_hour,
+        tm.tm_min,
+        tm.tm_sec,
+        since_epoch.subsec_millis()
+    )
+}
+
+/// Terminal output of `--no-clear`: the frames are printed one below the other as they are
+/// flushed, without the sequences erasing the ends of lines, so that they read well in logs.
+struct Log<W: Write> {
+    frame: Vec<u8>,
+    out: W,
+}
+
+impl<W: Write> Log<W> {
+    fn new(out: W) -> Self {
+        Self {
+            frame: Vec::new(),
+            out,
+        }
+    }
+}
+
+impl<W: Write> Write for Log<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.frame.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        let erase = ansi_escapes::EraseEndLine.to_string();
+        let frame = String::from_utf8_lossy(&self.frame).replace(&erase, "");
+        self.frame.clear();
+        self.out.write_all(frame.as_bytes())?;
+        self.out.flush()
+    }
+}
+
+/// Columns of the `--log-csv` files.
+const CSV_HEADER: &str = "timestamp,command,line,line_key,index,value,delta,rate";
+
+/// Open a `--log-csv` file for appending, writing the header first if the file is new.
+fn open_csv(path: &std::path::Path) -> Result<std::fs::File> {
+    let mut file = open_append(path)?;
+    if file.metadata()?.len() == 0 {
+        writeln!(file, "{}", CSV_HEADER)?;
+    }
+    Ok(file)
+}
+
+/// Quote a CSV field if needed.
+fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_owned()
+    }
+}
+
+/// Append the numbers of a frame to a CSV file, one row per number (unknown deltas and rates are
+/// left empty).
+fn write_csv(
+    out: &mut dyn Write,
+    frame: &[Record],
+    sources: &[Range<usize>],
+    commands: &[String],
+    timestamp: Duration,
+) -> Result<()> {
+    let mut rows = String::new();
+    for record in frame {
+        rows.push_str(&csv_row(
+            timestamp,
+            source(record, sources, commands),
+            record,
+        ));
+    }
+    // a frame is appended at once
+    out.write_all(rows.as_bytes())?;
+    Ok(())
+}
+
+/// A number as a CSV row, see `CSV_HEADER`.
+fn csv_row(timestamp: Duration, cmd: &str, record: &Record) -> String {
+    let (delta, rate) = match record.change {
+        Some((delta, rate)) => (delta.to_string(), rate.to_string()),
+        None => (String::new(), String::new()),
+    };
+    format!(
+        "{:.3},{},{},{},{},{},{},{}\n",
+        timestamp.as_secs_f64(),
+        csv_field(cmd),
+        record.line,
+        csv_field(&record.key),
+        record.index,
+        record.value,
+        delta,
+        rate
+    )
+}
+
+/// Number of samples of each number kept for `--history-csv`.
+const HISTORY_LEN: usize = 1000;
+
+/// A number identified across frames: its command, line and position in the line.
+type NumberKey = (String, u64, usize);
+
+/// Recent samples of the numbers of the frames, and which of them were focused or selected during
+/// the session, for `--history-csv`.
+#[derive(Default)]
+struct History {
+    samples: HashMap<NumberKey, VecDeque<(Duration, Record)>>,
+    watched: BTreeSet<NumberKey>,
+}
+
+impl History {
+    fn push(
+        &mut self,
+        timestamp: Duration,
+        frame: &[Record],
+        sources: &[Range<usize>],
+        commands: &[String],
+        targets: &BTreeSet<usize>,
+    ) {
+        for record in frame {
+            let key = (
+                source(record, sources, commands).to_owned(),
+                record.line,
+                record.index,
+            );
+            if targets.contains(&record.ordinal) {
+                self.watched.insert(key.clone());
+            }
+            let samples = self.samples.entry(key).or_default();
+            if samples.len() == HISTORY_LEN {
+                samples.pop_front();
+            }
+            samples.push_back((timestamp, record.clone()));
+        }
+    }
+
+    /// Write the samples of the numbers focused or selected at some point as CSV, in time order.
+    fn write(&self, out: &mut dyn Write) -> Result<()> {
+        let mut rows: Vec<_> = self
+            .watched
+            .iter()
+            .flat_map(|key| {
+                self.samples[key]
+                    .iter()
+                    .map(move |(timestamp, record)| (timestamp, key, record))
+            })
+            .collect();
+        rows.sort_by_key(|&(timestamp, key, _)| (*timestamp, key));
+        writeln!(out, "{}", CSV_HEADER)?;
+        for (&timestamp, (cmd, _, _), record) in rows {
+            out.write_all(csv_row(timestamp, cmd, record).as_bytes())?;
+        }
+        Ok(())
+    }
+}
+
+/// Metrics of a frame in the Prometheus text format: the values, deltas and rates of its numbers,
+/// labelled with their command, line (and its key) and column (from 1, as in alert rules).
+fn exposition(frame: &[Record], sources: &[Range<usize>], commands: &[String]) -> String {
+    let families = [
+        ("value", "Numbers output by the watched commands"),
+        ("delta", "Change of the numbers since the previous frame"),
+        (
+            "rate",
+            "Change of the numbers per rate unit (second by default)",
+        ),
+    ];
+    let mut text = String::new();
+    for (name, help) in families {
+        text.push_str(&format!(
+            "# HELP dwatch_{name} {help}.\n# TYPE dwatch_{name} gauge\n"
+        ));
+        for record in frame {
+            let sample = match (name, record.change) {
+                ("value", _) => record.value.to_string(),
+                ("delta", Some((delta, _))) => delta.to_string(),
+                ("rate", Some((_, rate))) => rate.to_string(),
+                _ => continue,
+            };
+            text.push_str(&format!(
+                "dwatch_{}{{command=\"{}\",line=\"{}\",line_key=\"{}\",column=\"{}\"}} {}\n",
+                name,
+                prometheus::label(source(record, sources, commands)),
+                record.line,
+                prometheus::label(&record.key),
+                record.index + 1,
+                sample
+            ));
+        }
+    }
+    text
+}
+
+/// Name of a number: the text preceding it in its line (`rx 10, tx 20` gives `rx` and `tx`), or
+/// the start of its line and its column when that is blank (`eth0: 1 2` gives `eth0` and
+/// `eth0.2`), or else its line and column numbers (`line3.2`).
+fn number_name(record: &Record) -> String {
+    // the text before each number of the line
+    let parts: Vec<_> = record.key.split('#').map(statsd::name).collect();
+    match parts.get(record.index).filter(|text| !text.is_empty()) {
+        Some(text) => text.clone(),
+        None if !parts[0].is_empty() => format!("{}.{}", parts[0], record.index + 1),
+        None => format!("line{}.{}", record.line + 1, record.index + 1),
+    }
+}
+
+/// StatsD metrics of a frame: the values as gauges and the deltas as counters, named after the
+/// command reduced to a single path component (or its label) and the numbers (`dwatch.ip.rx`),
+/// so that those of different commands do not overwrite each other.
+fn statsd_metrics(frame: &[Record], sources: &[Range<usize>], commands: &[String]) -> Vec<String> {
+    let mut metrics = Vec::with_capacity(frame.len() * 2);
+    for record in frame {
+        let command = statsd::name(source(record, sources, commands));
+        let label = number_name(record);
+        metrics.push(format!("dwatch.{}.{}:{}|g", command, label, record.value));
+        if let Some((delta, _)) = record.change {
+            metrics.push(format!("dwatch.{}.{}:{}|c", command, label, delta));
+        }
+    }
+    metrics
+}
+
+/// A frame as a line of `--ticker`: the time, then the numbers selected by name or key (every one
+/// that changed when none is), with the direction and size of their change and their rate, e.g.
+/// `12:00:05 rx 1234 ↑12 6.00/s | tx 88`.
+fn ticker_line(
+    frame: &[Record],
+    sources: &[Range<usize>],
+    selected: &[String],
+    ctx: &Context,
+    time: &str,
+) -> String {
+    let items = frame.iter().filter_map(|record| {
+        let name = number_name(record);
+        let change = record.change.filter(|(delta, _)| *delta != 0);
+        let shown = if selected.is_empty() {
+            change.is_some()
+        } else {
+            selected
+                .iter()
+                .any(|s| *s == name || *s == value_key(record, sources))
+        };
+        if !shown {
+            return None;
+        }
+        let mut item = format!(
+            "{} {}",
+            name,
+            ctx.theme.value.paint(ctx.format_value(record.value))
+        );
+        if let Some((delta, rate)) = change {
+            let arrow = if delta > 0 { '\u{2191}' } else { '\u{2193}' };
+            let rate = format_number(rate.abs(), Unit::None, ctx.precision, RateUnit::S);
+            item += &format!(
+                " {} {}",
+                ctx.theme
+                    .delta
+                    .paint(format!("{}{}", arrow, delta.unsigned_abs())),
+                ctx.theme
+                    .rate
+                    .paint(format!("{}/{}", rate, ctx.rate_unit.suffix()))
+            );
+        }
+        Some(item)
+    });
+    let items = items.collect_vec();
+    if items.is_empty() {
+        format!("{} -", time)
+    } else {
+        format!("{} {}", time, items.join(" | "))
+    }
+}
+
+/// A frame in the Graphite plaintext protocol: a `prefix.command.lineL.colN value timestamp`
+/// line per number, with lines and columns counted from 1 and the command reduced to a single
+/// path component.
+fn graphite_lines(
+    frame: &[Record],
+    sources: &[Range<usize>],
+    commands: &[String],
+    prefix: &str,
+    timestamp: Duration,
+) -> String {
+    let mut lines = String::new();
+    for record in frame {
+        lines.push_str(&format!(
+            "{}.{}.line{}.col{} {} {}\n",
+            prefix,
+            statsd::name(source(record, sources, commands)),
+            record.line + 1,
+            record.index + 1,
+            record.value,
+            timestamp.as_secs()
+        ));
+    }
+    lines
+}
+
+/// Escape a tag value or a measurement of the Influx line protocol.
+fn influx_tag(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            ',' | '=' | ' ' | '\\' => out.extend(['\\', c]),
+            '\n' | '\r' => out.push(' '),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// A frame in the Influx line protocol: a `dwatch` point per number, tagged with its command, line
+/// (and its key) and column (from 1), with its value, delta and rate as fields.
+fn line_protocol(
+    frame: &[Record],
+    sources: &[Range<usize>],
+    commands: &[String],
+    timestamp: Duration,
+) -> String {
+    let mut lines = String::new();
+    for record in frame {
+        let mut fields = format!("value={}i", record.value);
+        if let Some((delta, rate)) = record.change {
+            fields.push_str(&format!(",delta={}i", delta));
+            if rate.is_finite() {
+                fields.push_str(&format!(",rate={}", rate));
+            }
+        }
+        lines.push_str(&format!(
+            "dwatch,command={},line={},line_key={},column={} {} {}\n",
+            influx_tag(source(record, sources, commands)),
+            record.line,
+            influx_tag(&record.key),
+            record.index + 1,
+            fields,
+            timestamp.as_nanos()
+        ));
+    }
+    lines
+}
+
+/// Post the lines of a frame to an Influx write endpoint in the background, failures are ignored.
+fn post_influx(opt: &Options, url: &str, lines: String) {
+    let timeout = opt.timeout.filter(|t| !t.is_zero());
+    let url = url.to_owned();
+    std::thread::spawn(move || http::post(&url, "text/plain", lines.as_bytes(), &[], timeout));
+}
+
+/// Render the `--remote-write-template` of a number, whose placeholders are replaced with its
+/// attributes (`{command}` and `{line_key}` escaped as in JSON strings).
+fn render_template(template: &str, record: &Record, cmd: &str, timestamp: Duration) -> String {
+    let escaped = |s: &str| {
+        let quoted = json::string(s);
+        quoted[1..quoted.len() - 1].to_owned()
+    };
+    let (delta, rate) = match record.change {
+        Some((delta, rate)) => (delta.to_string(), json::number(rate)),
+        None => ("null".to_owned(), "null".to_owned()),
+    };
+    [
+        ("{command}", escaped(cmd)),
+        ("{line_key}", escaped(&record.key)),
+        ("{line}", record.line.to_string()),
+        ("{index}", record.index.to_string()),
+        ("{column}", (record.index + 1).to_string()),
+        ("{value}", record.value.to_string()),
+        ("{delta}", delta),
+        ("{rate}", rate),
+        ("{timestamp}", timestamp.as_secs().to_string()),
+        ("{timestamp_ms}", timestamp.as_millis().to_string()),
+    ]
+    .iter()
+    .fold(template.to_owned(), |text, (name, value)| {
+        text.replace(name, value)
+    })
+}
+
+/// Post the items of a frame to the `--remote-write` endpoint in the background, failures are
+/// ignored.
+fn remote_write(opt: &Options, url: &str, body: String) {
+    let timeout = opt.timeout.filter(|t| !t.is_zero());
+    let content_type = if opt.remote_write_template.is_some() {
+        "text/plain"
+    } else {
+        "application/x-ndjson"
+    };
+    let url = url.to_owned();
+    let headers = opt.remote_write_headers.clone();
+    std::thread::spawn(move || http::post(&url, content_type, body.as_bytes(), &headers, timeout));
+}
+
+/// Write the numbers of a frame as JSON records, one per line.
+fn write_records(
+    out: &mut dyn Write,
+    frame: &[Record],
+    sources: &[Range<usize>],
+    commands: &[String],
+    timestamp: Duration,
+) -> Result<()> {
+    for record in frame {
+        let cmd = source(record, sources, commands);
+        writeln!(out, "{}", record_json(record, cmd, timestamp))?;
+    }
+    out.flush()?;
+    Ok(())
+}
+
+/// Key of a number in the `--serve` API, `COMMAND.LINE.INDEX` (from 0).
+fn value_key(record: &Record, sources: &[Range<usize>]) -> String {
+    let cmd = sources
+        .iter()
+        .position(|r| r.contains(&record.ordinal))
+        .unwrap_or_default();
+    format!("{}.{}.{}", cmd, record.line, record.index)
+}
+
+/// A frame, for the `--serve` dashboard and API: the output of the commands, and their numbers
+/// as in `record_json` along with their key.
+fn dashboard_update<'a>(
+    frame: &[Record],
+    sources: &[Range<usize>],
+    commands: &[String],
+    outputs: impl Iterator<Item = &'a str>,
+    timestamp: Duration,
+) -> web::Update {
+    let samples: Vec<(String, i64)> = frame
+        .iter()
+        .map(|r| (value_key(r, sources), r.value))
+        .collect();
+    let values = json::array(frame.iter().zip(&samples).map(|(r, (key, _))| {
+        let mut members = vec![("key", json::string(key))];
+        members.extend(record_members(r, source(r, sources, commands), timestamp));
+        json::object(members)
+    }));
+    let outputs = commands.iter().zip(outputs).map(|(cmd, output)| {
+        json::object([
+            ("command", json::string(cmd)),
+            ("output", json::string(output)),
+        ])
+    });
+    let time = json::number(timestamp.as_millis() as f64 / 1000.0);
+    web::Update {
+        frame: json::object([
+            ("timestamp", time.clone()),
+            ("commands", json::array(outputs)),
+            ("values", values.clone()),
+        ]),
+        values: json::object([("timestamp", time), ("values", values)]),
+        samples,
+        timestamp,
+    }
+}
+
+/// A frame as a `Frame` message of `proto/dwatch.proto`, for `--grpc`.
+fn frame_message<'a>(
+    frame: &[Record],
+    sources: &[Range<usize>],
+    commands: &[String],
+    outputs: impl Iterator<Item = &'a str>,
+    timestamp: Duration,
+) -> Vec<u8> {
+    let mut message = Message::new().uint(1, timestamp.as_millis() as u64);
+    for (cmd, output) in commands.iter().zip(outputs) {
+        message = message.message(2, Message::new().string(1, cmd).string(2, output));
+    }
+    for record in frame {
+        let mut value = Message::new()
+            .string(1, &value_key(record, sources))
+            .string(2, source(record, sources, commands))
+            .string(3, &record.key)
+            .uint(4, record.line)
+            .uint(5, record.index as u64)
+            .sint(6, record.value);
+        if let Some((delta, rate)) = record.change {
+            value = value.sint(7, delta).double(8, rate);
+        }
+        message = message.message(3, value);
+    }
+    message.into_bytes()
+}
+
+/// The alerts and whether they are firing, for the `--serve` API.
+fn alerts_json(alerts: &[Alert], states: &[alert::State]) -> String {
+    json::array(alerts.iter().zip(states).map(|(alert, state)| {
+        let mut members = vec![
+            ("alert", json::string(&alert.title())),
+            ("rule", json::string(&alert.rule.to_string())),
+            ("active", state.active.to_string()),
+        ];
+        if let Some(severity) = &alert.severity {
+            members.push(("severity", json::string(severity)));
+        }
+        json::object(members)
+    }))
+}
+
+/// A number of a frame as a JSON object, output by `--output ndjson` and published by `--mqtt`.
+fn record_json(record: &Record, cmd: &str, timestamp: Duration) -> String {
+    json::object(record_members(record, cmd, timestamp))
+}
+
+fn record_members(record: &Record, cmd: &str, timestamp: Duration) -> Vec<(&'static str, String)> {
+    let (delta, rate) = match record.change {
+        Some((delta, rate)) => (delta.to_string(), json::number(rate)),
+        None => ("null".to_owned(), "null".to_owned()),
+    };
+    vec![
+        (
+            "timestamp",
+            json::number(timestamp.as_millis() as f64 / 1000.0),
+        ),
+        ("command", json::string(cmd)),
+        ("line_key", json::string(&record.key)),
+        ("line", record.line.to_string()),
+        ("index", record.index.to_string()),
+        ("value", record.value.to_string()),
+        ("delta", delta),
+        ("rate", rate),
+    ]
+}
+
+/// A number breaching an alert rule.
+struct Breach {
+    ordinal: usize,
+    value: i64,
+    rate: Option<f64>,
+}
+
+impl Breach {
+    fn describe(&self, ctx: &Context) -> String {
+        match self.rate {
+            Some(rate) => format!(
+                "value {}, rate {}",
+                self.value,
+                format_number(rate, Unit::None, ctx.precision, ctx.rate_unit)
+            ),
+            None => format!("value {}", self.value),
+        }
+    }
+}
+
+/// Number of terminal cells taken by a string, ignoring ANSI escape sequences.
+fn visible_width(s: &str, start_col: usize) -> usize {
+    let mut col = start_col;
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\x1b' => {
+                if chars.next() == Some('[') {
+                    for c in chars.by_ref() {
+                        if ('\x40'..='\x7e').contains(&c) {
+                            break;
+                        }
+                    }
+                }
+            }
+            '\t' => col = (col / 8 + 1) * 8,
+            _ => col += 1,
+        }
+    }
+    col - start_col
+}
+
+fn write_string(out: &mut dyn Write, sel: &mut Selection, string: &str) -> Result<()> {
+    sel.col += visible_width(string, sel.col);
+    write!(out, "{}", string)?;
+    Ok(())
+}
+
+fn write_number(
+    out: &mut dyn Write,
+    sel: &mut Selection,
+    numbers: (&i64, &i64, &i64, &i64),
+    ctx: &Context,
+) -> Result<()> {
+    let ordinal = sel.ordinal;
+    sel.ordinal += 1;
+
+    let writer = &WRITERS[sel.focus.style_of(ordinal, sel.style) % WRITERS.len()];
+    let mut buf = Vec::new();
+
+    if let Some(&alert) = sel
+        .alerting
+        .get(&ordinal)
+        .filter(|_| !sel.targets.contains(&ordinal))
+    {
+        if ctx.symbols {
+            write!(buf, "!")?;
+            (writer.write)(&mut buf, numbers, ctx)?;
+        } else {
+            // the whole value stands out, whatever the writer
+            let mut plain = Vec::new();
+            (writer.write)(&mut plain, numbers, sel.alerted)?;
+            let text = String::from_utf8_lossy(&plain).into_owned();
+            write!(buf, "{}", sel.alert_styles[alert].paint(text))?;
+        }
+    } else if !sel.targets.contains(&ordinal) {
+        (writer.write)(&mut buf, numbers, ctx)?;
+    } else {
+        if ctx.symbols {
+            write!(buf, ">")?;
+        }
+        (writer.write)(&mut buf, numbers, sel.focused)?;
+        if ctx.symbols {
+            write!(buf, "<")?;
+        }
+    }
+
+    let rendered = String::from_utf8_lossy(&buf);
+    let start = sel.col;
+    sel.col += visible_width(&rendered, start);
+    sel.hits.push(Hit {
+        row: sel.row,
+        cols: start..sel.col,
+        ordinal,
+    });
+
+    out.write_all(&buf)?;
+    Ok(())
+}
+
+/// Variables describing the frame being rendered, exported to the commands so that they can adapt
+/// their output. `LINES` counts the rows left below the banner.
+fn child_env(opt: &Options, iteration: u64, interval: Duration) -> Vec<(&'static str, String)> {
+    let size = pty::window_size();
+    let banner = if opt.no_banner { 0 } else { 2 };
+    let timestamp = unix_time();
+
+    vec![
+        ("DWATCH_ITERATION", iteration.to_string()),
+        ("DWATCH_INTERVAL", interval.as_secs_f64().to_string()),
+        ("DWATCH_TIMESTAMP", timestamp.as_secs().to_string()),
+        ("COLUMNS", size.ws_col.to_string()),
+        ("LINES", size.ws_row.saturating_sub(banner).to_string()),
+    ]
+}
+
+/// Build the command to run for a command line, through a shell unless `--exec`.
+fn build_command(
+    cmd: &str,
+    opt: &Options,
+    env: &[(&'static str, String)],
+) -> Result<impl FnMut() -> std::process::Command + Send + 'static> {
+    let argv = if opt.exec {
+        shell::split(cmd)
+    } else {
+        vec!["sh".to_owned(), "-c".to_owned(), cmd.to_owned()]
+    };
+    if argv.is_empty() {
+        return Err(anyhow!("empty command"));
+    }
+    let env = env.to_vec();
+    Ok(move || {
+        let mut command = std::process::Command::new(&argv[0]);
+        command
+            .args(&argv[1..])
+            .envs(env.iter().map(|(k, v)| (k, v)));
+        command
+    })
+}
+
+/// Start a run of the command in the background.
+fn start_command(cmd: &str, opt: &Options, env: &[(&'static str, String)]) -> Result<Job> {
+    let make = build_command(cmd, opt, env)?;
+    let timeout = opt.timeout.filter(|t| !t.is_zero());
+    Job::start(make, opt.pty, timeout, opt.retry.unwrap_or_default())
+        .with_context(|| format!("failed to execute {}", cmd))
+}
+
+/// Path of the file read by a `< PATH` command, which is not run but read by dwatch itself
+/// (`--file`).
+pub fn file_source(cmd: &str) -> Option<&str> {
+    cmd.strip_prefix('<').map(str::trim)
+}
+
+/// Command standing for the standard input of dwatch (`--stdin`).
+pub const STDIN: &str = "-";
+
+/// Whether the command is a source read by dwatch itself (a file, a URL or the standard input)
+/// rather than run.
+fn is_builtin(cmd: &str) -> bool {
+    file_source(cmd).is_some() || http::is_url(cmd) || cmd == STDIN
+}
+
+/// Content of a file, as the output of a successful command.
+fn read_file(path: &str) -> Result<Output> {
+    Ok(Output {
+        status: ExitStatus::from_raw(0),
+        stdout: std::fs::read(path).with_context(|| format!("failed to read {}", path))?,
+        stderr: Vec::new(),
+    })
+}
+
+/// Start a command for good (or read the standard input), see `--follow`.
+fn follow_command(
+    cmd: &str,
+    opt: &Options,
+    env: &[(&'static str, String)],
+    notify: impl Fn() + Send + 'static,
+) -> Result<(Job, Feed)> {
+    if cmd == STDIN {
+        return Job::stdin(opt.record_separator.clone(), FOLLOW_GAP, notify)
+            .context("failed to read the standard input");
+    }
+    let mut make = build_command(cmd, opt, env)?;
+    Job::follow(&mut make(), opt.pty, FOLLOW_GAP, notify)
+        .with_context(|| format!("failed to execute {}", cmd))
+}
+
+/// Text displayed for the standard output of a command.
+fn stdout_text(stdout: &[u8], opt: &Options) -> String {
+    let stdout = String::from_utf8_lossy(stdout);
+    let text = if opt.pty {
+        pty::strip_escapes(&stdout)
+    } else {
+        stdout.into_owned()
+    };
+    match &opt.pipe {
+        Some(cmd) => match process::filter(cmd, text.as_bytes()) {
+            Ok(filtered) => String::from_utf8_lossy(&filtered).into_owned(),
+            Err(e) => format!("dwatch: failed to run {}: {:#}\n", cmd, e),
+        },
+        None => text,
+    }
+}
+
+/// Text displayed for a run of a command: its output, or the reason it could not be run.
+fn output_text(output: &Result<Output>, opt: &Options) -> String {
+    match output {
+        Ok(output) => stdout_text(&output.stdout, opt),
+        Err(e) => format!("dwatch: {:#}\n", e),
+    }
+}
+
+/// Short form of an exit status: `✓`, `✗ <code>` or `✗ sig <signal>`.
+fn status_mark(status: ExitStatus) -> String {
+    match (status.code(), status.signal()) {
+        (Some(0), _) => "✓".to_owned(),
+        (Some(code), _) => format!("✗ {}", code),
+        (None, Some(signal)) => format!("✗ sig {}", signal),
+        (None, None) => "✗".to_owned(),
+    }
+}
+
+/// Runs of a command in flight (oldest first), and the output of the last one completed.
+#[derive(Default)]
+struct Runner {
+    jobs: VecDeque<Job>,
+    /// Blocks output by the command, when followed.
+    feed: Option<Feed>,
+    /// The output changed since the last frame.
+    fresh: bool,
+    /// Numbers of the last output, by line, to compute the deltas of the next one.
+    line_map: LineMap,
+    output: String,
+    stderr: String,
+    /// Some run completed successfully.
+    succeeded: bool,
+    /// Why the last run failed, when its output was discarded in favour of the previous one.
+    stale: Option<String>,
+    /// Outcome of the last run, e.g. `✓` or `✗ 2`.
+    status: Option<String>,
+    /// How long the last run took.
+    runtime: Option<Duration>,
+    /// Exit code of the last run (as a shell reports it, 128 + signal when killed, 1 when it
+    /// could not be run at all).
+    exit_code: Option<u8>,
+    /// When the command is next run, given its own interval.
+    due: Option<Instant>,
+    /// Not run this frame, as not due.
+    idle: bool,
+    /// When the command was last run, given its own interval, and the time since the run before,
+    /// over which its rates are computed.
+    last_run: Option<Instant>,
+    elapsed: Option<Duration>,
+}
+
+impl Runner {
+    fn start(&mut self, cmd: &str, opt: &Options, env: &[(&'static str, String)]) {
+        if let Some(path) = file_source(cmd) {
+            return self.complete(read_file(path), opt);
+        }
+        if http::is_url(cmd) {
+            let url = cmd.to_owned();
+            let timeout = opt.timeout.filter(|t| !t.is_zero());
+            self.jobs.push_back(Job::call(move || {
+                Ok(Output {
+                    status: ExitStatus::from_raw(0),
+                    stdout: http::get(&url, timeout)?,
+                    stderr: Vec::new(),
+                })
+            }));
+            return;
+        }
+        match start_command(cmd, opt, env) {
+            Ok(job) => self.jobs.push_back(job),
+            Err(e) => self.complete(Err(e), opt),
+        }
+    }
+
+    /// Start following the command, `notify` is called whenever it outputs a new block.
+    fn follow(
+        &mut self,
+        cmd: &str,
+        opt: &Options,
+        env: &[(&'static str, String)],
+        notify: impl Fn() + Send + 'static,
+    ) {
+        match follow_command(cmd, opt, env, notify) {
+            Ok((job, feed)) => {
+                self.jobs.push_back(job);
+                self.feed = Some(feed);
+            }
+            Err(e) => {
+                self.feed = Some(Feed::default());
+                self.complete(Err(e), opt);
+            }
+        }
+    }
+
+    /// Show the last block output by the followed command, if any.
+    fn take_block(&mut self, opt: &Options) {
+        if let Some(block) = self.feed.as_ref().and_then(Feed::take) {
+            self.output = stdout_text(&block.stdout, opt);
+            self.stderr = String::from_utf8_lossy(&block.stderr).into_owned();
+            self.fresh = true;
+        }
+    }
+
+    /// Account for a completed run. A failed one (non-zero exit, killed or not run at all) leaves
+    /// the last successful output on screen with `--keep-last`.
+    fn complete(&mut self, output: Result<Output>, opt: &Options) {
+        let failure = match &output {
+            Ok(output) if output.status.success() => None,
+            Ok(output) => Some(output.status.to_string()),
+            Err(e) => Some(format!("{:#}", e)),
+        };
+        self.status = Some(match &output {
+            Ok(output) => status_mark(output.status),
+            Err(_) => "✗".to_owned(),
+        });
+        self.exit_code = Some(match &output {
+            Ok(output) => match (output.status.code(), output.status.signal()) {
+                (Some(code), _) => code as u8,
+                (None, Some(signal)) => 128u8.wrapping_add(signal as u8),
+                (None, None) => 1,
+            },
+            Err(_) => 1,
+        });
+        if opt.keep_last && self.succeeded && failure.is_some() {
+            self.stale = failure;
+        } else {
+            let text = output_text(&output, opt);
+            self.fresh |= text != self.output;
+            self.output = text;
+            self.stderr = match &output {
+                Ok(output) => String::from_utf8_lossy(&output.stderr).into_owned(),
+                Err(_) => String::new(),
+            };
+            self.succeeded |= failure.is_none();
+            self.stale = None;
+        }
+    }
+
+    fn complete_job(&mut self, job: Job, opt: &Options) {
+        let (output, runtime) = job.wait_timed();
+        // the standard input and followed commands run for good
+        if self.feed.is_none() {
+            self.runtime = Some(runtime);
+        }
+        self.complete(output, opt);
+    }
+
+    /// How long the last run took, if close to the interval or beyond.
+    fn slow(&self, interval: Duration) -> Option<Duration> {
+        self.runtime
+            .filter(|&runtime| runtime.as_secs_f64() >= SLOW_RUN * interval.as_secs_f64())
+    }
+
+    /// How long the last run took, e.g. `123ms`, flagged when it took most of the interval (such
+    /// runs delay the frames, or get killed).
+    fn runtime_text(&self, interval: Duration) -> Option<String> {
+        let text = format_duration(self.runtime?.as_millis() as i64, true);
+        Some(match self.slow(interval) {
+            Some(_) => format!("\u{26a0} {}", text),
+            None => text,
+        })
+    }
+
+    /// Exit code of the last run, if it failed.
+    fn failure_code(&self) -> Option<u8> {
+        self.exit_code.filter(|&code| code != 0)
+    }
+
+    /// Take the output of the runs completed so far, in order.
+    fn collect(&mut self, opt: &Options) {
+        while self.jobs.front().is_some_and(Job::is_finished) {
+            let job = self.jobs.pop_front().unwrap();
+            self.complete_job(job, opt);
+        }
+    }
+
+    /// Wait for all the runs in flight to complete.
+    fn finish(&mut self, opt: &Options) {
+        while let Some(job) = self.jobs.pop_front() {
+            self.complete_job(job, opt);
+        }
+    }
+
+    /// Kill the runs in flight, keeping what they output until then.
+    fn kill(&mut self, opt: &Options) {
+        self.jobs.iter().for_each(Job::kill);
+        self.finish(opt);
+    }
+
+    /// Kill the runs in flight, discarding their output.
+    fn abort(&mut self) {
+        for job in self.jobs.drain(..) {
+            job.kill();
+            let _ = job.wait();
+        }
+    }
+}
+
+#[inline]
+pub fn parse_numbers(line: &str, ranges: &[Range<usize>]) -> Vec<i64> {
+    ranges
+        .iter()
+        .map(|r| line[r.clone()].parse::<i64>().unwrap())
+        .collect()
+}
+
+#[inline]
+pub fn parse_strings<'a>(line: &'a str, ranges: &[Range<usize>]) -> Vec<&'a str> {
+    complement_ranges(ranges, line.len())
+        .iter()
+        .map(|r| &line[r.clone()])
         .collect()
 }
 
@@ -433,6 +2880,7 @@ fn chunks_fingerprint(chunks: &[&str]) -> u64 {
 mod tests {
 
     use super::*;
+    use ansi_term::Colour;
 
     #[test]
     fn test_immutable_strings() {
@@ -453,4 +2901,730 @@ mod tests {
         assert_eq!(numbers[0], 1234);
         assert_eq!(numbers[1], 5678);
     }
+
+    #[test]
+    fn test_format_number_units() {
+        assert_eq!(format_number(1_500.0, Unit::None, 2, RateUnit::S), "1.50K");
+        assert_eq!(
+            format_number(2_000_000.0, Unit::Bits, 2, RateUnit::S),
+            "2.00_Mbps"
+        );
+        assert_eq!(
+            format_number(3_000_000_000.0, Unit::Bytes, 1, RateUnit::S),
+            "3.0_GB/s"
+        );
+        assert_eq!(format_number(12.0, Unit::Bytes, 0, RateUnit::S), "12_B/s");
+        assert_eq!(format_number(2_500.0, Unit::None, 1, RateUnit::M), "2.5K/m");
+        assert_eq!(format_number(64.0, Unit::Bits, 0, RateUnit::H), "64_b/h");
+    }
+
+    #[test]
+    fn test_group_digits() {
+        assert_eq!(group_digits(12345678, ','), "12,345,678");
+        assert_eq!(group_digits(-1234, '.'), "-1.234");
+        assert_eq!(group_digits(123, ','), "123");
+        assert_eq!(group_digits(0, ','), "0");
+        assert_eq!(group_digits(100000, '\u{202f}'), "100\u{202f}000");
+        assert_eq!(group_digits(i64::MIN, ','), "-9,223,372,036,854,775,808");
+    }
+
+    #[test]
+    fn test_separator_of() {
+        assert_eq!(separator_of(""), ',');
+        assert_eq!(separator_of("C"), ',');
+        assert_eq!(separator_of("POSIX"), ',');
+        assert_eq!(separator_of("en_US.UTF-8"), ',');
+        assert_eq!(separator_of("de_DE.UTF-8"), '.');
+        assert_eq!(separator_of("de_CH.UTF-8"), '\'');
+        assert_eq!(separator_of("fr_FR@euro"), '\u{202f}');
+        assert_eq!(separator_of("ru_RU"), '\u{a0}');
+    }
+
+    #[test]
+    fn test_rate_unit() {
+        let ctx = Context {
+            interval: Duration::from_secs(2),
+            precision: 2,
+            rate_unit: RateUnit::M,
+            theme: Theme::default(),
+            symbols: false,
+            grouping: None,
+        };
+        assert_eq!(ctx.rate(10.0), 300.0);
+    }
+
+    #[test]
+    fn test_visible_width() {
+        assert_eq!(visible_width("\x1b[1;34m1234\x1b[0m", 0), 4);
+        assert_eq!(visible_width("a\tb", 0), 9);
+        assert_eq!(visible_width("\u{2191}5", 3), 2);
+    }
+
+    #[test]
+    fn test_format_duration() {
+        assert_eq!(format_duration(42, false), "42s");
+        assert_eq!(format_duration(3723, false), "1h02m");
+        assert_eq!(format_duration(307, false), "5m07s");
+        assert_eq!(format_duration(-90061, false), "-1d01h");
+        assert_eq!(format_duration(250, true), "250ms");
+        assert_eq!(format_duration(1500, true), "1.500s");
+        assert_eq!(format_duration(61_000, true), "1m01s");
+    }
+
+    #[test]
+    fn test_keep_last() {
+        let opt = Options {
+            keep_last: true,
+            ..Default::default()
+        };
+        let mut runner = Runner::default();
+        for cmd in ["echo 1; false", "echo 2", "echo 3; exit 3"] {
+            runner.start(cmd, &opt, &[]);
+            runner.finish(&opt);
+        }
+        // failures are only hidden once there is a successful output to show instead
+        assert_eq!(runner.output, "2\n");
+        assert_eq!(runner.stale.as_deref(), Some("exit status: 3"));
+        assert_eq!(runner.status.as_deref(), Some("✗ 3"));
+        assert_eq!(runner.failure_code(), Some(3));
+
+        runner.start("echo 4", &opt, &[]);
+        runner.finish(&opt);
+        assert_eq!(
+            (runner.output.as_str(), runner.stale.as_deref()),
+            ("4\n", None)
+        );
+    }
+
+    #[test]
+    fn test_file_source() {
+        let opt = Options::default();
+        let file = std::env::temp_dir().join(format!("dwatch-file-{}", std::process::id()));
+        std::fs::write(&file, "eth0 42\n").unwrap();
+
+        let mut runner = Runner::default();
+        runner.start(&format!("< {}", file.display()), &opt, &[]);
+        assert!(runner.jobs.is_empty());
+        assert_eq!(runner.output, "eth0 42\n");
+        assert_eq!(runner.failure_code(), None);
+
+        std::fs::remove_file(&file).unwrap();
+        runner.start(&format!("< {}", file.display()), &opt, &[]);
+        assert!(runner.output.starts_with("dwatch: failed to read"));
+        assert_eq!(runner.failure_code(), Some(1));
+    }
+
+    #[test]
+    fn test_records() {
+        assert_eq!(line_key("rx 10 tx 20", &[3..5, 9..11]), "rx # tx #");
+
+        let frame = [Record {
+            ordinal: 1,
+            line: 2,
+            key: "rx # tx #".to_owned(),
+            index: 1,
+            value: 20,
+            change: Some((5, 2.5)),
+        }];
+        let mut out = Vec::new();
+        let commands = ["uptime".to_owned(), "ip -s link".to_owned()];
+        write_records(&mut out, &frame, &[0..1, 1..2], &commands, unix_time()).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.starts_with(r#"{"timestamp":"#));
+        assert!(out.ends_with(
+            r#","command":"ip -s link","line_key":"rx # tx #","line":2,"index":1,"value":20,"delta":5,"rate":2.5}
+"#
+        ));
+    }
+
+    #[test]
+    fn test_csv() {
+        assert_eq!(csv_field("uptime"), "uptime");
+        assert_eq!(csv_field("say 'a,b'"), "\"say 'a,b'\"");
+        assert_eq!(csv_field("a\"b"), "\"a\"\"b\"");
+
+        let frame = [Record {
+            ordinal: 0,
+            line: 0,
+            key: "rx #, tx #".to_owned(),
+            index: 0,
+            value: 10,
+            change: None,
+        }];
+        let mut out = Vec::new();
+        write_csv(
+            &mut out,
+            &frame,
+            &[0..1, 1..2],
+            &["ip".to_owned(), "ss".to_owned()],
+            unix_time(),
+        )
+        .unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert_eq!(
+            out.split_once(',').unwrap().1,
+            "ip,0,\"rx #, tx #\",0,10,,\n"
+        );
+    }
+
+    #[test]
+    fn test_exposition() {
+        let record = |line, value, change| Record {
+            ordinal: 0,
+            line,
+            key: "rx \"#\"".to_owned(),
+            index: 0,
+            value,
+            change,
+        };
+        let frame = [record(0, 10, None), record(1, 20, Some((5, 2.5)))];
+        let text = exposition(&frame, &[0..2, 2..3], &["ip".to_owned(), "ss".to_owned()]);
+        let samples: Vec<_> = text.lines().filter(|l| !l.starts_with('#')).collect();
+        assert_eq!(
+            samples,
+            [
+                r##"dwatch_value{command="ip",line="0",line_key="rx \"#\"",column="1"} 10"##,
+                r##"dwatch_value{command="ip",line="1",line_key="rx \"#\"",column="1"} 20"##,
+                r##"dwatch_delta{command="ip",line="1",line_key="rx \"#\"",column="1"} 5"##,
+                r##"dwatch_rate{command="ip",line="1",line_key="rx \"#\"",column="1"} 2.5"##,
+            ]
+        );
+        assert!(text.starts_with("# HELP dwatch_value "));
+        assert!(text.contains("\n# TYPE dwatch_rate gauge\n"));
+    }
+
+    #[test]
+    fn test_statsd_metrics() {
+        let record = |ordinal, line, key: &str, index, change| Record {
+            ordinal,
+            line,
+            key: key.to_owned(),
+            index,
+            value: 10,
+            change,
+        };
+        let frame = [
+            record(0, 0, "rx #, tx #", 1, Some((3, 1.0))),
+            record(1, 1, "eth0: # #", 0, None),
+            record(2, 1, "eth0: # #", 1, None),
+            record(3, 2, "# #", 1, None),
+            record(4, 0, "rx #, tx #", 1, None),
+        ];
+        let commands = ["ip -s link".to_owned(), "rx/tx".to_owned()];
+        assert_eq!(
+            statsd_metrics(&frame, &[0..4, 4..5], &commands),
+            [
+                "dwatch.ip_-s_link.tx:10|g",
+                "dwatch.ip_-s_link.tx:3|c",
+                "dwatch.ip_-s_link.eth0:10|g",
+                "dwatch.ip_-s_link.eth0.2:10|g",
+                "dwatch.ip_-s_link.line3.2:10|g",
+                "dwatch.rx_tx.tx:10|g",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_dry_run_line() {
+        let mut ctx = Context {
+            interval: Duration::from_secs(1),
+            precision: 2,
+            rate_unit: RateUnit::S,
+            theme: Theme::default(),
+            symbols: false,
+            grouping: None,
+        };
+        let (marked, numbers) = dry_run_line("rx 1234, tx 88", 3, 1, &ctx);
+        assert_eq!(
+            marked,
+            format!(
+                "rx {}, tx {}",
+                Colour::Blue.underline().paint("1234"),
+                Colour::Blue.underline().paint("88")
+            )
+        );
+        assert_eq!(numbers, "1.3.0 rx=1234  1.3.1 tx=88");
+
+        ctx.symbols = true;
+        let (marked, numbers) = dry_run_line("up 3 days", 0, 0, &ctx);
+        assert_eq!(marked, "up [3] days");
+        assert_eq!(numbers, "0.0.0 up=3");
+        assert_eq!(
+            dry_run_line("load", 0, 0, &ctx),
+            ("load".to_owned(), String::new())
+        );
+    }
+
+    #[test]
+    fn test_ticker_line() {
+        let ctx = Context {
+            interval: Duration::from_secs(1),
+            precision: 1,
+            rate_unit: RateUnit::S,
+            theme: Theme::by_name("none").unwrap(),
+            symbols: false,
+            grouping: None,
+        };
+        let record = |ordinal, index, value, change| Record {
+            ordinal,
+            line: 0,
+            key: "rx #, tx #".to_owned(),
+            index,
+            value,
+            change,
+        };
+        let frame = [
+            record(0, 0, 1234, Some((12, 6.0))),
+            record(1, 1, 88, Some((0, 0.0))),
+        ];
+        let ticker = |selected: &[&str]| {
+            let selected: Vec<String> = selected.iter().map(|s| s.to_string()).collect();
+            ticker_line(&frame, &[0..1, 1..2], &selected, &ctx, "12:00:05")
+        };
+        // rates are in bold with the plain theme
+        assert_eq!(
+            ticker(&[]),
+            "12:00:05 rx 1234 \u{2191}12 \x1b[1m6.0/s\x1b[0m"
+        );
+        assert_eq!(
+            ticker(&["tx", "0.0.0"]),
+            "12:00:05 rx 1234 \u{2191}12 \x1b[1m6.0/s\x1b[0m | tx 88"
+        );
+        assert_eq!(ticker(&["rx.1"]), "12:00:05 -");
+        // values are grouped as on screen
+        let ctx = Context {
+            grouping: Some(','),
+            ..ctx.clone()
+        };
+        assert_eq!(
+            ticker_line(&frame, &[0..1, 1..2], &[], &ctx, "12:00:05"),
+            "12:00:05 rx 1,234 \u{2191}12 \x1b[1m6.0/s\x1b[0m"
+        );
+    }
+
+    #[test]
+    fn test_graphite_lines() {
+        let frame = [Record {
+            ordinal: 0,
+            line: 2,
+            key: "load # # #".to_owned(),
+            index: 1,
+            value: 3,
+            change: None,
+        }];
+        let commands = ["cat /proc/loadavg".to_owned(), "ss".to_owned()];
+        assert_eq!(
+            graphite_lines(
+                &frame,
+                &[0..1, 1..2],
+                &commands,
+                "host1",
+                Duration::from_secs(7)
+            ),
+            "host1.cat_proc_loadavg.line3.col2 3 7\n"
+        );
+    }
+
+    #[test]
+    fn test_line_protocol() {
+        let frame = [Record {
+            ordinal: 1,
+            line: 0,
+            key: "rx #, tx #".to_owned(),
+            index: 1,
+            value: 20,
+            change: Some((4, 2.0)),
+        }];
+        let commands = ["ip".to_owned(), "cat /proc/net/dev".to_owned()];
+        assert_eq!(
+            line_protocol(&frame, &[0..1, 1..2], &commands, Duration::from_secs(2)),
+            "dwatch,command=cat\\ /proc/net/dev,line=0,line_key=rx\\ #\\,\\ tx\\ #,column=2 \
+             value=20i,delta=4i,rate=2 2000000000\n"
+        );
+    }
+
+    #[test]
+    fn test_history() {
+        let record = |ordinal, value| Record {
+            ordinal,
+            line: 0,
+            key: "# #".to_owned(),
+            index: ordinal,
+            value,
+            change: None,
+        };
+        let commands = ["cat counters".to_owned(), "ss".to_owned()];
+        let sources = [0..2, 2..2];
+        let mut history = History::default();
+        for t in 0..HISTORY_LEN as u64 + 2 {
+            let focused = if t == 1 {
+                BTreeSet::from([1])
+            } else {
+                BTreeSet::new()
+            };
+            let frame = [record(0, t as i64), record(1, 10 * t as i64)];
+            history.push(
+                Duration::from_secs(t),
+                &frame,
+                &sources,
+                &commands,
+                &focused,
+            );
+        }
+
+        let mut out = Vec::new();
+        history.write(&mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        let rows: Vec<_> = out.lines().collect();
+        assert_eq!(rows.len(), HISTORY_LEN + 1);
+        assert_eq!(rows[0], CSV_HEADER);
+        assert_eq!(rows[1], "2.000,cat counters,0,# #,1,20,,");
+    }
+
+    #[test]
+    fn test_summary() {
+        let record = |value, change| Record {
+            ordinal: 0,
+            line: 1,
+            key: "rx #".to_owned(),
+            index: 0,
+            value,
+            change,
+        };
+        let commands = ["ip".to_owned(), "ss".to_owned()];
+        let mut summary = Summary::new(1);
+        for frame in [[record(10, None)], [record(30, Some((20, 20.0)))]] {
+            summary.push(&frame, &[0..1, 1..1], &commands);
+        }
+        summary.fired[0] = 2;
+        let alerts: Vec<Alert> = vec!["delta > 5".parse().unwrap()];
+        assert_eq!(
+            summary.to_json(2, Duration::from_secs(1), &alerts),
+            r#"{"iterations":2,"duration":1,"alerts":[{"alert":"delta > 5","rule":"delta > 5","fired":2}],"values":[{"command":"ip","line":1,"line_key":"rx #","index":0,"samples":2,"min":10,"max":30,"avg":20,"last":30,"total_delta":20}]}"#
+        );
+    }
+
+    #[test]
+    fn test_render_template() {
+        let record = Record {
+            ordinal: 0,
+            line: 2,
+            key: "\"rx\" #".to_owned(),
+            index: 0,
+            value: 7,
+            change: None,
+        };
+        assert_eq!(
+            render_template(
+                r#"{"name":"{line_key}","col":{column},"v":{value},"d":{delta},"t":{timestamp_ms}}"#,
+                &record,
+                "ip",
+                Duration::from_millis(1500)
+            ),
+            r#"{"name":"\"rx\" #","col":1,"v":7,"d":null,"t":1500}"#
+        );
+    }
+
+    #[test]
+    fn test_dashboard_update() {
+        let record = Record {
+            ordinal: 0,
+            line: 2,
+            key: "up #".to_owned(),
+            index: 0,
+            value: 3,
+            change: Some((1, 0.5)),
+        };
+        let commands = ["true".to_owned(), "uptime".to_owned()];
+        let update = dashboard_update(
+            &[record],
+            &[0..0, 0..1],
+            &commands,
+            ["", "\n\nup 3\n"].into_iter(),
+            Duration::from_secs(2),
+        );
+        assert_eq!(
+            update.frame,
+            r#"{"timestamp":2,"commands":[{"command":"true","output":""},{"command":"uptime","output":"\n\nup 3\n"}],"values":[{"key":"1.2.0","timestamp":2,"command":"uptime","line_key":"up #","line":2,"index":0,"value":3,"delta":1,"rate":0.5}]}"#
+        );
+        assert!(update
+            .values
+            .starts_with(r#"{"timestamp":2,"values":[{"key":"1.2.0","#));
+        assert_eq!(update.samples, [("1.2.0".to_owned(), 3)]);
+    }
+
+    #[test]
+    fn test_frame_message() {
+        let record = Record {
+            ordinal: 0,
+            line: 1,
+            key: "#".to_owned(),
+            index: 0,
+            value: -1,
+            change: None,
+        };
+        let commands = ["true".to_owned(), "echo".to_owned()];
+        let message = frame_message(
+            &[record],
+            &[0..0, 0..1],
+            &commands,
+            ["", "\n-1"].into_iter(),
+            Duration::from_millis(300),
+        );
+        let mut expected = b"\x08\xac\x02".to_vec();
+        expected.extend_from_slice(b"\x12\x08\x0a\x04true\x12\x00");
+        expected.extend_from_slice(b"\x12\x0b\x0a\x04echo\x12\x03\n-1");
+        expected.extend_from_slice(b"\x1a\x16\x0a\x051.1.0\x12\x04echo\x1a\x01#");
+        expected.extend_from_slice(b"\x20\x01\x28\x00\x30\x01");
+        assert_eq!(message, expected);
+    }
+
+    #[test]
+    fn test_log() {
+        let mut log = Log::new(Vec::new());
+        write!(log, "--- t ---\nrx 1{}\n", ansi_escapes::EraseEndLine).unwrap();
+        assert!(log.out.is_empty());
+        log.flush().unwrap();
+        assert_eq!(log.out, b"--- t ---\nrx 1\n");
+
+        let time = std::time::UNIX_EPOCH + Duration::from_millis(86_400_250);
+        let text = local_time(time);
+        assert_eq!(text.len(), "1970-01-02 00:00:00.250".len());
+        assert!(text.ends_with(".250"));
+    }
+
+    #[test]
+    fn test_changed_lines() {
+        assert_eq!(changed_lines("a\nb\nc", "a\nb\nc"), 0);
+        assert_eq!(changed_lines("a\nb\nc", "a\nx\nc"), 1);
+        assert_eq!(changed_lines("a\nb", "a\nb\nc\nd"), 2);
+        assert_eq!(changed_lines("", "a"), 1);
+    }
+
+    #[test]
+    fn test_random_delay() {
+        let max = Duration::from_secs(5);
+        let delays: Vec<_> = (0..10).map(|_| random_delay(max)).collect();
+        assert!(delays.iter().all(|&d| d < max));
+        assert!(delays.iter().any(|&d| d != delays[0]));
+    }
+
+    #[test]
+    fn test_banner() {
+        let control = Control::new(0)
+            .with_commands(vec!["ps aux | grep -c x".into(), "uptime".into()])
+            .with_interval(Duration::from_secs(2));
+        let runners = [Runner::default(), Runner::default()];
+        let banner = |opt: &Options| {
+            let mut out = Vec::new();
+            write_banner(&mut out, opt, &control, &runners, 0, &[], None).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+        assert_eq!(
+            banner(&Options::default()),
+            "Every 2000 ms, delta[default]: ps aux | grep -c x | uptime\x1b[K\n\n"
+        );
+        let opt = Options {
+            title: Some("processes".into()),
+            ..Default::default()
+        };
+        assert_eq!(
+            banner(&opt),
+            "Every 2000 ms, delta[default]: processes\x1b[K\n\n"
+        );
+    }
+
+    #[test]
+    fn test_skip_missed() {
+        let start = Instant::now();
+        let second = Duration::from_secs(1);
+
+        let mut next = start + second;
+        assert_eq!(skip_missed(&mut next, second, start), 0);
+        assert_eq!(next, start + second);
+
+        // at 3.5s the slots at 1s, 2s and 3s are gone, the next one stays on the grid
+        assert_eq!(
+            skip_missed(&mut next, second, start + second * 3 + second / 2),
+            3
+        );
+        assert_eq!(next, start + second * 4);
+    }
+
+    #[test]
+    fn test_slow() {
+        let opt = Options::default();
+        let mut runner = Runner::default();
+        runner.start("sleep 0.2", &opt, &[]);
+        runner.finish(&opt);
+        assert!(runner.slow(Duration::from_millis(200)).is_some());
+        assert!(runner.slow(Duration::from_secs(1)).is_none());
+
+        runner.runtime = Some(Duration::from_millis(123));
+        assert_eq!(
+            runner.runtime_text(Duration::from_secs(1)).as_deref(),
+            Some("123ms")
+        );
+        assert_eq!(
+            runner.runtime_text(Duration::from_millis(150)).as_deref(),
+            Some("\u{26a0} 123ms")
+        );
+    }
+
+    #[test]
+    fn test_pipe() {
+        let opt = Options {
+            pipe: Some("grep eth0".to_owned()),
+            ..Default::default()
+        };
+        let mut runner = Runner::default();
+        runner.start("printf 'lo 1\\neth0 2\\n'", &opt, &[]);
+        runner.finish(&opt);
+        assert_eq!(runner.output, "eth0 2\n");
+    }
+
+    #[test]
+    fn test_child_env() {
+        let opt = Options {
+            no_banner: true,
+            ..Default::default()
+        };
+        let env = child_env(&opt, 3, Duration::from_millis(1500));
+        let mut runner = Runner::default();
+        runner.start("echo $DWATCH_ITERATION $DWATCH_INTERVAL", &opt, &env);
+        runner.finish(&opt);
+        assert_eq!(runner.output, "3 1.5\n");
+    }
+
+    #[test]
+    fn test_symbols() {
+        let ctx = Context {
+            interval: Duration::from_secs(1),
+            precision: 2,
+            rate_unit: RateUnit::S,
+            theme: Theme::default(),
+            symbols: true,
+            grouping: None,
+        };
+        assert_eq!(ctx.format_delta(5), "\u{2191}5");
+        assert_eq!(ctx.format_delta(-5), "\u{2193}5");
+        assert_eq!(ctx.format_delta(0), "0");
+        assert_eq!(ctx.bracket("1/2".to_owned()), "[1/2]");
+    }
+
+    fn selection<'a>(
+        ctx: &'a Context,
+        alerts: &'a [Alert],
+        alerted: &'a Context,
+        alert_styles: &'a [Style],
+    ) -> Selection<'a> {
+        Selection {
+            style: 0,
+            focus: Focus::default(),
+            targets: BTreeSet::new(),
+            ordinal: 0,
+            lines: Vec::new(),
+            focused: ctx,
+            row: 0,
+            col: 0,
+            hits: Vec::new(),
+            exit_if: &[],
+            triggered: false,
+            alerts,
+            alerted,
+            alert_styles,
+            alerting: BTreeMap::new(),
+            firing: BTreeMap::new(),
+            changed: false,
+            records: None,
+        }
+    }
+
+    #[test]
+    fn test_alert() {
+        let ctx = Context {
+            interval: Duration::from_secs(1),
+            precision: 2,
+            rate_unit: RateUnit::S,
+            theme: Theme::by_name("none").unwrap(),
+            symbols: true,
+            grouping: None,
+        };
+        let alerts: Vec<Alert> = vec!["col[2].delta > 5".parse().unwrap()];
+        let styles = [Style::new()];
+        let mut lmap = LineMap::new();
+        let mut out = Vec::new();
+
+        for (line, firing) in [("a 1 10", false), ("a 2 20", true)] {
+            let mut sel = selection(&ctx, &alerts, &ctx, &styles);
+            out.clear();
+            writeln_line(&mut out, &mut sel, line, 0, &mut lmap, &ctx, true).unwrap();
+            assert_eq!(!sel.firing.is_empty(), firing);
+            assert_eq!(sel.changed, firing);
+            if firing {
+                assert_eq!(sel.firing[&0].ordinal, 1);
+                assert_eq!(sel.firing[&0].describe(&ctx), "value 20, rate 10.00");
+            }
+        }
+        // only the second number breached the rule
+        assert!(String::from_utf8_lossy(&out).starts_with("a 2 !20"));
+    }
+
+    #[test]
+    fn test_baseline() {
+        let ctx = Context {
+            interval: Duration::from_secs(1),
+            precision: 2,
+            rate_unit: RateUnit::S,
+            theme: Theme::default(),
+            symbols: false,
+            grouping: None,
+        };
+        let styles = [Style::new()];
+        let baseline = line_map("eth0: 100 5\nlo: 7");
+        assert_eq!(baseline.len(), 2);
+
+        let mut out = Vec::new();
+        let mut sel = selection(&ctx, &[], &ctx, &styles);
+        sel.records = Some(Vec::new());
+        let mut lmap = baseline.clone();
+        writeln_line(&mut out, &mut sel, "eth0: 150 5", 0, &mut lmap, &ctx, true).unwrap();
+        let records = sel.records.unwrap();
+        assert_eq!(records[0].change.map(|c| c.0), Some(50));
+        assert_eq!(records[1].change.map(|c| c.0), Some(0));
+    }
+
+    #[test]
+    fn test_alert_style() {
+        let ctx = Context {
+            interval: Duration::from_secs(1),
+            precision: 2,
+            rate_unit: RateUnit::S,
+            theme: Theme::default(),
+            symbols: false,
+            grouping: None,
+        };
+        let alerts: Vec<Alert> = vec!["delta > 5".parse().unwrap()];
+        let styles = [ctx.alert_style(&alerts[0], false)];
+        let alerted = ctx.alerted();
+        let mut lmap = LineMap::new();
+        let mut out = Vec::new();
+
+        for line in ["10", "20"] {
+            let mut sel = selection(&ctx, &alerts, &alerted, &styles);
+            // abs-delta: the value and its delta, styled as a whole
+            sel.style = 1;
+            out.clear();
+            writeln_line(&mut out, &mut sel, line, 0, &mut lmap, &ctx, true).unwrap();
+        }
+        let expected = Colour::White.on(Colour::Red).bold().paint("20_10");
+        assert!(String::from_utf8_lossy(&out).starts_with(&expected.to_string()));
+
+        let mut alert = alerts[0].clone();
+        alert.color = Some(Colour::Purple);
+        assert_eq!(
+            ctx.alert_style(&alert, true),
+            Colour::White.on(Colour::Purple).bold().blink()
+        );
+    }
 }
@@ -0,0 +1,183 @@
+//! Frames rendered when the watched files change (`--on-change`), as reported by inotify, or
+//! found by polling the files where changes are not reported (e.g. in /proc).
+
+use std::{
+    collections::hash_map::DefaultHasher,
+    hash::Hasher,
+    path::PathBuf,
+    sync::{atomic::Ordering, Arc},
+};
+
+use crate::control::Control;
+
+/// Notify the rendering loop of every change of the file, from a thread of its own.
+pub fn spawn(path: PathBuf, control: Arc<Control>) {
+    std::thread::spawn(move || {
+        #[cfg(target_os = "linux")]
+        inotify::watch(&path, &control);
+        poll(&path, &control);
+    });
+}
+
+/// Hash of the content of the file (0 if it cannot be read).
+fn fingerprint(path: &PathBuf) -> u64 {
+    let mut h = DefaultHasher::new();
+    if let Ok(content) = std::fs::read(path) {
+        h.write(&content);
+    }
+    h.finish()
+}
+
+/// Compare the content of the file with the previous one at every interval.
+fn poll(path: &PathBuf, control: &Control) {
+    let mut last = fingerprint(path);
+    while !control.term.load(Ordering::Relaxed) {
+        std::thread::sleep(control.interval());
+        let current = fingerprint(path);
+        if current != last {
+            last = current;
+            control.notify();
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod inotify {
+    use std::{
+        ffi::CString,
+        os::{fd::AsRawFd, fd::FromRawFd, fd::OwnedFd, unix::ffi::OsStrExt},
+        path::Path,
+        sync::atomic::Ordering,
+        time::Duration,
+    };
+
+    use crate::control::Control;
+
+    /// Filesystems whose files change without any notification.
+    const PSEUDO_FS: [libc::c_long; 2] = [0x9fa0 /* proc */, 0x6265_6572 /* sysfs */];
+
+    /// Quiet time after which the events of a change are considered complete.
+    const SETTLE: Duration = Duration::from_millis(20);
+
+    const EVENTS: u32 = libc::IN_MODIFY
+        | libc::IN_CLOSE_WRITE
+        | libc::IN_ATTRIB
+        | libc::IN_MOVE_SELF
+        | libc::IN_DELETE_SELF;
+
+    /// Notify every change of the file reported by inotify. Returns (so that the file is polled
+    /// instead) when changes cannot be reported.
+    pub fn watch(path: &Path, control: &Control) {
+        let Ok(cpath) = CString::new(path.as_os_str().as_bytes()) else {
+            return;
+        };
+        // SAFETY: statfs only writes the structure passed in.
+        unsafe {
+            let mut fs: libc::statfs = std::mem::zeroed();
+            if libc::statfs(cpath.as_ptr(), &mut fs) == 0
+                && PSEUDO_FS.contains(&(fs.f_type as libc::c_long))
+            {
+                return;
+            }
+        }
+        // SAFETY: the descriptor is checked and owned right away.
+        let fd = unsafe {
+            let fd = libc::inotify_init1(libc::IN_CLOEXEC);
+            if fd < 0 {
+                return;
+            }
+            OwnedFd::from_raw_fd(fd)
+        };
+
+        // files replaced as a whole (as editors save them) are watched again under their name
+        let mut buf = [0u64; 512];
+        while !control.term.load(Ordering::Relaxed) {
+            // SAFETY: the path is NUL terminated.
+            if unsafe { libc::inotify_add_watch(fd.as_raw_fd(), cpath.as_ptr(), EVENTS) } < 0 {
+                if !path.exists() {
+                    std::thread::sleep(control.interval());
+                    continue;
+                }
+                return;
+            }
+
+            loop {
+                // SAFETY: reads at most the size of the buffer, aligned for inotify_event.
+                let n = unsafe {
+                    libc::read(
+                        fd.as_raw_fd(),
+                        buf.as_mut_ptr().cast(),
+                        std::mem::size_of_val(&buf),
+                    )
+                };
+                if n <= 0 {
+                    if std::io::Error::last_os_error().kind() == std::io::ErrorKind::Interrupted {
+                        continue;
+                    }
+                    return;
+                }
+                let gone = gone(&buf, n as usize);
+                // a write comes with several events (e.g. truncation, then data), the frame is
+                // rendered once they settled
+                if !gone && settling(fd.as_raw_fd()) {
+                    continue;
+                }
+                control.notify();
+                if gone {
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Whether more events come within `SETTLE`.
+    fn settling(fd: libc::c_int) -> bool {
+        let mut pfd = libc::pollfd {
+            fd,
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        // SAFETY: pfd is a single valid pollfd structure.
+        unsafe { libc::poll(&mut pfd, 1, SETTLE.as_millis() as libc::c_int) > 0 }
+    }
+
+    /// Whether the events read tell the file was removed, moved or its watch dropped.
+    fn gone(buf: &[u64], len: usize) -> bool {
+        let bytes = buf.as_ptr().cast::<u8>();
+        let mut offset = 0;
+        let mut gone = false;
+        while offset + std::mem::size_of::<libc::inotify_event>() <= len {
+            // SAFETY: the kernel writes whole events, the header is read unaligned.
+            let event = unsafe {
+                std::ptr::read_unaligned(bytes.add(offset).cast::<libc::inotify_event>())
+            };
+            gone |=
+                event.mask & (libc::IN_MOVE_SELF | libc::IN_DELETE_SELF | libc::IN_IGNORED) != 0;
+            offset += std::mem::size_of::<libc::inotify_event>() + event.len as usize;
+        }
+        gone
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_change() {
+        let file = std::env::temp_dir().join(format!("dwatch-change-{}", std::process::id()));
+        std::fs::write(&file, "1\n").unwrap();
+        let control = Arc::new(Control::new(0).with_interval(Duration::from_millis(50)));
+        spawn(file.clone(), Arc::clone(&control));
+        std::thread::sleep(Duration::from_millis(100));
+
+        let start = Instant::now();
+        std::fs::write(&file, "2\n").unwrap();
+        assert!(!control.wait(start + Duration::from_secs(10)));
+        assert!(start.elapsed() < Duration::from_secs(10));
+
+        control.quit();
+        std::fs::remove_file(&file).unwrap();
+    }
+}
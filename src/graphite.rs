@@ -0,0 +1,79 @@
+//! Graphite (Carbon) plaintext protocol sink for `--graphite`.
+
+use std::{
+    io::Write,
+    net::{TcpStream, ToSocketAddrs},
+    sync::mpsc::{self, Sender},
+    time::Duration,
+};
+
+use anyhow::{anyhow, Result};
+
+/// Time given to Carbon to accept a connection or the lines of a frame.
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Handle on the thread sending the lines to Carbon, so that a slow or absent server never holds
+/// the frames.
+pub struct Client {
+    tx: Sender<String>,
+}
+
+impl Client {
+    /// Queue `path value timestamp` lines for sending. The lines of frames Carbon cannot be
+    /// reached for are dropped, and the connection is opened again for the next ones.
+    pub fn send(&self, lines: String) {
+        let _ = self.tx.send(lines);
+    }
+}
+
+/// Start sending to a `host:port` address (port 2003 by default).
+pub fn connect(addr: &str) -> Result<Client> {
+    let addr = if addr.rsplit_once(':').is_some_and(|(_, p)| !p.contains(']')) {
+        addr.to_owned()
+    } else {
+        format!("{}:2003", addr)
+    };
+    let target = addr
+        .to_socket_addrs()
+        .map_err(|e| anyhow!("graphite {}: {}", addr, e))?
+        .next()
+        .ok_or_else(|| anyhow!("graphite {}: no address", addr))?;
+
+    let (tx, rx) = mpsc::channel::<String>();
+    std::thread::spawn(move || {
+        let mut stream: Option<TcpStream> = None;
+        for lines in rx {
+            if stream.is_none() {
+                stream = TcpStream::connect_timeout(&target, TIMEOUT).ok();
+                if let Some(s) = &stream {
+                    let _ = s.set_write_timeout(Some(TIMEOUT));
+                }
+            }
+            if let Some(s) = stream.as_mut() {
+                if s.write_all(lines.as_bytes()).is_err() {
+                    stream = None;
+                }
+            }
+        }
+    });
+    Ok(Client { tx })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use std::net::TcpListener;
+
+    #[test]
+    fn test_send() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = connect(&listener.local_addr().unwrap().to_string()).unwrap();
+        client.send("dwatch.uptime.line1.col1 10 1700000000\n".to_owned());
+
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut buf = [0u8; 64];
+        let n = stream.read(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"dwatch.uptime.line1.col1 10 1700000000\n");
+    }
+}
@@ -0,0 +1,513 @@
+//! gRPC server for `--grpc`, streaming every frame to the subscribers of
+//! `dwatch.Watch/Subscribe` (see `proto/dwatch.proto`).
+//!
+//! It speaks just enough HTTP/2 over cleartext TCP (with prior knowledge, as gRPC clients do
+//! without TLS) to answer that call: messages are sent as far as the flow-control windows let
+//! them, the rest once the subscriber opens them again. A subscriber that falls behind skips to
+//! the last frame when it catches up, so that it never holds the others or dwatch.
+
+use std::{
+    io::{Read, Write},
+    net::{Shutdown, TcpListener, TcpStream},
+    sync::{
+        mpsc::{self, Sender},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use anyhow::{Context as _, Result};
+
+use crate::{hpack, verbose};
+
+/// Path of the streaming call.
+const SUBSCRIBE: &str = "/dwatch.Watch/Subscribe";
+
+/// Connection preface sent by clients.
+const PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+
+/// Time given to a subscriber to take a frame.
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Flow-control window of connections and streams until the peer changes it.
+const DEFAULT_WINDOW: i64 = 65535;
+
+/// Largest frame payload sent, the smallest a peer can accept.
+const MAX_FRAME: usize = 16384;
+
+// frame types
+const DATA: u8 = 0x0;
+const HEADERS: u8 = 0x1;
+const RST_STREAM: u8 = 0x3;
+const SETTINGS: u8 = 0x4;
+const PING: u8 = 0x6;
+const GOAWAY: u8 = 0x7;
+const WINDOW_UPDATE: u8 = 0x8;
+const CONTINUATION: u8 = 0x9;
+
+// frame flags
+const END_STREAM: u8 = 0x1;
+const ACK: u8 = 0x1;
+const END_HEADERS: u8 = 0x4;
+const PADDED: u8 = 0x8;
+const PRIORITY: u8 = 0x20;
+
+/// Setting of the initial window of the streams.
+const SETTINGS_INITIAL_WINDOW_SIZE: u16 = 0x4;
+
+/// gRPC status of calls to other methods.
+const UNIMPLEMENTED: u32 = 12;
+
+/// What a connection is told, by its peer or by dwatch.
+enum Event {
+    /// A frame to stream, as an encoded `Frame` message.
+    Publish(Arc<Vec<u8>>),
+    /// A call to the given path on a new stream.
+    Call(u32, String),
+    Settings(Option<u32>),
+    Ping([u8; 8]),
+    WindowUpdate(u32, u32),
+    /// Data received on a stream, with its length and whether it ends the stream.
+    Data(u32, u32, bool),
+    Reset(u32),
+    Closed,
+}
+
+#[derive(Default)]
+struct Shared {
+    /// Last frame, sent first to new subscribers.
+    last: Mutex<Option<Arc<Vec<u8>>>>,
+    connections: Mutex<Vec<Sender<Event>>>,
+}
+
+/// Handle on the server, to which every frame is published.
+pub struct Server {
+    shared: Arc<Shared>,
+}
+
+impl Server {
+    /// Stream a frame, an encoded `Frame` message, to the subscribers.
+    pub fn publish(&self, frame: Vec<u8>) {
+        let frame = Arc::new(frame);
+        self.shared
+            .connections
+            .lock()
+            .unwrap()
+            .retain(|tx| tx.send(Event::Publish(Arc::clone(&frame))).is_ok());
+        *self.shared.last.lock().unwrap() = Some(frame);
+    }
+}
+
+/// Listen on an address such as `:50051` (all interfaces) or `127.0.0.1:50051`.
+pub fn serve(addr: &str) -> Result<Server> {
+    let bind = match addr.strip_prefix(':') {
+        Some(port) => format!("0.0.0.0:{}", port),
+        None => addr.to_owned(),
+    };
+    let listener =
+        TcpListener::bind(&bind).with_context(|| format!("cannot listen on {}", addr))?;
+    Ok(spawn(listener))
+}
+
+/// Serve the connections to the listener, each from its own threads.
+fn spawn(listener: TcpListener) -> Server {
+    let shared = Arc::new(Shared::default());
+    let served = Arc::clone(&shared);
+    std::thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let shared = Arc::clone(&served);
+            // a subscriber going away is no concern of dwatch
+            std::thread::spawn(move || {
+                let _ = connection(&stream, &shared);
+                let _ = stream.shutdown(Shutdown::Both);
+            });
+        }
+    });
+    Server { shared }
+}
+
+/// A stream subscribed to the frames.
+struct Subscriber {
+    id: u32,
+    window: i64,
+    /// Rest of the message being sent, held back until the windows open.
+    pending: Vec<u8>,
+    /// Last frame published meanwhile, sent once the pending message is through.
+    next: Option<Arc<Vec<u8>>>,
+}
+
+impl Subscriber {
+    fn new(id: u32, window: i64) -> Self {
+        Self {
+            id,
+            window,
+            pending: Vec::new(),
+            next: None,
+        }
+    }
+
+    /// Queue a frame, an encoded `Frame` message, in place of any other not yet started.
+    fn queue(&mut self, frame: Arc<Vec<u8>>) {
+        if self.next.replace(frame).is_some() {
+            verbose::event!(
+                "grpc",
+                "stream {}: frame skipped, subscriber behind",
+                self.id
+            );
+        }
+    }
+
+    /// Send as much of the pending message, and then of the next one, as the flow-control
+    /// windows (that of the connection and that of the stream) take.
+    fn send(&mut self, out: &mut impl Write, window: &mut i64) -> std::io::Result<()> {
+        loop {
+            if self.pending.is_empty() {
+                match self.next.take() {
+                    Some(message) => self.pending = length_prefixed(&message),
+                    None => break,
+                }
+            }
+            let len = (self.pending.len() as i64)
+                .min(*window)
+                .min(self.window)
+                .min(MAX_FRAME as i64);
+            if len <= 0 {
+                break;
+            }
+            write_frame(out, DATA, 0, self.id, &self.pending[..len as usize])?;
+            self.pending.drain(..len as usize);
+            *window -= len;
+            self.window -= len;
+        }
+        out.flush()
+    }
+}
+
+/// Serve a connection: its frames are read from a thread, and turned into events handled here
+/// along with the frames published.
+fn connection(stream: &TcpStream, shared: &Shared) -> std::io::Result<()> {
+    let mut out = stream.try_clone()?;
+    out.set_write_timeout(Some(TIMEOUT))?;
+    let mut input = stream.try_clone()?;
+    let mut preface = [0u8; PREFACE.len()];
+    input.read_exact(&mut preface)?;
+    if preface != PREFACE {
+        return Err(std::io::Error::other("not an HTTP/2 connection"));
+    }
+    write_frame(&mut out, SETTINGS, 0, 0, &[])?;
+
+    let (tx, rx) = mpsc::channel();
+    shared.connections.lock().unwrap().push(tx.clone());
+    std::thread::spawn(move || {
+        let _ = read_frames(&mut input, &tx);
+        let _ = tx.send(Event::Closed);
+    });
+
+    let mut subscribers: Vec<Subscriber> = Vec::new();
+    let mut window = DEFAULT_WINDOW;
+    let mut initial_window = DEFAULT_WINDOW;
+    for event in rx {
+        match event {
+            Event::Publish(frame) => {
+                for subscriber in &mut subscribers {
+                    subscriber.queue(Arc::clone(&frame));
+                    subscriber.send(&mut out, &mut window)?;
+                }
+            }
+            Event::Call(id, path) if path == SUBSCRIBE => {
+                let headers = [(":status", "200"), ("content-type", "application/grpc")];
+                write_frame(
+                    &mut out,
+                    HEADERS,
+                    END_HEADERS,
+                    id,
+                    &encode_headers(&headers),
+                )?;
+                let mut subscriber = Subscriber::new(id, initial_window);
+                let last = shared.last.lock().unwrap().clone();
+                if let Some(frame) = last {
+                    subscriber.queue(frame);
+                    subscriber.send(&mut out, &mut window)?;
+                }
+                subscribers.push(subscriber);
+            }
+            Event::Call(id, _) => {
+                let status = UNIMPLEMENTED.to_string();
+                let headers = [
+                    (":status", "200"),
+                    ("content-type", "application/grpc"),
+                    ("grpc-status", &status),
+                    ("grpc-message", "unknown method"),
+                ];
+                let block = encode_headers(&headers);
+                write_frame(&mut out, HEADERS, END_HEADERS | END_STREAM, id, &block)?;
+            }
+            Event::Settings(initial) => {
+                if let Some(initial) = initial {
+                    // the windows of open streams move by the change
+                    let change = initial as i64 - initial_window;
+                    subscribers.iter_mut().for_each(|s| s.window += change);
+                    initial_window = initial as i64;
+                }
+                write_frame(&mut out, SETTINGS, ACK, 0, &[])?;
+                for subscriber in &mut subscribers {
+                    subscriber.send(&mut out, &mut window)?;
+                }
+            }
+            Event::Ping(data) => write_frame(&mut out, PING, ACK, 0, &data)?,
+            Event::WindowUpdate(0, increment) => {
+                window += increment as i64;
+                for subscriber in &mut subscribers {
+                    subscriber.send(&mut out, &mut window)?;
+                }
+            }
+            Event::WindowUpdate(id, increment) => {
+                if let Some(s) = subscribers.iter_mut().find(|s| s.id == id) {
+                    s.window += increment as i64;
+                    s.send(&mut out, &mut window)?;
+                }
+            }
+            // what the peer sends is consumed at once, its windows are given back as much
+            Event::Data(id, len, end) => {
+                let increment = len.to_be_bytes();
+                write_frame(&mut out, WINDOW_UPDATE, 0, 0, &increment)?;
+                if !end {
+                    write_frame(&mut out, WINDOW_UPDATE, 0, id, &increment)?;
+                }
+                out.flush()?;
+            }
+            Event::Reset(id) => subscribers.retain(|s| s.id != id),
+            Event::Closed => break,
+        }
+    }
+    Ok(())
+}
+
+/// A gRPC message as sent on a stream: uncompressed, prefixed with its length.
+fn length_prefixed(message: &[u8]) -> Vec<u8> {
+    let mut data = Vec::with_capacity(message.len() + 5);
+    data.push(0);
+    data.extend_from_slice(&(message.len() as u32).to_be_bytes());
+    data.extend_from_slice(message);
+    data
+}
+
+/// Read the frames of the peer until the connection is closed, turning them into events.
+fn read_frames(input: &mut impl Read, tx: &Sender<Event>) -> std::io::Result<()> {
+    let invalid = |what| std::io::Error::new(std::io::ErrorKind::InvalidData, what);
+    let mut decoder = hpack::Decoder::default();
+    // header block being received, with its stream
+    let mut block: Option<(u32, Vec<u8>)> = None;
+    loop {
+        let mut header = [0u8; 9];
+        input.read_exact(&mut header)?;
+        let len = u32::from_be_bytes([0, header[0], header[1], header[2]]) as usize;
+        let (kind, flags) = (header[3], header[4]);
+        let id = u32::from_be_bytes([header[5], header[6], header[7], header[8]]) & 0x7fff_ffff;
+        let mut payload = vec![0u8; len];
+        input.read_exact(&mut payload)?;
+
+        let event = match kind {
+            HEADERS => {
+                let mut fragment = &payload[..];
+                if flags & PADDED != 0 {
+                    let pad = *fragment.first().ok_or_else(|| invalid("invalid padding"))?;
+                    fragment = fragment
+                        .get(1..fragment.len().saturating_sub(pad as usize))
+                        .ok_or_else(|| invalid("invalid padding"))?;
+                }
+                if flags & PRIORITY != 0 {
+                    fragment = fragment
+                        .get(5..)
+                        .ok_or_else(|| invalid("invalid priority"))?;
+                }
+                block = Some((id, fragment.to_vec()));
+                None
+            }
+            CONTINUATION => {
+                if let Some((_, fragment)) = block.as_mut() {
+                    fragment.extend_from_slice(&payload);
+                }
+                None
+            }
+            SETTINGS if flags & ACK == 0 => {
+                let initial = payload.chunks_exact(6).rev().find_map(|setting| {
+                    (u16::from_be_bytes([setting[0], setting[1]]) == SETTINGS_INITIAL_WINDOW_SIZE)
+                        .then(|| {
+                            u32::from_be_bytes([setting[2], setting[3], setting[4], setting[5]])
+                        })
+                });
+                Some(Event::Settings(initial))
+            }
+            PING if flags & ACK == 0 => {
+                let data = payload.try_into().map_err(|_| invalid("invalid ping"))?;
+                Some(Event::Ping(data))
+            }
+            WINDOW_UPDATE => {
+                let increment = payload
+                    .get(..4)
+                    .map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]) & 0x7fff_ffff)
+                    .ok_or_else(|| invalid("invalid window update"))?;
+                Some(Event::WindowUpdate(id, increment))
+            }
+            DATA if len > 0 => Some(Event::Data(id, len as u32, flags & END_STREAM != 0)),
+            RST_STREAM => Some(Event::Reset(id)),
+            GOAWAY => return Ok(()),
+            // the content of the request message, priorities and the acknowledgments are of no use
+            _ => None,
+        };
+        if let Some(event) = event {
+            let _ = tx.send(event);
+        }
+
+        if matches!(kind, HEADERS | CONTINUATION) && flags & END_HEADERS != 0 {
+            if let Some((id, fragment)) = block.take() {
+                let headers = decoder
+                    .decode(&fragment)
+                    .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+                let path = headers
+                    .into_iter()
+                    .find(|(name, _)| name == ":path")
+                    .map(|(_, value)| value)
+                    .unwrap_or_default();
+                let _ = tx.send(Event::Call(id, path));
+            }
+        }
+    }
+}
+
+fn write_frame(
+    out: &mut impl Write,
+    kind: u8,
+    flags: u8,
+    id: u32,
+    payload: &[u8],
+) -> std::io::Result<()> {
+    let len = (payload.len() as u32).to_be_bytes();
+    out.write_all(&[len[1], len[2], len[3], kind, flags])?;
+    out.write_all(&id.to_be_bytes())?;
+    out.write_all(payload)
+}
+
+/// Encode header fields as literals without indexing, which needs no state.
+fn encode_headers(headers: &[(&str, &str)]) -> Vec<u8> {
+    let mut block = Vec::new();
+    for (name, value) in headers {
+        block.push(0);
+        for s in [name, value] {
+            // lengths below 127 fit the 7-bit prefix, as all the headers sent
+            block.push(s.len() as u8);
+            block.extend_from_slice(s.as_bytes());
+        }
+    }
+    block
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Read a frame: (type, flags, stream, payload).
+    fn read_frame(input: &mut impl Read) -> (u8, u8, u32, Vec<u8>) {
+        let mut header = [0u8; 9];
+        input.read_exact(&mut header).unwrap();
+        let len = u32::from_be_bytes([0, header[0], header[1], header[2]]) as usize;
+        let mut payload = vec![0u8; len];
+        input.read_exact(&mut payload).unwrap();
+        let id = u32::from_be_bytes([header[5], header[6], header[7], header[8]]);
+        (header[3], header[4], id, payload)
+    }
+
+    #[test]
+    fn test_subscribe() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = spawn(listener);
+        server.publish(b"first".to_vec());
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.write_all(PREFACE).unwrap();
+        write_frame(&mut stream, SETTINGS, 0, 0, &[]).unwrap();
+        let headers = [(":method", "POST"), (":path", SUBSCRIBE)];
+        write_frame(
+            &mut stream,
+            HEADERS,
+            END_HEADERS,
+            1,
+            &encode_headers(&headers),
+        )
+        .unwrap();
+        let headers = [(":method", "POST"), (":path", "/dwatch.Watch/Other")];
+        let block = encode_headers(&headers);
+        write_frame(&mut stream, HEADERS, END_HEADERS, 3, &block).unwrap();
+
+        assert_eq!(read_frame(&mut stream).0, SETTINGS);
+        let (kind, flags, id, _) = read_frame(&mut stream);
+        assert_eq!((kind, flags, id), (SETTINGS, ACK, 0));
+        let (kind, _, id, block) = read_frame(&mut stream);
+        assert_eq!((kind, id), (HEADERS, 1));
+        let headers = hpack::Decoder::default().decode(&block).unwrap();
+        assert_eq!(headers[0], (":status".into(), "200".into()));
+        assert_eq!(
+            read_frame(&mut stream),
+            (DATA, 0, 1, b"\x00\x00\x00\x00\x05first".to_vec())
+        );
+        let (kind, flags, id, block) = read_frame(&mut stream);
+        assert_eq!((kind, flags, id), (HEADERS, END_HEADERS | END_STREAM, 3));
+        let headers = hpack::Decoder::default().decode(&block).unwrap();
+        assert_eq!(headers[2], ("grpc-status".into(), "12".into()));
+
+        server.publish(b"second".to_vec());
+        assert_eq!(read_frame(&mut stream).3, b"\x00\x00\x00\x00\x06second");
+    }
+
+    #[test]
+    fn test_flow_control() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = spawn(listener);
+        let frame: Vec<u8> = (0..100_000).map(|i| i as u8).collect();
+        server.publish(frame.clone());
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.write_all(PREFACE).unwrap();
+        write_frame(&mut stream, SETTINGS, 0, 0, &[]).unwrap();
+        let headers = [(":method", "POST"), (":path", SUBSCRIBE)];
+        let block = encode_headers(&headers);
+        write_frame(&mut stream, HEADERS, END_HEADERS, 1, &block).unwrap();
+        // the (empty) request message
+        write_frame(&mut stream, DATA, END_STREAM, 1, &[0; 5]).unwrap();
+
+        // the message is sent up to the default windows, and the request data given back
+        let mut data = Vec::new();
+        let mut updates = Vec::new();
+        while data.len() < DEFAULT_WINDOW as usize || updates.is_empty() {
+            match read_frame(&mut stream) {
+                (DATA, _, 1, payload) => {
+                    assert!(payload.len() <= MAX_FRAME);
+                    data.extend(payload);
+                }
+                (WINDOW_UPDATE, _, id, payload) => updates.push((id, payload)),
+                _ => {}
+            }
+        }
+        assert_eq!(data.len(), DEFAULT_WINDOW as usize);
+        assert_eq!(updates, [(0, 5u32.to_be_bytes().to_vec())]);
+        stream
+            .set_read_timeout(Some(Duration::from_millis(100)))
+            .unwrap();
+        assert!(stream.peek(&mut [0]).is_err());
+        stream.set_read_timeout(None).unwrap();
+
+        // and the rest once the windows open
+        for id in [0, 1] {
+            write_frame(&mut stream, WINDOW_UPDATE, 0, id, &40_000u32.to_be_bytes()).unwrap();
+        }
+        while data.len() < frame.len() + 5 {
+            let (kind, _, id, payload) = read_frame(&mut stream);
+            assert_eq!((kind, id), (DATA, 1));
+            data.extend(payload);
+        }
+        assert_eq!(data, length_prefixed(&frame));
+    }
+}
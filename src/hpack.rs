@@ -0,0 +1,322 @@
+//! HPACK header decoding (RFC 7541), for the HTTP/2 requests of `--grpc`.
+
+use std::collections::{HashMap, VecDeque};
+
+use anyhow::{anyhow, Result};
+
+/// Size of the dynamic table unless the peer sets another.
+const DEFAULT_TABLE_SIZE: usize = 4096;
+
+lazy_static! {
+    /// Octets by (bit length, Huffman code).
+    static ref HUFFMAN_SYMBOLS: HashMap<(u8, u32), u16> = HUFFMAN_LENGTHS
+        .iter()
+        .zip(HUFFMAN_CODES)
+        .enumerate()
+        .map(|(sym, (&len, code))| ((len, code), sym as u16))
+        .collect();
+}
+
+/// Decoder of the header blocks of a connection, which share the dynamic table.
+pub struct Decoder {
+    /// Most recent entry first.
+    table: VecDeque<(String, String)>,
+    size: usize,
+    max_size: usize,
+}
+
+impl Default for Decoder {
+    fn default() -> Self {
+        Self {
+            table: VecDeque::new(),
+            size: 0,
+            max_size: DEFAULT_TABLE_SIZE,
+        }
+    }
+}
+
+impl Decoder {
+    /// Decode a header block into its (name, value) fields.
+    pub fn decode(&mut self, mut block: &[u8]) -> Result<Vec<(String, String)>> {
+        let mut headers = Vec::new();
+        while let Some(&first) = block.first() {
+            if first & 0x80 != 0 {
+                // indexed field
+                let index = integer(&mut block, 7)?;
+                headers.push(self.entry(index)?);
+            } else if first & 0xe0 == 0x20 {
+                // dynamic table size update
+                self.max_size = integer(&mut block, 5)?;
+                self.evict(0);
+            } else {
+                // literal field, with incremental indexing or not
+                let indexing = first & 0x40 != 0;
+                let index = integer(&mut block, if indexing { 6 } else { 4 })?;
+                let name = match index {
+                    0 => string(&mut block)?,
+                    index => self.entry(index)?.0,
+                };
+                let value = string(&mut block)?;
+                if indexing {
+                    self.insert(name.clone(), value.clone());
+                }
+                headers.push((name, value));
+            }
+        }
+        Ok(headers)
+    }
+
+    fn entry(&self, index: usize) -> Result<(String, String)> {
+        let entry = match index.checked_sub(1) {
+            Some(i) if i < STATIC_TABLE.len() => {
+                let (name, value) = STATIC_TABLE[i];
+                Some((name.to_owned(), value.to_owned()))
+            }
+            Some(i) => self.table.get(i - STATIC_TABLE.len()).cloned(),
+            None => None,
+        };
+        entry.ok_or_else(|| anyhow!("invalid header index {}", index))
+    }
+
+    fn insert(&mut self, name: String, value: String) {
+        let size = entry_size(&name, &value);
+        self.evict(size);
+        // an entry larger than the table empties it, and is not added
+        if size <= self.max_size {
+            self.size += size;
+            self.table.push_front((name, value));
+        }
+    }
+
+    /// Drop the oldest entries until there is room for an entry of the given size.
+    fn evict(&mut self, room: usize) {
+        while self.size + room > self.max_size {
+            match self.table.pop_back() {
+                Some((name, value)) => self.size -= entry_size(&name, &value),
+                None => break,
+            }
+        }
+    }
+}
+
+fn entry_size(name: &str, value: &str) -> usize {
+    name.len() + value.len() + 32
+}
+
+/// Read an integer with an N-bit prefix.
+fn integer(block: &mut &[u8], prefix: u8) -> Result<usize> {
+    let truncated = || anyhow!("truncated header block");
+    let mask = (1u16 << prefix) as usize - 1;
+    let (&first, rest) = block.split_first().ok_or_else(truncated)?;
+    *block = rest;
+    let mut value = first as usize & mask;
+    if value < mask {
+        return Ok(value);
+    }
+    let mut shift = 0;
+    loop {
+        let (&byte, rest) = block.split_first().ok_or_else(truncated)?;
+        *block = rest;
+        if shift > 28 {
+            return Err(anyhow!("header integer overflow"));
+        }
+        value += ((byte & 0x7f) as usize) << shift;
+        shift += 7;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+}
+
+/// Read a string literal, Huffman encoded or not.
+fn string(block: &mut &[u8]) -> Result<String> {
+    let huffman = block.first().is_some_and(|b| b & 0x80 != 0);
+    let len = integer(block, 7)?;
+    let bytes = block
+        .get(..len)
+        .ok_or_else(|| anyhow!("truncated header block"))?;
+    *block = &block[len..];
+    let bytes = if huffman {
+        huffman_decode(bytes)?
+    } else {
+        bytes.to_vec()
+    };
+    Ok(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+fn huffman_decode(bytes: &[u8]) -> Result<Vec<u8>> {
+    let mut out = Vec::with_capacity(bytes.len() * 8 / 5);
+    let (mut code, mut len) = (0u32, 0u8);
+    for bit in bytes
+        .iter()
+        .flat_map(|byte| (0..8).rev().map(move |i| (byte >> i) as u32 & 1))
+    {
+        code = code << 1 | bit;
+        len += 1;
+        match HUFFMAN_SYMBOLS.get(&(len, code)) {
+            Some(256) => return Err(anyhow!("end of string in Huffman data")),
+            Some(&sym) => {
+                out.push(sym as u8);
+                (code, len) = (0, 0);
+            }
+            None if len > 30 => return Err(anyhow!("invalid Huffman data")),
+            None => {}
+        }
+    }
+    // the padding is made of the most significant bits of the end of string, all ones
+    if len > 7 || code != (1 << len) - 1 {
+        return Err(anyhow!("invalid Huffman padding"));
+    }
+    Ok(out)
+}
+
+/// Huffman codes of the 256 octets and of the end of string (RFC 7541, appendix B).
+const HUFFMAN_CODES: [u32; 257] = [
+    0x1ff8, 0x7fffd8, 0xfffffe2, 0xfffffe3, 0xfffffe4, 0xfffffe5, 0xfffffe6, 0xfffffe7, 0xfffffe8,
+    0xffffea, 0x3ffffffc, 0xfffffe9, 0xfffffea, 0x3ffffffd, 0xfffffeb, 0xfffffec, 0xfffffed,
+    0xfffffee, 0xfffffef, 0xffffff0, 0xffffff1, 0xffffff2, 0x3ffffffe, 0xffffff3, 0xffffff4,
+    0xffffff5, 0xffffff6, 0xffffff7, 0xffffff8, 0xffffff9, 0xffffffa, 0xffffffb, 0x14, 0x3f8,
+    0x3f9, 0xffa, 0x1ff9, 0x15, 0xf8, 0x7fa, 0x3fa, 0x3fb, 0xf9, 0x7fb, 0xfa, 0x16, 0x17, 0x18,
+    0x0, 0x1, 0x2, 0x19, 0x1a, 0x1b, 0x1c, 0x1d, 0x1e, 0x1f, 0x5c, 0xfb, 0x7ffc, 0x20, 0xffb,
+    0x3fc, 0x1ffa, 0x21, 0x5d, 0x5e, 0x5f, 0x60, 0x61, 0x62, 0x63, 0x64, 0x65, 0x66, 0x67, 0x68,
+    0x69, 0x6a, 0x6b, 0x6c, 0x6d, 0x6e, 0x6f, 0x70, 0x71, 0x72, 0xfc, 0x73, 0xfd, 0x1ffb, 0x7fff0,
+    0x1ffc, 0x3ffc, 0x22, 0x7ffd, 0x3, 0x23, 0x4, 0x24, 0x5, 0x25, 0x26, 0x27, 0x6, 0x74, 0x75,
+    0x28, 0x29, 0x2a, 0x7, 0x2b, 0x76, 0x2c, 0x8, 0x9, 0x2d, 0x77, 0x78, 0x79, 0x7a, 0x7b, 0x7ffe,
+    0x7fc, 0x3ffd, 0x1ffd, 0xffffffc, 0xfffe6, 0x3fffd2, 0xfffe7, 0xfffe8, 0x3fffd3, 0x3fffd4,
+    0x3fffd5, 0x7fffd9, 0x3fffd6, 0x7fffda, 0x7fffdb, 0x7fffdc, 0x7fffdd, 0x7fffde, 0xffffeb,
+    0x7fffdf, 0xffffec, 0xffffed, 0x3fffd7, 0x7fffe0, 0xffffee, 0x7fffe1, 0x7fffe2, 0x7fffe3,
+    0x7fffe4, 0x1fffdc, 0x3fffd8, 0x7fffe5, 0x3fffd9, 0x7fffe6, 0x7fffe7, 0xffffef, 0x3fffda,
+    0x1fffdd, 0xfffe9, 0x3fffdb, 0x3fffdc, 0x7fffe8, 0x7fffe9, 0x1fffde, 0x7fffea, 0x3fffdd,
+    0x3fffde, 0xfffff0, 0x1fffdf, 0x3fffdf, 0x7fffeb, 0x7fffec, 0x1fffe0, 0x1fffe1, 0x3fffe0,
+    0x1fffe2, 0x7fffed, 0x3fffe1, 0x7fffee, 0x7fffef, 0xfffea, 0x3fffe2, 0x3fffe3, 0x3fffe4,
+    0x7ffff0, 0x3fffe5, 0x3fffe6, 0x7ffff1, 0x3ffffe0, 0x3ffffe1, 0xfffeb, 0x7fff1, 0x3fffe7,
+    0x7ffff2, 0x3fffe8, 0x1ffffec, 0x3ffffe2, 0x3ffffe3, 0x3ffffe4, 0x7ffffde, 0x7ffffdf,
+    0x3ffffe5, 0xfffff1, 0x1ffffed, 0x7fff2, 0x1fffe3, 0x3ffffe6, 0x7ffffe0, 0x7ffffe1, 0x3ffffe7,
+    0x7ffffe2, 0xfffff2, 0x1fffe4, 0x1fffe5, 0x3ffffe8, 0x3ffffe9, 0xffffffd, 0x7ffffe3, 0x7ffffe4,
+    0x7ffffe5, 0xfffec, 0xfffff3, 0xfffed, 0x1fffe6, 0x3fffe9, 0x1fffe7, 0x1fffe8, 0x7ffff3,
+    0x3fffea, 0x3fffeb, 0x1ffffee, 0x1ffffef, 0xfffff4, 0xfffff5, 0x3ffffea, 0x7ffff4, 0x3ffffeb,
+    0x7ffffe6, 0x3ffffec, 0x3ffffed, 0x7ffffe7, 0x7ffffe8, 0x7ffffe9, 0x7ffffea, 0x7ffffeb,
+    0xffffffe, 0x7ffffec, 0x7ffffed, 0x7ffffee, 0x7ffffef, 0x7fffff0, 0x3ffffee, 0x3fffffff,
+];
+
+/// Bit lengths of the Huffman codes.
+const HUFFMAN_LENGTHS: [u8; 257] = [
+    13, 23, 28, 28, 28, 28, 28, 28, 28, 24, 30, 28, 28, 30, 28, 28, 28, 28, 28, 28, 28, 28, 30, 28,
+    28, 28, 28, 28, 28, 28, 28, 28, 6, 10, 10, 12, 13, 6, 8, 11, 10, 10, 8, 11, 8, 6, 6, 6, 5, 5,
+    5, 6, 6, 6, 6, 6, 6, 6, 7, 8, 15, 6, 12, 10, 13, 6, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7,
+    7, 7, 7, 7, 7, 7, 7, 7, 8, 7, 8, 13, 19, 13, 14, 6, 15, 5, 6, 5, 6, 5, 6, 6, 6, 5, 7, 7, 6, 6,
+    6, 5, 6, 7, 6, 5, 5, 6, 7, 7, 7, 7, 7, 15, 11, 14, 13, 28, 20, 22, 20, 20, 22, 22, 22, 23, 22,
+    23, 23, 23, 23, 23, 24, 23, 24, 24, 22, 23, 24, 23, 23, 23, 23, 21, 22, 23, 22, 23, 23, 24, 22,
+    21, 20, 22, 22, 23, 23, 21, 23, 22, 22, 24, 21, 22, 23, 23, 21, 21, 22, 21, 23, 22, 23, 23, 20,
+    22, 22, 22, 23, 22, 22, 23, 26, 26, 20, 19, 22, 23, 22, 25, 26, 26, 26, 27, 27, 26, 24, 25, 19,
+    21, 26, 27, 27, 26, 27, 24, 21, 21, 26, 26, 28, 27, 27, 27, 20, 24, 20, 21, 22, 21, 21, 23, 22,
+    22, 25, 25, 24, 24, 26, 23, 26, 27, 26, 26, 27, 27, 27, 27, 27, 28, 27, 27, 27, 27, 27, 26, 30,
+];
+
+/// Header fields of the static table (RFC 7541, appendix A), from index 1.
+const STATIC_TABLE: [(&str, &str); 61] = [
+    (":authority", ""),
+    (":method", "GET"),
+    (":method", "POST"),
+    (":path", "/"),
+    (":path", "/index.html"),
+    (":scheme", "http"),
+    (":scheme", "https"),
+    (":status", "200"),
+    (":status", "204"),
+    (":status", "206"),
+    (":status", "304"),
+    (":status", "400"),
+    (":status", "404"),
+    (":status", "500"),
+    ("accept-charset", ""),
+    ("accept-encoding", "gzip, deflate"),
+    ("accept-language", ""),
+    ("accept-ranges", ""),
+    ("accept", ""),
+    ("access-control-allow-origin", ""),
+    ("age", ""),
+    ("allow", ""),
+    ("authorization", ""),
+    ("cache-control", ""),
+    ("content-disposition", ""),
+    ("content-encoding", ""),
+    ("content-language", ""),
+    ("content-length", ""),
+    ("content-location", ""),
+    ("content-range", ""),
+    ("content-type", ""),
+    ("cookie", ""),
+    ("date", ""),
+    ("etag", ""),
+    ("expect", ""),
+    ("expires", ""),
+    ("from", ""),
+    ("host", ""),
+    ("if-match", ""),
+    ("if-modified-since", ""),
+    ("if-none-match", ""),
+    ("if-range", ""),
+    ("if-unmodified-since", ""),
+    ("last-modified", ""),
+    ("link", ""),
+    ("location", ""),
+    ("max-forwards", ""),
+    ("proxy-authenticate", ""),
+    ("proxy-authorization", ""),
+    ("range", ""),
+    ("referer", ""),
+    ("refresh", ""),
+    ("retry-after", ""),
+    ("server", ""),
+    ("set-cookie", ""),
+    ("strict-transport-security", ""),
+    ("transfer-encoding", ""),
+    ("user-agent", ""),
+    ("vary", ""),
+    ("via", ""),
+    ("www-authenticate", ""),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_integer() {
+        // RFC 7541, C.1.2: 1337 with a 5-bit prefix
+        let mut block: &[u8] = &[0x1f, 0x9a, 0x0a, 0xff];
+        assert_eq!(integer(&mut block, 5).unwrap(), 1337);
+        assert_eq!(block, [0xff]);
+        assert!(integer(&mut &[0x1f, 0x9a][..], 5).is_err());
+    }
+
+    #[test]
+    fn test_decode() {
+        // RFC 7541, C.4: requests with Huffman coding, sharing the dynamic table
+        let mut decoder = Decoder::default();
+        let first = [
+            0x82, 0x86, 0x84, 0x41, 0x8c, 0xf1, 0xe3, 0xc2, 0xe5, 0xf2, 0x3a, 0x6b, 0xa0, 0xab,
+            0x90, 0xf4, 0xff,
+        ];
+        assert_eq!(
+            decoder.decode(&first).unwrap(),
+            [
+                (":method", "GET"),
+                (":scheme", "http"),
+                (":path", "/"),
+                (":authority", "www.example.com"),
+            ]
+            .map(|(n, v)| (n.to_owned(), v.to_owned()))
+        );
+        let second = [
+            0x82, 0x86, 0x84, 0xbe, 0x58, 0x86, 0xa8, 0xeb, 0x10, 0x64, 0x9c, 0xbf,
+        ];
+        let headers = decoder.decode(&second).unwrap();
+        assert_eq!(headers[3], (":authority".into(), "www.example.com".into()));
+        assert_eq!(headers[4], ("cache-control".into(), "no-cache".into()));
+        assert_eq!(decoder.size, 110);
+
+        assert!(decoder.decode(&[0xff, 0x00]).is_err());
+    }
+}
@@ -0,0 +1,266 @@
+//! Minimal HTTP/1.1 client for the `--url` sources and the `--webhook`, `--influx` and
+//! `--remote-write` sinks. Only plain
+//! `http://` is supported, no TLS implementation being available to dwatch.
+
+use std::{
+    io::{Read, Write},
+    net::{TcpStream, ToSocketAddrs},
+    time::Duration,
+};
+
+use anyhow::{anyhow, Context as _, Result};
+
+/// Timeout of connections and reads when no `--timeout` is set, so that a stalled server cannot
+/// hold a source forever.
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Whether a command is the URL of a source fetched by dwatch rather than run.
+pub fn is_url(cmd: &str) -> bool {
+    (cmd.starts_with("http://") || cmd.starts_with("https://"))
+        && !cmd.contains(char::is_whitespace)
+}
+
+/// Split an `http://host[:port][/path]` URL into (host, port, path).
+fn parse(url: &str) -> Result<(&str, u16, &str)> {
+    if url.starts_with("https://") {
+        return Err(anyhow!("https is not supported, only http"));
+    }
+    let rest = url
+        .strip_prefix("http://")
+        .ok_or_else(|| anyhow!("invalid URL {}", url))?;
+    let (authority, path) = match rest.find('/') {
+        Some(idx) => (&rest[..idx], &rest[idx..]),
+        None => (rest, "/"),
+    };
+    let (host, port) = match authority.rsplit_once(':') {
+        Some((host, port)) if !port.contains(']') => (
+            host,
+            port.parse()
+                .map_err(|_| anyhow!("invalid port in URL {}", url))?,
+        ),
+        _ => (authority, 80),
+    };
+    if host.is_empty() {
+        return Err(anyhow!("invalid URL {}", url));
+    }
+    Ok((host, port, path))
+}
+
+/// Check that a URL can be requested, i.e. that it is a valid `http://` URL.
+pub fn check(url: &str) -> Result<()> {
+    parse(url).map(|_| ())
+}
+
+/// Decode a body sent with `Transfer-Encoding: chunked`.
+fn dechunk(mut data: &[u8]) -> Result<Vec<u8>> {
+    let mut body = Vec::new();
+    loop {
+        let eol = data
+            .windows(2)
+            .position(|w| w == b"\r\n")
+            .ok_or_else(|| anyhow!("truncated chunked body"))?;
+        let line = String::from_utf8_lossy(&data[..eol]);
+        let size = usize::from_str_radix(line.split(';').next().unwrap_or("").trim(), 16)
+            .map_err(|_| anyhow!("invalid chunk size '{}'", line))?;
+        data = &data[eol + 2..];
+        if size == 0 {
+            return Ok(body);
+        }
+        let chunk = data
+            .get(..size)
+            .ok_or_else(|| anyhow!("truncated chunked body"))?;
+        body.extend_from_slice(chunk);
+        data = data.get(size + 2..).unwrap_or_default();
+    }
+}
+
+/// Split a response into its body, failing on a status other than 2xx.
+fn body(response: &[u8]) -> Result<Vec<u8>> {
+    let end = response
+        .windows(4)
+        .position(|w| w == b"\r\n\r\n")
+        .ok_or_else(|| anyhow!("invalid HTTP response"))?;
+    let head = String::from_utf8_lossy(&response[..end]);
+    let mut lines = head.split("\r\n");
+    let status = lines.next().unwrap_or_default();
+    let code = status.split_whitespace().nth(1).unwrap_or_default();
+    if !code.starts_with('2') {
+        return Err(anyhow!("{}", status));
+    }
+
+    let chunked = lines.any(|l| {
+        l.split_once(':').is_some_and(|(name, value)| {
+            name.trim().eq_ignore_ascii_case("transfer-encoding")
+                && value.trim().eq_ignore_ascii_case("chunked")
+        })
+    });
+    let body = &response[end + 4..];
+    if chunked {
+        dechunk(body)
+    } else {
+        Ok(body.to_vec())
+    }
+}
+
+/// Fetch the URL with a GET request, returning the body of the response.
+pub fn get(url: &str, timeout: Option<Duration>) -> Result<Vec<u8>> {
+    request("GET", url, None, &[], timeout)
+}
+
+/// Send a JSON document to the URL with a POST request, returning the body of the response.
+pub fn post_json(url: &str, json: &str, timeout: Option<Duration>) -> Result<Vec<u8>> {
+    post(url, "application/json", json.as_bytes(), &[], timeout)
+}
+
+/// Send a body of the given content type to the URL with a POST request, along with extra
+/// `Name: value` headers (which may override the content type), returning the body of the
+/// response.
+pub fn post(
+    url: &str,
+    content_type: &str,
+    body: &[u8],
+    headers: &[String],
+    timeout: Option<Duration>,
+) -> Result<Vec<u8>> {
+    request("POST", url, Some((content_type, body)), headers, timeout)
+}
+
+/// Check that a header is of the `Name: value` form.
+pub fn check_header(header: &str) -> Result<()> {
+    match header.split_once(':') {
+        Some((name, _)) if !name.is_empty() && !name.contains(char::is_whitespace) => Ok(()),
+        _ => Err(anyhow!(
+            "invalid header '{}', expected 'Name: value'",
+            header
+        )),
+    }
+}
+
+/// Send a request, with a body given along with its content type.
+fn request(
+    method: &str,
+    url: &str,
+    content: Option<(&str, &[u8])>,
+    headers: &[String],
+    timeout: Option<Duration>,
+) -> Result<Vec<u8>> {
+    let timeout = timeout.unwrap_or(DEFAULT_TIMEOUT);
+    let (host, port, path) = parse(url)?;
+
+    let addr = (host.trim_matches(['[', ']']), port)
+        .to_socket_addrs()
+        .with_context(|| format!("failed to resolve {}", host))?
+        .next()
+        .ok_or_else(|| anyhow!("failed to resolve {}", host))?;
+    let mut stream = TcpStream::connect_timeout(&addr, timeout)
+        .with_context(|| format!("failed to connect to {}", url))?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+
+    let mut request = format!(
+        "{} {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: dwatch/{}\r\nAccept: */*\r\nConnection: close\r\n",
+        method,
+        path,
+        host,
+        env!("CARGO_PKG_VERSION")
+    )
+    .into_bytes();
+    let mut typed = false;
+    for header in headers {
+        typed |= header
+            .split_once(':')
+            .is_some_and(|(name, _)| name.eq_ignore_ascii_case("content-type"));
+        request.extend_from_slice(format!("{}\r\n", header.trim()).as_bytes());
+    }
+    match content {
+        Some((content_type, body)) => {
+            if !typed {
+                request.extend_from_slice(format!("Content-Type: {}\r\n", content_type).as_bytes());
+            }
+            request.extend_from_slice(format!("Content-Length: {}\r\n\r\n", body.len()).as_bytes());
+            request.extend_from_slice(body);
+        }
+        None => request.extend_from_slice(b"\r\n"),
+    }
+    stream.write_all(&request)?;
+    let mut response = Vec::new();
+    stream
+        .read_to_end(&mut response)
+        .with_context(|| format!("failed to read from {}", url))?;
+    body(&response).with_context(|| format!("{} {}", method, url))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(parse("http://localhost").unwrap(), ("localhost", 80, "/"));
+        assert_eq!(
+            parse("http://10.0.0.1:9100/metrics?x=1").unwrap(),
+            ("10.0.0.1", 9100, "/metrics?x=1")
+        );
+        assert_eq!(parse("http://[::1]:8080/").unwrap(), ("[::1]", 8080, "/"));
+        assert!(parse("https://example.com").is_err());
+        assert!(parse("http://host:port/").is_err());
+    }
+
+    #[test]
+    fn test_get() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/metrics", listener.local_addr().unwrap());
+        std::thread::spawn(move || {
+            for reply in [
+                "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n4\r\nrx 1\r\n3\r\n2\n\n\r\n0\r\n\r\n",
+                "HTTP/1.1 503 Service Unavailable\r\nContent-Length: 0\r\n\r\n",
+            ] {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut request = Vec::new();
+                let mut buf = [0u8; 1024];
+                while !request.ends_with(b"\r\n\r\n") {
+                    let n = stream.read(&mut buf).unwrap();
+                    request.extend_from_slice(&buf[..n]);
+                }
+                stream.write_all(reply.as_bytes()).unwrap();
+            }
+        });
+
+        assert_eq!(get(&url, None).unwrap(), b"rx 12\n\n");
+        let err = get(&url, None).unwrap_err();
+        assert!(format!("{:#}", err).contains("503 Service Unavailable"));
+    }
+
+    #[test]
+    fn test_check_header() {
+        assert!(check_header("Authorization: Bearer x").is_ok());
+        assert!(check_header("Authorization").is_err());
+        assert!(check_header("Bad Name: x").is_err());
+    }
+
+    #[test]
+    fn test_post_json() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = Vec::new();
+            let mut buf = [0u8; 1024];
+            while !request.ends_with(b"}") {
+                let n = stream.read(&mut buf).unwrap();
+                request.extend_from_slice(&buf[..n]);
+            }
+            stream
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok")
+                .unwrap();
+            String::from_utf8(request).unwrap()
+        });
+
+        assert_eq!(post_json(&url, r#"{"text":"x"}"#, None).unwrap(), b"ok");
+        let request = server.join().unwrap();
+        assert!(request.starts_with("POST /hook HTTP/1.1\r\n"));
+        assert!(request.contains("Content-Type: application/json\r\nContent-Length: 12\r\n"));
+        assert!(request.ends_with("\r\n\r\n{\"text\":\"x\"}"));
+    }
+}
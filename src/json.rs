@@ -0,0 +1,61 @@
+//! Just enough JSON to write the documents dwatch emits.
+
+/// Quote a string as a JSON string.
+pub fn string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// Render a number, `null` for the values JSON cannot represent (infinities and NaN).
+pub fn number(n: f64) -> String {
+    if n.is_finite() {
+        n.to_string()
+    } else {
+        "null".to_owned()
+    }
+}
+
+/// Render an object from its (key, rendered value) members.
+pub fn object<'a>(members: impl IntoIterator<Item = (&'a str, String)>) -> String {
+    let members: Vec<String> = members
+        .into_iter()
+        .map(|(key, value)| format!("{}:{}", string(key), value))
+        .collect();
+    format!("{{{}}}", members.join(","))
+}
+
+/// Render an array from its rendered items.
+pub fn array(items: impl IntoIterator<Item = String>) -> String {
+    format!("[{}]", items.into_iter().collect::<Vec<_>>().join(","))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_json() {
+        assert_eq!(string("a \"b\"\\\n\x01"), r#""a \"b\"\\\n\u0001""#);
+        assert_eq!(number(1.5), "1.5");
+        assert_eq!(number(f64::NAN), "null");
+        assert_eq!(
+            object([("text", string("x")), ("value", number(3.0))]),
+            r#"{"text":"x","value":3}"#
+        );
+        assert_eq!(array([number(1.0), string("a")]), r#"[1,"a"]"#);
+        assert_eq!(array([]), "[]");
+    }
+}
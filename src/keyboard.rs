@@ -0,0 +1,313 @@
+use std::{
+    io::{Read, Write},
+    sync::atomic::Ordering,
+    sync::Arc,
+};
+
+use crate::control::{Control, Prompt};
+
+/// Keeps the terminal in non-canonical, no-echo mode for as long as it lives, so that single
+/// key presses are delivered immediately. Signal generation (Ctrl-C, Ctrl-\, Ctrl-Z) is left on.
+pub struct RawMode {
+    orig: libc::termios,
+}
+
+impl RawMode {
+    pub fn enable() -> Option<Self> {
+        // SAFETY: tcgetattr/tcsetattr only read and write the termios structure passed in.
+        unsafe {
+            let mut orig: libc::termios = std::mem::zeroed();
+            if libc::tcgetattr(libc::STDIN_FILENO, &mut orig) != 0 {
+                return None;
+            }
+            let mut raw = orig;
+            raw.c_lflag &= !(libc::ICANON | libc::ECHO);
+            // reads return after at most 100ms, so that a lone ESC can be told apart from a
+            // key sequence and the input thread notices termination
+            raw.c_cc[libc::VMIN] = 0;
+            raw.c_cc[libc::VTIME] = 1;
+            if libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &raw) != 0 {
+                return None;
+            }
+            Some(Self { orig })
+        }
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        // SAFETY: restores the attributes saved by enable.
+        unsafe {
+            libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &self.orig);
+        }
+    }
+}
+
+/// Stop dwatch as the default Ctrl-Z action would, handing a usable terminal back to the shell
+/// meanwhile: raw mode and mouse reporting are turned off until the process is continued, and the
+/// screen is repainted from scratch. The standard output is locked throughout, so that a frame
+/// being rendered cannot interleave with the sequence.
+pub fn suspend(control: &Control, raw: bool, mouse: bool) {
+    let mut out = std::io::stdout().lock();
+    // SAFETY: tcgetattr/tcsetattr only read and write the termios structure passed in, raise
+    // stops the whole process until SIGCONT.
+    unsafe {
+        let mut current: libc::termios = std::mem::zeroed();
+        let raw = raw && libc::tcgetattr(libc::STDIN_FILENO, &mut current) == 0;
+        if raw {
+            let mut cooked = current;
+            cooked.c_lflag |= libc::ICANON | libc::ECHO;
+            libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &cooked);
+        }
+        if mouse {
+            let _ = write!(out, "\x1b[?1006l\x1b[?1000l");
+        }
+        let _ = writeln!(out);
+        let _ = out.flush();
+
+        libc::raise(libc::SIGSTOP);
+
+        if raw {
+            libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &current);
+        }
+        if mouse {
+            let _ = write!(out, "\x1b[?1000h\x1b[?1006h");
+        }
+    }
+    let _ = write!(out, "{}", ansi_escapes::ClearScreen);
+    let _ = out.flush();
+    drop(out);
+    control.refresh();
+}
+
+/// Enables mouse reporting (SGR encoding) for as long as it lives.
+pub struct MouseMode;
+
+impl MouseMode {
+    pub fn enable() -> Self {
+        print!("\x1b[?1000h\x1b[?1006h");
+        let _ = std::io::stdout().flush();
+        Self
+    }
+}
+
+impl Drop for MouseMode {
+    fn drop(&mut self) {
+        print!("\x1b[?1006l\x1b[?1000l");
+        let _ = std::io::stdout().flush();
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Key {
+    Char(char),
+    Up,
+    Down,
+    Left,
+    Right,
+    Tab,
+    BackTab,
+    Enter,
+    Backspace,
+    Esc,
+    /// Left button press at a 0-based (row, column) cell.
+    Click(usize, usize),
+}
+
+fn read_byte(input: &mut impl Read) -> std::io::Result<Option<u8>> {
+    let mut buf = [0u8; 1];
+    match input.read(&mut buf)? {
+        0 => Ok(None),
+        _ => Ok(Some(buf[0])),
+    }
+}
+
+/// Decode the remainder of an SGR mouse report: `button;column;row` followed by `M` (press) or
+/// `m` (release), with 1-based coordinates.
+fn read_mouse(input: &mut impl Read) -> std::io::Result<Option<Key>> {
+    let mut report = String::new();
+    let kind = loop {
+        match read_byte(input)? {
+            Some(c @ (b'M' | b'm')) => break c,
+            Some(c) if c.is_ascii_digit() || c == b';' => report.push(c as char),
+            _ => return Ok(None),
+        }
+    };
+
+    let fields: Vec<usize> = report.split(';').filter_map(|f| f.parse().ok()).collect();
+    match (kind, fields.as_slice()) {
+        (b'M', [0, col, row]) if *col > 0 && *row > 0 => Ok(Some(Key::Click(row - 1, col - 1))),
+        _ => Ok(None),
+    }
+}
+
+/// Decode the next key press, None if no input arrived within the read timeout.
+fn next_key(input: &mut impl Read) -> std::io::Result<Option<Key>> {
+    let key = match read_byte(input)? {
+        None => return Ok(None),
+        Some(0x1b) => match read_byte(input)? {
+            None => Key::Esc,
+            Some(b'[') | Some(b'O') => match read_byte(input)? {
+                Some(b'A') => Key::Up,
+                Some(b'B') => Key::Down,
+                Some(b'C') => Key::Right,
+                Some(b'D') => Key::Left,
+                Some(b'Z') => Key::BackTab,
+                Some(b'<') => return read_mouse(input),
+                _ => return Ok(None),
+            },
+            Some(_) => return Ok(None),
+        },
+        Some(b'\t') => Key::Tab,
+        Some(b'\r') | Some(b'\n') => Key::Enter,
+        Some(0x7f) | Some(0x08) => Key::Backspace,
+        Some(c) if c.is_ascii_graphic() || c == b' ' => Key::Char(c as char),
+        Some(_) => return Ok(None),
+    };
+    Ok(Some(key))
+}
+
+fn handle(control: &Control, key: Key) {
+    let mut prompt = control.prompt.lock().unwrap();
+
+    if let Some(open) = prompt.as_mut() {
+        match key {
+            Key::Char(c) => open.text_mut().push(c),
+            Key::Backspace => {
+                open.text_mut().pop();
+            }
+            Key::Enter => match prompt.take() {
+                Some(Prompt::Style(name)) => {
+                    control.select_style(name.trim());
+                }
+                Some(Prompt::Command(idx, command)) => {
+                    control.set_command(idx, &command);
+                }
+                None => {}
+            },
+            Key::Esc => *prompt = None,
+            _ => return,
+        }
+        // what is typed shows right away, whenever the next frame is due
+        drop(prompt);
+        control.repaint();
+        return;
+    }
+
+    drop(prompt);
+
+    match key {
+        Key::Right | Key::Down | Key::Tab => control.focus_step(1),
+        Key::Left | Key::Up | Key::BackTab => control.focus_step(-1),
+        Key::Char('s') => control.cycle_style(1),
+        Key::Char('S') => control.cycle_style(-1),
+        Key::Char(' ') => control.toggle_selection(),
+        Key::Char('l') => control.toggle_line_mode(),
+        Key::Char('/') => {
+            *control.prompt.lock().unwrap() = Some(Prompt::Style(String::new()));
+            control.repaint();
+        }
+        Key::Char('e') => {
+            control.edit_command();
+            control.repaint();
+        }
+        Key::Char('p') => control.toggle_pause(),
+        Key::Char('r') | Key::Enter => control.refresh(),
+        Key::Char('z') => control.reset_deltas(),
+        Key::Esc => control.drop_focus(),
+        Key::Char('+') => control.adjust_interval(1),
+        Key::Char('-') => control.adjust_interval(-1),
+        Key::Char('.') => control.seek(1),
+        Key::Char(',') => control.seek(-1),
+        Key::Char('>') => control.seek(10),
+        Key::Char('<') => control.seek(-10),
+        Key::Char(']') => control.adjust_speed(2.0),
+        Key::Char('[') => control.adjust_speed(0.5),
+        Key::Char('q') => control.quit(),
+        Key::Click(row, col) => {
+            control.focus_at(row, col);
+        }
+        _ => {}
+    }
+}
+
+/// Read key presses from the terminal until dwatch terminates.
+pub fn spawn(control: Arc<Control>) {
+    std::thread::spawn(move || {
+        let mut stdin = std::io::stdin().lock();
+        while !control.term.load(Ordering::Relaxed) {
+            match next_key(&mut stdin) {
+                Ok(Some(key)) => handle(&control, key),
+                Ok(None) => {}
+                Err(_) => break,
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_next_key() {
+        let mut input: &[u8] = b"s\x1b[A\x1b[D\x1bOC\t\x7f\r";
+        let mut keys = Vec::new();
+        while let Some(key) = next_key(&mut input).unwrap() {
+            keys.push(key);
+        }
+        assert_eq!(
+            keys,
+            vec![
+                Key::Char('s'),
+                Key::Up,
+                Key::Left,
+                Key::Right,
+                Key::Tab,
+                Key::Backspace,
+                Key::Enter
+            ]
+        );
+
+        let mut input: &[u8] = b"\x1b[<0;12;3M\x1b[<0;12;3m";
+        assert_eq!(next_key(&mut input).unwrap(), Some(Key::Click(2, 11)));
+        assert_eq!(next_key(&mut input).unwrap(), None);
+
+        let mut input: &[u8] = b"\x1b";
+        assert_eq!(next_key(&mut input).unwrap(), Some(Key::Esc));
+    }
+
+    #[test]
+    fn test_style_prompt() {
+        let control = Control::new(0);
+        for key in [Key::Char('/'), Key::Char('d'), Key::Char('e')] {
+            handle(&control, key);
+        }
+        assert_eq!(
+            *control.prompt.lock().unwrap(),
+            Some(Prompt::Style("de".into()))
+        );
+        // each key typed wakes up the rendering loop to repaint, rather than refresh
+        let deadline = Instant::now() + Duration::from_secs(60);
+        assert!(!control.wait(deadline));
+        assert!(control.repaint.swap(false, Ordering::Relaxed));
+        handle(&control, Key::Backspace);
+        assert!(control.repaint.swap(false, Ordering::Relaxed));
+        handle(&control, Key::Char('e'));
+        handle(&control, Key::Esc);
+        assert!(control.repaint.swap(false, Ordering::Relaxed));
+        assert_eq!(*control.prompt.lock().unwrap(), None);
+
+        handle(&control, Key::Char('/'));
+        assert!(control.repaint.swap(false, Ordering::Relaxed));
+        handle(&control, Key::Char('d'));
+        handle(&control, Key::Char('e'));
+        for c in "lta".chars() {
+            handle(&control, Key::Char(c));
+        }
+        handle(&control, Key::Enter);
+        assert_eq!(*control.prompt.lock().unwrap(), None);
+        assert_eq!(control.style_name(), "delta");
+    }
+}
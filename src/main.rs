@@ -1,62 +1,367 @@
+mod alert;
+mod completions;
+mod condition;
+mod config;
+mod control;
+mod ctl;
 mod dwatch;
+mod filewatch;
+mod graphite;
+mod grpc;
+mod hpack;
+mod http;
+mod json;
+mod keyboard;
+mod mqtt;
+mod notify;
 mod options;
+mod pattern;
+mod process;
+mod prometheus;
+mod protobuf;
+mod pty;
 mod ranges;
+mod remote;
+mod session;
+mod shell;
+mod statsd;
+mod theme;
+mod verbose;
+mod web;
 
-use anyhow::Result;
-use clap::Parser;
-use options::Options;
+use anyhow::{anyhow, Result};
+use clap::{error::ErrorKind, CommandFactory, FromArgMatches};
+use config::{Config, StyleMap};
+use control::Control;
+use options::{Action, Cli, Completion};
+use session::Player;
 use signal_hook::consts::signal::*;
 use signal_hook::consts::TERM_SIGNALS;
 use signal_hook::iterator::exfiltrator::SignalOnly;
 use signal_hook::iterator::SignalsInfo;
-use std::sync::atomic::AtomicBool;
-use std::sync::atomic::AtomicUsize;
-use std::sync::atomic::Ordering;
+use std::io::IsTerminal;
+use std::process::ExitCode;
 use std::sync::Arc;
+use std::time::Duration;
 
 #[macro_use]
 extern crate lazy_static;
 
-fn main() -> Result<()> {
-    let mut opts = Options::parse();
-    if opts.commands.is_empty() {
-        return Ok(());
+/// Real-time signal `SIGRTMIN+n`, where the platform provides them (`kill -s RTMIN+1 <pid>`).
+#[cfg(target_os = "linux")]
+fn rt_signal(n: i32) -> Option<i32> {
+    Some(libc::SIGRTMIN() + n)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn rt_signal(_: i32) -> Option<i32> {
+    None
+}
+
+/// The commands of a profile, a non-empty array of strings.
+fn profile_commands(profile: &Config) -> Result<Vec<String>> {
+    let commands: Option<Vec<String>> = match profile.get("commands") {
+        Some(config::Value::Array(items)) => items
+            .iter()
+            .map(|item| item.as_str().map(str::to_owned))
+            .collect(),
+        Some(config::Value::String(cmd)) => Some(vec![cmd.clone()]),
+        _ => None,
+    };
+    commands
+        .filter(|c| !c.is_empty())
+        .ok_or_else(|| anyhow!("'commands' must be a command or an array of commands"))
+}
+
+/// The styles and the color themes, for `dwatch styles`.
+fn list_styles() {
+    println!("styles:");
+    for idx in 0..dwatch::WriterBox::count() {
+        println!("  {}", dwatch::WriterBox::name(idx));
+    }
+    println!("themes:");
+    for name in theme::Theme::names() {
+        println!("  {}", name);
+    }
+}
+
+/// The names completed by the completion scripts, for `dwatch __complete`.
+fn list_names(names: Completion) -> Result<()> {
+    match names {
+        Completion::Styles => (0..dwatch::WriterBox::count())
+            .for_each(|idx| println!("{}", dwatch::WriterBox::name(idx))),
+        Completion::Themes => theme::Theme::names()
+            .iter()
+            .for_each(|name| println!("{}", name)),
+        Completion::Profiles => Config::load(None)?
+            .profiles()
+            .iter()
+            .for_each(|name| println!("{}", name)),
+    }
+    Ok(())
+}
+
+fn main() -> Result<ExitCode> {
+    let matches = Cli::command().get_matches();
+    let cli = Cli::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    let mut opts = match cli.action {
+        None => cli.options,
+        Some(Action::Styles) => {
+            list_styles();
+            return Ok(ExitCode::SUCCESS);
+        }
+        Some(Action::Completions { shell }) => {
+            print!("{}", completions::script(shell));
+            return Ok(ExitCode::SUCCESS);
+        }
+        Some(Action::Complete { names }) => {
+            list_names(names)?;
+            return Ok(ExitCode::SUCCESS);
+        }
+        Some(action) => action.into_options().expect("an action watching commands"),
+    };
+    // the options of an action are matched by its subcommand
+    opts.set_given(matches.subcommand().map_or(&matches, |(_, args)| args));
+    if opts.verbose {
+        verbose::init(opts.verbose_log.as_deref())?;
+    }
+
+    let config = Config::load(opts.config.as_deref())?;
+    // a bare dwatch watches the default profile, that named by `default-profile` or `default`
+    if opts.commands.is_empty()
+        && opts.files.is_empty()
+        && opts.urls.is_empty()
+        && !opts.stdin
+        && opts.replay.is_none()
+        && opts.commands_file.is_none()
+        && opts.cmds.is_empty()
+    {
+        let name = config
+            .get("default-profile")
+            .and_then(config::Value::as_str)
+            .unwrap_or("default");
+        if config.profile(name).is_none() {
+            Cli::command()
+                .error(
+                    ErrorKind::MissingRequiredArgument,
+                    "no command to watch: give one, or the commands to watch by default in a \
+                     [profiles.default] section of the configuration",
+                )
+                .exit();
+        }
+        opts.commands = vec![format!("@{}", name)];
+    }
+    // `@name` watches the commands of a profile, whose options come before the general ones
+    if let Some(name) = opts.commands.first().and_then(|c| c.strip_prefix('@')) {
+        let profile = config
+            .profile(name)
+            .ok_or_else(|| anyhow!("no profile '{}' in the configuration", name))?;
+        if opts.commands.len() > 1 {
+            return Err(anyhow!(
+                "profile '{}' cannot be combined with commands",
+                name
+            ));
+        }
+        opts.commands =
+            profile_commands(&profile).map_err(|e| anyhow!("profile '{}': {}", name, e))?;
+        opts.multiple_commands = true;
+        opts.merge(&profile)?;
+    }
+    // each --cmd is a command of its own, whatever it holds; those of --cmd and of a file come
+    // with their labels (the others headed by their command line) and intervals
+    let specs = match &opts.commands_file {
+        Some(path) => options::read_commands_file(path)?,
+        None => std::mem::take(&mut opts.cmds),
+    };
+    if !specs.is_empty() {
+        if opts.labels.is_empty() && specs.iter().any(|s| s.label.is_some()) {
+            opts.labels = specs
+                .iter()
+                .map(|s| s.label.clone().unwrap_or_else(|| s.command.clone()))
+                .collect();
+        }
+        opts.intervals = specs.iter().map(|s| s.interval).collect();
+        opts.commands = specs.into_iter().map(|s| s.command).collect();
+        opts.multiple_commands = true;
+    }
+    // an export writes what the session holds, the outputs configured for watching have no part
+    if !opts.export {
+        opts.merge(&config)?;
+    }
+    for url in &opts.webhooks {
+        http::check(url).map_err(|e| anyhow!("--webhook {}: {}", url, e))?;
+    }
+    if let Some(url) = opts.influx.as_deref().filter(|t| http::is_url(t)) {
+        http::check(url).map_err(|e| anyhow!("--influx {}: {}", url, e))?;
+    }
+    if let Some(url) = &opts.remote_write {
+        http::check(url).map_err(|e| anyhow!("--remote-write {}: {}", url, e))?;
+    }
+    for header in &opts.remote_write_headers {
+        http::check_header(header).map_err(|e| anyhow!("--remote-write-header: {}", e))?;
     }
 
-    let term = Arc::new(AtomicBool::new(false));
-    let style = Arc::new(AtomicUsize::new(
-        opts.style
-            .as_ref()
-            .and_then(|name| dwatch::WriterBox::index(name))
-            .unwrap_or(0),
-    ));
+    // headless frames are logged as plain text, one below the other
+    if opts.headless {
+        opts.no_clear = true;
+        opts.no_color = true;
+    }
 
-    let cloned_term = Arc::clone(&term);
-    let cloned_style = Arc::clone(&style);
+    // without a shell the arguments are quoted, so that they are split back as they were given
+    if !opts.multiple_commands && !opts.commands.is_empty() {
+        opts.commands = vec![if opts.exec {
+            shell::join(&opts.commands)
+        } else {
+            opts.commands.join(" ")
+        }];
+    }
+    // each command is run in every pod or container given, on every host given
+    if !opts.pods.is_empty() {
+        opts.commands = opts
+            .commands
+            .iter()
+            .flat_map(|cmd| opts.pods.iter().map(|pod| remote::kubectl(pod, cmd)))
+            .collect();
+    }
+    if !opts.containers.is_empty() {
+        opts.commands = opts
+            .commands
+            .iter()
+            .flat_map(|cmd| opts.containers.iter().map(|c| remote::docker(c, cmd)))
+            .collect();
+    }
+    if !opts.hosts.is_empty() {
+        opts.commands = opts
+            .commands
+            .iter()
+            .flat_map(|cmd| opts.hosts.iter().map(|host| remote::ssh(host, cmd)))
+            .collect();
+    }
+    // the interval of a command holds for all its targets
+    let targets = [&opts.pods, &opts.containers, &opts.hosts]
+        .iter()
+        .map(|t| t.len().max(1))
+        .product();
+    opts.intervals = opts
+        .intervals
+        .iter()
+        .flat_map(|&interval| std::iter::repeat_n(interval, targets))
+        .collect();
+    // files are watched as `< PATH` commands, read by dwatch itself, and URLs as they are
+    let files = opts.files.iter().map(|f| format!("< {}", f.display()));
+    opts.commands.extend(files);
+    opts.commands.extend(opts.urls.iter().cloned());
+    if opts.stdin {
+        opts.commands.push(dwatch::STDIN.to_owned());
+    }
+    // a replay shows the commands recorded, none is run
+    let player = match &opts.replay {
+        Some(path) => {
+            let player = Player::new(session::load(path)?);
+            opts.commands = player.commands();
+            Some(player)
+        }
+        None => None,
+    };
+
+    // a dry run shows how the output of the commands is parsed, without watching them
+    if opts.dry_run {
+        if player.is_some() {
+            return Err(anyhow!(
+                "--dry-run runs the commands, a replay has none to run"
+            ));
+        }
+        dwatch::dry_run(&opts)?;
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    let style_map = StyleMap::load()?;
+    let alerts = opts.alert.iter().cloned().chain(alert::load()?).collect();
+
+    let control = Arc::new(
+        Control::new(
+            opts.style
+                .as_deref()
+                .or((opts.differences || opts.headless).then_some("abs-delta"))
+                .or_else(|| style_map.get(&dwatch::command_names(&opts.labels, &opts.commands)))
+                .and_then(dwatch::WriterBox::index)
+                .unwrap_or(0),
+        )
+        .with_commands(opts.commands.clone())
+        .with_focus_timeout(opts.focus_timeout.unwrap_or(control::FOCUS_LIFETIME_LIMIT))
+        .with_interval(opts.interval.unwrap_or(Duration::from_secs(1)))
+        .with_speed(opts.speed.unwrap_or(1.0)),
+    );
+
+    if opts.on_change {
+        for path in opts.commands.iter().filter_map(|c| dwatch::file_source(c)) {
+            filewatch::spawn(path.into(), Arc::clone(&control));
+        }
+    }
+
+    // the keyboard drives focus and styles when attached to a terminal, signals remain available
+    let raw_mode = if std::io::stdin().is_terminal() && !opts.export && !opts.headless {
+        let raw_mode = keyboard::RawMode::enable();
+        keyboard::spawn(Arc::clone(&control));
+        raw_mode
+    } else {
+        None
+    };
+    let mouse_mode = (opts.mouse && raw_mode.is_some()).then(keyboard::MouseMode::enable);
+
+    // scripts drive dwatch through the socket for as long as it runs
+    let _ctl = match &opts.control_socket {
+        Some(path) => {
+            let path = path
+                .clone()
+                .or_else(ctl::default_path)
+                .ok_or_else(|| anyhow!("--control-socket: no cache directory, pass a path"))?;
+            Some(ctl::serve(&path, Arc::clone(&control))?)
+        }
+        None => None,
+    };
+
+    let cloned_control = Arc::clone(&control);
+    let (raw, mouse) = (raw_mode.is_some(), mouse_mode.is_some());
 
     std::thread::spawn(move || {
-        let mut sigs = vec![SIGTSTP, SIGWINCH];
+        let focus_next = rt_signal(0);
+        let focus_prev = rt_signal(1);
+        let pause = rt_signal(2);
+        let reset = rt_signal(3);
+        let slower = rt_signal(4);
+        let faster = rt_signal(5);
+        let mut sigs = vec![SIGTSTP, SIGWINCH, SIGUSR1, SIGUSR2];
 
         sigs.extend(TERM_SIGNALS);
+        sigs.extend(focus_next);
+        sigs.extend(focus_prev);
+        sigs.extend(pause);
+        sigs.extend(reset);
+        sigs.extend(slower);
+        sigs.extend(faster);
         let mut signals = SignalsInfo::<SignalOnly>::new(&sigs).unwrap();
 
         for info in &mut signals {
             match info {
-                SIGTERM | SIGINT | SIGTSTP => {
-                    cloned_term.store(true, Ordering::Relaxed);
+                SIGTERM | SIGINT => {
+                    cloned_control.quit();
                     break;
                 }
-                SIGQUIT => {
-                    cloned_style.fetch_add(1, Ordering::Relaxed);
-                }
+                SIGTSTP => keyboard::suspend(&cloned_control, raw, mouse),
+                SIGQUIT => cloned_control.cycle_style(1),
+                SIGUSR1 => cloned_control.refresh(),
+                SIGUSR2 => cloned_control.cycle_style(-1),
+                sig if Some(sig) == focus_next => cloned_control.focus_step(1),
+                sig if Some(sig) == focus_prev => cloned_control.focus_step(-1),
+                sig if Some(sig) == pause => cloned_control.toggle_pause(),
+                sig if Some(sig) == reset => cloned_control.reset_deltas(),
+                sig if Some(sig) == slower => cloned_control.adjust_interval(1),
+                sig if Some(sig) == faster => cloned_control.adjust_interval(-1),
                 _ => {}
             }
         }
     });
 
-    if !opts.multiple_commands {
-        opts.commands = vec![opts.commands.join(" ")];
-    }
-
-    dwatch::run(opts, term, style)
+    dwatch::run(opts, control, style_map, alerts, player).map(ExitCode::from)
 }
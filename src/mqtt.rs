@@ -0,0 +1,180 @@
+//! MQTT publisher for `--mqtt`, speaking just enough MQTT 3.1.1 to publish messages with QoS 0.
+
+use std::{
+    io::{Read, Write},
+    net::{TcpStream, ToSocketAddrs},
+    sync::mpsc::{self, Sender},
+    time::Duration,
+};
+
+use anyhow::{anyhow, Result};
+
+/// Time given to the broker to accept a connection or a message.
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Handle on the thread publishing to the broker, so that a slow or absent broker never holds the
+/// frames.
+pub struct Client {
+    tx: Sender<Vec<String>>,
+}
+
+impl Client {
+    /// Queue messages for publishing. The messages of frames the broker cannot be reached for are
+    /// dropped, and the connection is opened again for the next ones.
+    pub fn publish(&self, messages: Vec<String>) {
+        let _ = self.tx.send(messages);
+    }
+}
+
+/// Split a `host[:port]/topic` target into (address, topic), port 1883 by default.
+fn parse(target: &str) -> Result<(String, String)> {
+    let invalid = || {
+        anyhow!(
+            "invalid MQTT target '{}', expected host[:port]/topic",
+            target
+        )
+    };
+    let (host, topic) = target.split_once('/').ok_or_else(invalid)?;
+    if host.is_empty() || topic.is_empty() {
+        return Err(invalid());
+    }
+    let addr = if host.rsplit_once(':').is_some_and(|(_, p)| !p.contains(']')) {
+        host.to_owned()
+    } else {
+        format!("{}:1883", host)
+    };
+    Ok((addr, topic.to_owned()))
+}
+
+/// Start publishing to the topic of a `host[:port]/topic` target.
+pub fn connect(target: &str) -> Result<Client> {
+    let (addr, topic) = parse(target)?;
+    let broker = addr
+        .to_socket_addrs()
+        .map_err(|e| anyhow!("mqtt {}: {}", addr, e))?
+        .next()
+        .ok_or_else(|| anyhow!("mqtt {}: no address", addr))?;
+    let client_id = format!("dwatch-{}", std::process::id());
+
+    let (tx, rx) = mpsc::channel::<Vec<String>>();
+    std::thread::spawn(move || {
+        let mut stream: Option<TcpStream> = None;
+        for messages in rx {
+            if stream.is_none() {
+                stream = open(&broker, &client_id).ok();
+            }
+            if let Some(s) = stream.as_mut() {
+                let sent = messages
+                    .iter()
+                    .try_for_each(|m| s.write_all(&publish_packet(&topic, m.as_bytes())));
+                if sent.is_err() {
+                    stream = None;
+                }
+            }
+        }
+    });
+    Ok(Client { tx })
+}
+
+/// Connect to the broker, with a clean session and no keep-alive.
+fn open(broker: &std::net::SocketAddr, client_id: &str) -> std::io::Result<TcpStream> {
+    let mut stream = TcpStream::connect_timeout(broker, TIMEOUT)?;
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+    stream.write_all(&connect_packet(client_id))?;
+
+    let mut connack = [0u8; 4];
+    stream.read_exact(&mut connack)?;
+    if connack[0] != 0x20 || connack[3] != 0 {
+        return Err(std::io::Error::other("connection refused by the broker"));
+    }
+    Ok(stream)
+}
+
+/// Append the variable byte integer encoding of the remaining length of a packet.
+fn encode_length(out: &mut Vec<u8>, mut len: usize) {
+    loop {
+        let byte = (len % 128) as u8;
+        len /= 128;
+        if len == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+/// Append a length-prefixed string.
+fn encode_string(out: &mut Vec<u8>, s: &[u8]) {
+    out.extend_from_slice(&(s.len() as u16).to_be_bytes());
+    out.extend_from_slice(s);
+}
+
+/// A packet with its fixed header.
+fn packet(kind: u8, body: &[u8]) -> Vec<u8> {
+    let mut out = vec![kind];
+    encode_length(&mut out, body.len());
+    out.extend_from_slice(body);
+    out
+}
+
+fn connect_packet(client_id: &str) -> Vec<u8> {
+    let mut body = Vec::new();
+    encode_string(&mut body, b"MQTT");
+    // protocol level 4 (3.1.1), clean session, keep-alive disabled
+    body.extend_from_slice(&[4, 0x02, 0, 0]);
+    encode_string(&mut body, client_id.as_bytes());
+    packet(0x10, &body)
+}
+
+fn publish_packet(topic: &str, payload: &[u8]) -> Vec<u8> {
+    let mut body = Vec::with_capacity(topic.len() + payload.len() + 2);
+    encode_string(&mut body, topic.as_bytes());
+    body.extend_from_slice(payload);
+    packet(0x30, &body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            parse("localhost/dwatch/values").unwrap(),
+            ("localhost:1883".to_owned(), "dwatch/values".to_owned())
+        );
+        assert_eq!(parse("10.0.0.1:1884/x").unwrap().0, "10.0.0.1:1884");
+        assert!(parse("localhost").is_err());
+        assert!(parse("localhost/").is_err());
+    }
+
+    #[test]
+    fn test_encode_length() {
+        let mut out = Vec::new();
+        encode_length(&mut out, 321);
+        assert_eq!(out, [0xc1, 0x02]);
+    }
+
+    #[test]
+    fn test_publish() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let client = connect(&format!("{}/dwatch", addr)).unwrap();
+        client.publish(vec!["{}".to_owned()]);
+
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut connect = [0u8; 2];
+        stream.read_exact(&mut connect).unwrap();
+        assert_eq!(connect[0], 0x10);
+        let mut rest = vec![0u8; connect[1] as usize];
+        stream.read_exact(&mut rest).unwrap();
+        assert!(rest.starts_with(b"\x00\x04MQTT\x04\x02"));
+        stream.write_all(&[0x20, 2, 0, 0]).unwrap();
+
+        let mut publish = [0u8; 12];
+        stream.read_exact(&mut publish).unwrap();
+        assert_eq!(&publish, b"\x30\x0a\x00\x06dwatch{}");
+    }
+}
@@ -0,0 +1,18 @@
+//! Desktop notifications, sent through `notify-send` (libnotify, over D-Bus) so that no
+//! notification daemon is needed when they are not requested.
+
+use std::process::{Command, Stdio};
+
+/// Post a notification in the background. Failures (no `notify-send`, no session bus) are
+/// ignored: notifications are a convenience, dwatch keeps running without them.
+pub fn send(summary: &str, body: &str) {
+    let child = Command::new("notify-send")
+        .args(["--app-name=dwatch", summary, body])
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn();
+    if let Ok(mut child) = child {
+        std::thread::spawn(move || child.wait());
+    }
+}
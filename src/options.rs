@@ -1,25 +1,1457 @@
-use clap::Parser;
+use std::{
+    collections::BTreeSet,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::{anyhow, Context as _, Result};
+use clap::{
+    builder::BoolishValueParser, parser::ValueSource, ArgMatches, Parser, Subcommand, ValueEnum,
+};
+
+use crate::alert::Alert;
+use crate::condition::Condition;
+use crate::config::{Config, Value};
+use crate::process::Retry;
+
+/// Parse `N[:delay]`, the number of retries of a failed command and the delay in seconds before
+/// each of them (1 by default).
+pub fn parse_retry(s: &str) -> Result<Retry, String> {
+    let (attempts, delay) = s.split_once(':').unwrap_or((s, "1"));
+    let attempts = attempts
+        .parse()
+        .map_err(|_| format!("invalid number of retries '{}'", attempts))?;
+    let delay = delay
+        .parse::<f64>()
+        .ok()
+        .and_then(|d| Duration::try_from_secs_f64(d).ok())
+        .ok_or_else(|| format!("invalid retry delay '{}'", delay))?;
+    Ok(Retry { attempts, delay })
+}
+
+/// Parse a duration given in seconds (possibly fractional) or with a unit, e.g. `0.2`, `250ms`,
+/// `2s` or `1m`. Durations are counted in milliseconds (e.g. the interval), a shorter one is
+/// rejected rather than taken for 0.
+pub fn parse_duration(s: &str) -> Result<Duration, String> {
+    let (number, scale) = [("ms", 0.001), ("s", 1.0), ("m", 60.0), ("h", 3600.0)]
+        .iter()
+        .find_map(|(unit, scale)| s.strip_suffix(unit).map(|n| (n, *scale)))
+        .unwrap_or((s, 1.0));
+    number
+        .trim()
+        .parse::<f64>()
+        .ok()
+        .and_then(|n| Duration::try_from_secs_f64(n * scale).ok())
+        .filter(|d| *d >= Duration::from_millis(1))
+        .ok_or_else(|| format!("invalid duration '{}' (1ms at least)", s))
+}
+
+/// Parse a timeout, a duration as with `parse_duration` or 0 for none.
+pub fn parse_timeout(s: &str) -> Result<Duration, String> {
+    match s.trim().parse::<f64>() {
+        Ok(0.0) => Ok(Duration::ZERO),
+        _ => parse_duration(s),
+    }
+}
+
+/// A command as declared with `--cmd` or on a line of a `--commands-file`, with its own label and
+/// interval.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CommandSpec {
+    pub command: String,
+    pub label: Option<String>,
+    pub interval: Option<Duration>,
+}
+
+/// Split the label off `LABEL=COMMAND`, e.g. `rx/tx=ip -s link`. A label holds no space nor shell
+/// syntax, so that `awk -v n=1 ...` is a command, though `LANG=C df` would be labelled `LANG`
+/// (`env LANG=C df` is not).
+pub fn split_label(s: &str) -> (Option<&str>, &str) {
+    match s.split_once('=') {
+        Some((label, command))
+            if !label.is_empty()
+                && !label.contains(|c: char| c.is_whitespace() || "'\"$`|&;<>()".contains(c)) =>
+        {
+            (Some(label), command.trim_start())
+        }
+        _ => (None, s),
+    }
+}
+
+/// Parse `[INTERVAL:][LABEL=]COMMAND`, e.g. `10s:temp=sensors` (see `split_label`).
+pub fn parse_command(s: &str) -> Result<CommandSpec, String> {
+    let s = s.trim();
+    let (interval, rest) = match s.split_once(':') {
+        Some((interval, rest)) => match parse_duration(interval) {
+            Ok(interval) => (Some(interval), rest.trim_start()),
+            Err(_) => (None, s),
+        },
+        None => (None, s),
+    };
+    let (label, command) = split_label(rest);
+    if command.is_empty() {
+        return Err(format!("no command in '{}'", s));
+    }
+    Ok(CommandSpec {
+        command: command.to_owned(),
+        label: label.map(str::to_owned),
+        interval,
+    })
+}
+
+/// Read the commands of a `--commands-file`, one per line, blank lines and `#` comments aside.
+pub fn read_commands_file(path: &Path) -> Result<Vec<CommandSpec>> {
+    let text =
+        std::fs::read_to_string(path).with_context(|| format!("cannot read {}", path.display()))?;
+    let specs = text
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty() && !line.trim_start().starts_with('#'))
+        .map(|(idx, line)| {
+            parse_command(line).map_err(|e| anyhow!("{}:{}: {}", path.display(), idx + 1, e))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    if specs.is_empty() {
+        return Err(anyhow!("no command in {}", path.display()));
+    }
+    Ok(specs)
+}
+
+/// When the output is colored.
+#[derive(ValueEnum, Clone, Copy, Default, Debug, PartialEq)]
+pub enum ColorMode {
+    /// unless NO_COLOR is set
+    #[default]
+    Auto,
+    /// even if NO_COLOR is set
+    Always,
+    /// as with --no-color
+    Never,
+}
+
+/// Time base used to express rates.
+#[derive(ValueEnum, Clone, Copy, Default, Debug, PartialEq)]
+pub enum RateUnit {
+    /// per second
+    #[default]
+    S,
+    /// per minute
+    M,
+    /// per hour
+    H,
+}
+
+impl RateUnit {
+    pub fn as_secs_f64(&self) -> f64 {
+        match self {
+            RateUnit::S => 1.0,
+            RateUnit::M => 60.0,
+            RateUnit::H => 3600.0,
+        }
+    }
+
+    pub fn suffix(&self) -> &'static str {
+        match self {
+            RateUnit::S => "s",
+            RateUnit::M => "m",
+            RateUnit::H => "h",
+        }
+    }
+}
+
+/// What to do when a command is still running at the next refresh.
+#[derive(ValueEnum, Clone, Copy, Default, Debug, PartialEq)]
+pub enum Overlap {
+    /// wait for it to complete before running it again
+    #[default]
+    Queue,
+    /// let it complete without running it again, its output is shown at a later refresh
+    Skip,
+    /// kill it and run the command again
+    Kill,
+    /// run the command again alongside it, the latest output completed is shown
+    Concurrent,
+}
+
+/// How the frames are output.
+#[derive(ValueEnum, Clone, Copy, Default, Debug, PartialEq)]
+pub enum OutputFormat {
+    /// rendered on the terminal
+    #[default]
+    Tui,
+    /// one JSON object per number and frame, one per line
+    Ndjson,
+}
+
+/// Format of `dwatch export`.
+#[derive(ValueEnum, Clone, Copy, Default, Debug, PartialEq)]
+pub enum ExportFormat {
+    /// the records of `--output ndjson`
+    #[default]
+    Ndjson,
+    /// the rows of `--log-csv`
+    Csv,
+}
+
+/// Shells `dwatch completions` writes a script for.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+/// Names completed by the scripts of `dwatch completions`, which depend on the configuration.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum Completion {
+    Styles,
+    Themes,
+    Profiles,
+}
+
+// The command line: `dwatch [OPTIONS] COMMAND...` watches the commands, as `dwatch run` does.
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None, args_conflicts_with_subcommands = true)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub action: Option<Action>,
+
+    #[command(flatten)]
+    pub options: Options,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Action {
+    /// Watch commands (the default)
+    Run(Options),
+    /// Watch commands, recording their raw output at every frame to a session file
+    Record {
+        #[arg(value_name = "FILE", help = "Session file, replaced if it exists")]
+        file: PathBuf,
+
+        #[command(flatten)]
+        options: Options,
+    },
+    /// Play back a recorded session instead of running commands
+    Replay {
+        #[arg(value_name = "FILE")]
+        file: PathBuf,
+
+        #[arg(
+            long,
+            value_name = "FACTOR",
+            help = "Speed relative to the recorded pace, e.g. 2 or 0.5 [default: 1]"
+        )]
+        speed: Option<f64>,
+
+        #[command(flatten)]
+        options: Options,
+    },
+    /// List the styles and the color themes
+    Styles,
+    /// Write the numbers of a recorded session, with their deltas and rates, without rendering it
+    Export {
+        #[arg(value_name = "FILE")]
+        file: PathBuf,
+
+        #[arg(long, value_enum, default_value_t, help = "Format of the numbers")]
+        format: ExportFormat,
+
+        #[arg(
+            short,
+            long,
+            value_name = "FILE",
+            help = "Write to a file rather than to the standard output"
+        )]
+        output: Option<PathBuf>,
+
+        #[arg(long, value_enum, help = "Time base of the rates [default: s]")]
+        rate_unit: Option<RateUnit>,
+    },
+    /// Write a completion script for a shell, e.g. `dwatch completions bash > /etc/bash_completion.d/dwatch`
+    Completions {
+        #[arg(value_enum)]
+        shell: Shell,
+    },
+    /// List the names a completion script offers, one per line
+    #[command(name = "__complete", hide = true)]
+    Complete {
+        #[arg(value_enum)]
+        names: Completion,
+    },
+}
 
 #[derive(Parser, Default, Debug)]
-#[command(author, version, about, long_about = None)]
 pub struct Options {
     #[clap(short, long, help = "Exit after the specified number of seconds")]
     pub seconds: Option<u64>,
 
-    #[clap(short, long, help = "Suppress the banner")]
+    #[clap(
+        long,
+        value_name = "N",
+        help = "Exit after N iterations, leaving the last one on screen"
+    )]
+    pub count: Option<u64>,
+
+    #[clap(
+        short = 't',
+        long,
+        visible_alias = "no-title",
+        env = "DWATCH_NO_BANNER",
+        value_parser = BoolishValueParser::new(),
+        help = "Suppress the banner"
+    )]
     pub no_banner: bool,
 
+    #[clap(
+        long,
+        env = "DWATCH_NO_CLEAR",
+        value_parser = BoolishValueParser::new(),
+        help = "Print each frame below the previous one, under a timestamp header, rather than redrawing the screen"
+    )]
+    pub no_clear: bool,
+
+    #[clap(
+        short,
+        long,
+        help = "Log when and why frames are rendered, the runs of the commands, the lines whose deltas restart and the saving of the styles, to the standard error (see --verbose-log)"
+    )]
+    pub verbose: bool,
+
+    #[clap(
+        long,
+        value_name = "FILE",
+        requires = "verbose",
+        help = "With --verbose, append the log to a file rather than write it to the standard error"
+    )]
+    pub verbose_log: Option<PathBuf>,
+
+    #[clap(
+        long,
+        help = "Run the commands once, print their output with the numbers found underlined, along with their keys and names, and exit"
+    )]
+    pub dry_run: bool,
+
+    #[clap(
+        long,
+        help = "Produce no terminal output and take no keyboard input, e.g. to run under systemd or nohup (see --log)"
+    )]
+    pub headless: bool,
+
+    #[clap(
+        long,
+        value_name = "FILE",
+        requires = "headless",
+        help = "With --headless, append every frame to a file, as rendered with --no-clear --no-color (the abs-delta style unless --style is given)"
+    )]
+    pub log: Option<PathBuf>,
+
+    #[clap(
+        short,
+        long,
+        env = "DWATCH_QUIET",
+        value_parser = BoolishValueParser::new(),
+        help = "Leave the screen (or the --no-clear output, or the --log) alone when a frame is identical to the previous one"
+    )]
+    pub quiet: bool,
+
+    #[clap(
+        long,
+        value_name = "NAMES",
+        num_args = 0..=1,
+        require_equals = true,
+        value_delimiter = ',',
+        help = "Print a line per frame with the numbers that changed, or those given by name or key (e.g. --ticker=rx,tx), instead of rendering the frames"
+    )]
+    pub ticker: Option<Vec<String>>,
+
     #[clap(short, long, help = "Interpret arguments as multiple commands")]
     pub multiple_commands: bool,
 
-    #[clap(short, long, help = "Set the update interval in seconds")]
-    pub interval: Option<u64>,
+    #[clap(
+        long = "cmd",
+        value_name = "COMMAND",
+        value_parser = parse_command,
+        conflicts_with_all = ["commands", "commands_file"],
+        help = "Watch a command, given as [INTERVAL:][LABEL=]COMMAND (e.g. 10s:temp=sensors), can be repeated to watch several (arguments are then not taken as commands)"
+    )]
+    pub cmds: Vec<CommandSpec>,
+
+    #[clap(
+        long,
+        value_name = "FILE",
+        conflicts_with = "commands",
+        help = "Watch the commands of a file, one per line as [INTERVAL:][LABEL=]COMMAND (e.g. 10s:temp=sensors)"
+    )]
+    pub commands_file: Option<PathBuf>,
+
+    #[clap(
+        long = "label",
+        value_name = "LABEL",
+        help = "Header shown above the output of each command, in order, can be repeated"
+    )]
+    pub labels: Vec<String>,
+
+    #[clap(
+        long,
+        help = "Title shown in the banner instead of the commands, along with the outcome of their runs"
+    )]
+    pub title: Option<String>,
+
+    #[clap(
+        short = 'x',
+        long,
+        help = "Run the command directly rather than through 'sh -c' (arguments are not interpreted by a shell)"
+    )]
+    pub exec: bool,
+
+    #[clap(
+        long,
+        help = "Run commands in a pseudo-terminal with the size of the real one, as if run interactively"
+    )]
+    pub pty: bool,
+
+    #[clap(
+        long,
+        help = "Run multiple commands one after another, in the given order, rather than concurrently"
+    )]
+    pub sequential: bool,
+
+    #[clap(
+        long,
+        help = "Start the commands once and render a frame whenever their output pauses (e.g. 'vmstat 1'), rather than running them again at every interval"
+    )]
+    pub follow: bool,
+
+    #[clap(
+        long,
+        help = "Watch the standard input, rendering a frame for each record written by another program (see --record-separator)"
+    )]
+    pub stdin: bool,
+
+    #[clap(
+        long,
+        value_name = "LINE",
+        requires = "stdin",
+        allow_hyphen_values = true,
+        help = "Line ending each record read from the standard input, e.g. '---' [default: records end at pauses in the input]"
+    )]
+    pub record_separator: Option<String>,
+
+    #[clap(
+        long = "host",
+        value_name = "[USER@]HOST",
+        help = "Run the commands on a remote host over ssh, can be repeated to watch several hosts side by side"
+    )]
+    pub hosts: Vec<String>,
+
+    #[clap(
+        long = "docker",
+        value_name = "CONTAINER",
+        help = "Run the commands inside a container with docker exec, can be repeated to watch several containers side by side"
+    )]
+    pub containers: Vec<String>,
+
+    #[clap(
+        long = "pod",
+        value_name = "[NAMESPACE/]POD",
+        conflicts_with = "containers",
+        help = "Run the commands inside a Kubernetes pod with kubectl exec, can be repeated to watch several pods side by side"
+    )]
+    pub pods: Vec<String>,
+
+    #[clap(
+        long = "file",
+        value_name = "PATH",
+        help = "Watch the content of a file (e.g. /proc/net/dev), read directly rather than through a command, can be repeated"
+    )]
+    pub files: Vec<PathBuf>,
+
+    #[clap(
+        long = "url",
+        value_name = "URL",
+        help = "Watch the body of an http:// URL (e.g. metrics endpoints), fetched by dwatch at every interval, can be repeated"
+    )]
+    pub urls: Vec<String>,
+
+    #[clap(
+        long,
+        help = "Render a frame when a watched file changes rather than at every interval (files that do not report changes, e.g. in /proc, are checked at the interval)"
+    )]
+    pub on_change: bool,
+
+    #[clap(
+        short,
+        long,
+        visible_short_alias = 'n',
+        env = "DWATCH_INTERVAL",
+        value_parser = parse_duration,
+        help = "Set the update interval, in seconds (e.g. 0.5) or with a unit (e.g. 250ms)"
+    )]
+    pub interval: Option<Duration>,
+
+    #[clap(
+        long,
+        help = "Run one iteration at a time, waiting for a refresh (r key, Enter or SIGUSR1)"
+    )]
+    pub step: bool,
+
+    #[clap(
+        long,
+        value_name = "PATH",
+        num_args = 0..=1,
+        help = "Accept commands (pause, resume, refresh-now, set-interval, set-style, dump-state, ...) on a Unix socket, ~/.cache/dwatch/ctl.sock by default"
+    )]
+    pub control_socket: Option<Option<PathBuf>>,
+
+    #[clap(
+        long,
+        value_name = "DURATION",
+        env = "DWATCH_TIMEOUT",
+        value_parser = parse_timeout,
+        help = "Kill commands still running after the given time, in seconds (e.g. 0.5) or with a unit (e.g. 250ms) (0: never, the default)"
+    )]
+    pub timeout: Option<Duration>,
+
+    #[clap(
+        short,
+        long,
+        help = "Exit on the first command failure, leaving its output on screen, with its exit code"
+    )]
+    pub errexit: bool,
+
+    #[clap(
+        short = 'g',
+        long,
+        help = "Exit (successfully) as soon as the output of the commands changes"
+    )]
+    pub chgexit: bool,
+
+    #[clap(
+        long,
+        value_name = "CMD",
+        help = "Run a shell command whenever the output of the commands differs from the previous frame (see DWATCH_CHANGED and DWATCH_CHANGED_LINES)"
+    )]
+    pub on_diff: Option<String>,
+
+    #[clap(
+        short,
+        long,
+        help = "Ring the terminal bell when a number changes, or when an alert starts firing"
+    )]
+    pub beep: bool,
+
+    #[clap(
+        long,
+        value_name = "REGEX",
+        help = "Exit (successfully) once a line of output matches the extended regular expression"
+    )]
+    pub until: Option<String>,
+
+    #[clap(
+        long,
+        value_name = "CONDITION",
+        help = "Exit (successfully) once a condition holds for some number, e.g. 'rate > 1M' (value, delta or rate; >, >=, <, <=, ==, !=), can be repeated"
+    )]
+    pub exit_if: Vec<Condition>,
+
+    #[clap(
+        long,
+        value_name = "FILE",
+        help = "Compute the deltas against a saved output of the command rather than the previous frame, can be repeated for each command in turn"
+    )]
+    pub baseline: Vec<PathBuf>,
+
+    #[clap(
+        long,
+        value_name = "RULE",
+        help = "Highlight the numbers breaching a rule, e.g. 'rate > 1M' or 'col[3].rate > 1M' for the third number of each line only, optionally followed by 'for N' (frames) and 'cooldown DURATION', can be repeated"
+    )]
+    pub alert: Vec<Alert>,
+
+    #[clap(
+        long,
+        help = "Exit with status 2 if an alert fired at any time during the session"
+    )]
+    pub fail_on_alert: bool,
+
+    #[clap(
+        long,
+        help = "Exit with status 2 if an alert is firing on the last frame"
+    )]
+    pub fail_on_firing: bool,
+
+    #[clap(long, help = "Make the numbers breaching an alert blink")]
+    pub blink: bool,
+
+    #[clap(
+        long,
+        help = "Send a desktop notification (with notify-send) when an alert starts firing"
+    )]
+    pub notify: bool,
+
+    #[clap(
+        long,
+        value_name = "CMD",
+        help = "Run a shell command when an alert starts firing (see DWATCH_ALERT, DWATCH_VALUE and DWATCH_RATE)"
+    )]
+    pub on_alert: Option<String>,
+
+    #[clap(
+        long = "webhook",
+        value_name = "URL",
+        help = "Post a JSON document (also a valid Slack message) to an http:// URL when an alert starts or stops firing, can be repeated"
+    )]
+    pub webhooks: Vec<String>,
+
+    #[clap(
+        long,
+        value_name = "N[:DELAY]",
+        value_parser = parse_retry,
+        help = "Run a failed command again up to N times within a frame, DELAY seconds apart [default delay: 1]"
+    )]
+    pub retry: Option<Retry>,
+
+    #[clap(
+        long,
+        help = "Lengthen the interval when a command takes longer to run (commands taking most of the interval are flagged in the banner)"
+    )]
+    pub auto_interval: bool,
+
+    #[clap(
+        short,
+        long,
+        help = "Keep frames on a fixed schedule (start + k * interval), skipping the slots missed by slow runs rather than catching up"
+    )]
+    pub precise: bool,
+
+    #[clap(
+        long,
+        value_parser = parse_duration,
+        help = "Delay the first run by a random time up to the given duration, so that instances started together do not run their commands at the same instant"
+    )]
+    pub jitter: Option<Duration>,
+
+    #[clap(
+        long,
+        value_enum,
+        help = "What to do when a command is still running at the next refresh [default: queue]"
+    )]
+    pub overlap: Option<Overlap>,
+
+    #[clap(
+        long,
+        value_enum,
+        help = "Output the numbers of every frame as JSON records (ndjson) instead of rendering them [default: tui]"
+    )]
+    pub output: Option<OutputFormat>,
+
+    #[clap(
+        long,
+        value_name = "FILE",
+        help = "Append the records of --output to a file, while rendering the frames on the terminal"
+    )]
+    pub output_file: Option<PathBuf>,
+
+    #[clap(
+        long,
+        value_name = "FILE",
+        help = "Append the numbers of every frame (with their deltas and rates) to a CSV file, - for the standard output"
+    )]
+    pub log_csv: Option<PathBuf>,
+
+    #[clap(
+        long,
+        value_name = "FILE",
+        help = "On exit, write the recent samples of the values focused or selected during the session to a CSV file"
+    )]
+    pub history_csv: Option<PathBuf>,
+
+    #[clap(
+        long,
+        value_name = "FILE",
+        help = "On exit, write statistics of the session as JSON: iterations, times each alert fired, and the min, max, average and total delta of each value"
+    )]
+    pub summary_json: Option<PathBuf>,
+
+    /// Session file written by `dwatch record`.
+    #[clap(skip)]
+    pub record: Option<PathBuf>,
+
+    /// Session file played back by `dwatch replay` or `dwatch export`.
+    #[clap(skip)]
+    pub replay: Option<PathBuf>,
+
+    /// Speed of `dwatch replay`.
+    #[clap(skip)]
+    pub speed: Option<f64>,
+
+    /// Set by `dwatch export`: the session is played back as fast as possible, with nothing
+    /// rendered, until its end.
+    #[clap(skip)]
+    pub export: bool,
+
+    /// Interval of each command, when it has its own (given with `--cmd` or in a commands file).
+    #[clap(skip)]
+    pub intervals: Vec<Option<Duration>>,
+
+    #[clap(
+        long,
+        value_name = "[HOST]:PORT",
+        help = "Serve the numbers of the last frame as Prometheus metrics on /metrics, e.g. --prometheus :9101"
+    )]
+    pub prometheus: Option<String>,
+
+    #[clap(
+        long,
+        value_name = "[HOST]:PORT",
+        help = "Serve a web page showing the current frame live, with the deltas and sparklines of its numbers, e.g. --serve :8080"
+    )]
+    pub serve: Option<String>,
+
+    #[clap(
+        long,
+        value_name = "[HOST]:PORT",
+        help = "Stream every frame to gRPC subscribers of dwatch.Watch/Subscribe (see proto/dwatch.proto), e.g. --grpc :50051"
+    )]
+    pub grpc: Option<String>,
+
+    #[clap(
+        long,
+        value_name = "HOST:PORT",
+        help = "Push the numbers of every frame to a StatsD agent over UDP, values as gauges and deltas as counters"
+    )]
+    pub statsd: Option<String>,
+
+    #[clap(
+        long,
+        value_name = "HOST[:PORT]",
+        help = "Push the numbers of every frame to Graphite (Carbon) in the plaintext protocol [default port: 2003]"
+    )]
+    pub graphite: Option<String>,
+
+    #[clap(
+        long,
+        value_name = "PREFIX",
+        help = "First component of the metric paths sent to Graphite [default: dwatch]"
+    )]
+    pub graphite_prefix: Option<String>,
+
+    #[clap(
+        long,
+        value_name = "FILE|URL",
+        help = "Write the numbers of every frame in the Influx line protocol, appended to a file or posted to an http:// write endpoint"
+    )]
+    pub influx: Option<String>,
+
+    #[clap(
+        long,
+        value_name = "URL",
+        help = "Post the numbers of every frame to an http:// endpoint, as JSON records or rendered with --remote-write-template"
+    )]
+    pub remote_write: Option<String>,
+
+    #[clap(
+        long = "remote-write-header",
+        value_name = "NAME: VALUE",
+        help = "Header sent along with the --remote-write requests, e.g. an authorization token, can be repeated"
+    )]
+    pub remote_write_headers: Vec<String>,
+
+    #[clap(
+        long,
+        value_name = "TEMPLATE",
+        help = "Line posted by --remote-write for each number, with the placeholders {command}, {line}, {line_key}, {index}, {column}, {value}, {delta}, {rate}, {timestamp} and {timestamp_ms}"
+    )]
+    pub remote_write_template: Option<String>,
+
+    #[clap(
+        long,
+        value_name = "HOST[:PORT]/TOPIC",
+        help = "Publish the numbers of every frame to an MQTT broker, a JSON message per number [default port: 1883]"
+    )]
+    pub mqtt: Option<String>,
 
     #[clap(
         long,
-        help = "Style (one of: default, abs-delta, delta, fancy, fancy-net, stats, stats-net)"
+        value_name = "COMMAND",
+        help = "Filter the output of every command through a shell command (e.g. 'grep eth0') before it is displayed"
+    )]
+    pub pipe: Option<String>,
+
+    #[clap(
+        long,
+        help = "Keep showing the last successful output of a command while its runs fail (non-zero exit, timeout)"
+    )]
+    pub keep_last: bool,
+
+    #[clap(
+        long,
+        help = "Display the standard error of the commands below their output, in a distinct color"
+    )]
+    pub show_stderr: bool,
+
+    #[clap(
+        long,
+        env = "DWATCH_PRECISION",
+        help = "Number of decimals used for formatted rates"
+    )]
+    pub precision: Option<usize>,
+
+    #[clap(
+        long,
+        value_name = "SEP",
+        num_args = 0..=1,
+        require_equals = true,
+        help = "Group the digits of the values by thousands, with the separator of the locale (LC_NUMERIC), or that given (e.g. --grouping=\"'\")"
+    )]
+    pub grouping: Option<Option<char>>,
+
+    #[clap(
+        long,
+        value_enum,
+        env = "DWATCH_RATE_UNIT",
+        help = "Time base of rates [default: s]"
+    )]
+    pub rate_unit: Option<RateUnit>,
+
+    #[clap(
+        short,
+        long,
+        help = "Show the changes of the numbers next to them (the abs-delta style), unless --style is given"
+    )]
+    pub differences: bool,
+
+    #[clap(
+        long,
+        env = "DWATCH_STYLE",
+        help = "Style (one of: default, abs-delta, delta, fancy, fancy-net, fancy-bytes, stats, stats-net, stats-bytes, duration, duration-ms)"
     )]
     pub style: Option<String>,
 
+    #[clap(
+        long,
+        env = "DWATCH_THEME",
+        help = "Color theme (one of: default, solarized-dark, solarized-light, high-contrast, none)"
+    )]
+    pub theme: Option<String>,
+
+    #[clap(
+        long,
+        help = "Disable colors: mark deltas with arrows and statistics with brackets"
+    )]
+    pub no_color: bool,
+
+    #[clap(
+        long,
+        value_enum,
+        value_name = "WHEN",
+        env = "DWATCH_COLOR",
+        help = "Whether to color the output [default: auto]"
+    )]
+    pub color: Option<ColorMode>,
+
+    #[clap(
+        long,
+        env = "DWATCH_MOUSE",
+        value_parser = BoolishValueParser::new(),
+        help = "Focus values by clicking on them (enables mouse reporting)"
+    )]
+    pub mouse: bool,
+
+    #[clap(
+        long,
+        value_name = "FRAMES",
+        env = "DWATCH_FOCUS_TIMEOUT",
+        help = "Drop an untouched focus after the given number of frames, 0 keeps it until dismissed (Esc) [default: 5]"
+    )]
+    pub focus_timeout: Option<usize>,
+
+    #[clap(
+        long,
+        env = "DWATCH_CONFIG",
+        help = "Load the configuration from the given file"
+    )]
+    pub config: Option<PathBuf>,
+
+    /// The options given on the command line or in the environment, and the flags set by a
+    /// profile once merged, which the configuration leaves as they are.
+    #[clap(skip)]
+    pub given: BTreeSet<String>,
+
     pub commands: Vec<String>,
 }
+
+impl Action {
+    /// The options of the watch the action stands for, none for the actions that do not watch.
+    pub fn into_options(self) -> Option<Options> {
+        match self {
+            Action::Run(options) => Some(options),
+            Action::Record { file, options } => Some(Options {
+                record: Some(file),
+                ..options
+            }),
+            Action::Replay {
+                file,
+                speed,
+                options,
+            } => Some(Options {
+                replay: Some(file),
+                speed,
+                ..options
+            }),
+            Action::Styles | Action::Completions { .. } | Action::Complete { .. } => None,
+            Action::Export {
+                file,
+                format,
+                output,
+                rate_unit,
+            } => {
+                let mut options = Options {
+                    replay: Some(file),
+                    export: true,
+                    no_banner: true,
+                    rate_unit,
+                    ..Default::default()
+                };
+                match format {
+                    ExportFormat::Ndjson => {
+                        options.output = Some(OutputFormat::Ndjson);
+                        options.output_file = output;
+                    }
+                    ExportFormat::Csv => {
+                        options.log_csv = Some(output.unwrap_or_else(|| "-".into()));
+                    }
+                }
+                Some(options)
+            }
+        }
+    }
+}
+
+impl Options {
+    /// Note the options given on the command line or in the environment, out of the matches the
+    /// options were parsed from.
+    pub fn set_given(&mut self, args: &ArgMatches) {
+        self.given = args
+            .ids()
+            .filter(|id| {
+                matches!(
+                    args.value_source(id.as_str()),
+                    Some(ValueSource::CommandLine | ValueSource::EnvVariable)
+                )
+            })
+            .map(|id| id.to_string())
+            .collect();
+    }
+
+    /// Fill the options not given on the command line (or in the environment) with the values of
+    /// the configuration file. Keys are named after the long options, e.g. `no-banner = true`.
+    pub fn merge(&mut self, cfg: &Config) -> Result<()> {
+        fn get<'a, T>(
+            cfg: &'a Config,
+            key: &str,
+            conv: impl Fn(&'a Value) -> Option<T>,
+        ) -> Result<Option<T>> {
+            cfg.get(key)
+                .map(|v| conv(v).ok_or_else(|| anyhow!("config: invalid value for '{}'", key)))
+                .transpose()
+        }
+
+        // a flag is set as configured unless given otherwise, even as false (DWATCH_QUIET=0), or
+        // by a profile merged before
+        fn flag(
+            given: &mut BTreeSet<String>,
+            field: &mut bool,
+            cfg: &Config,
+            key: &str,
+        ) -> Result<()> {
+            let id = key.replace('-', "_");
+            if !*field && !given.contains(&id) {
+                if let Some(value) = get(cfg, key, Value::as_bool)? {
+                    *field = value;
+                    given.insert(id);
+                }
+            }
+            Ok(())
+        }
+
+        fn unsigned(v: &Value) -> Option<u64> {
+            v.as_integer().and_then(|i| u64::try_from(i).ok())
+        }
+
+        fn strings(v: &Value) -> Option<Vec<String>> {
+            match v {
+                Value::Array(items) => items
+                    .iter()
+                    .map(|item| item.as_str().map(str::to_owned))
+                    .collect(),
+                _ => None,
+            }
+        }
+
+        fn duration(v: &Value) -> Option<Duration> {
+            match v {
+                Value::Integer(i) => parse_duration(&i.to_string()).ok(),
+                Value::Float(f) => parse_duration(&f.to_string()).ok(),
+                _ => v.as_str().and_then(|s| parse_duration(s).ok()),
+            }
+        }
+
+        fn timeout(v: &Value) -> Option<Duration> {
+            match v {
+                Value::Integer(i) => parse_timeout(&i.to_string()).ok(),
+                Value::Float(f) => parse_timeout(&f.to_string()).ok(),
+                _ => v.as_str().and_then(|s| parse_timeout(s).ok()),
+            }
+        }
+
+        if self.seconds.is_none() {
+            self.seconds = get(cfg, "seconds", unsigned)?;
+        }
+        if self.count.is_none() {
+            self.count = get(cfg, "count", unsigned)?;
+        }
+        if self.on_diff.is_none() {
+            self.on_diff = get(cfg, "on-diff", |v| v.as_str().map(str::to_owned))?;
+        }
+        if self.on_alert.is_none() {
+            self.on_alert = get(cfg, "on-alert", |v| v.as_str().map(str::to_owned))?;
+        }
+        if self.interval.is_none() {
+            self.interval = get(cfg, "interval", duration)?;
+        }
+        if self.jitter.is_none() {
+            self.jitter = get(cfg, "jitter", duration)?;
+        }
+        if self.timeout.is_none() {
+            self.timeout = get(cfg, "timeout", timeout)?;
+        }
+        if self.control_socket.is_none() {
+            self.control_socket = get(cfg, "control-socket", |v| match v {
+                Value::Bool(true) => Some(Some(None)),
+                Value::Bool(false) => Some(None),
+                v => v.as_str().map(|path| Some(Some(PathBuf::from(path)))),
+            })?
+            .flatten();
+        }
+        if self.remote_write.is_none() {
+            self.remote_write = get(cfg, "remote-write", |v| v.as_str().map(str::to_owned))?;
+        }
+        if self.remote_write_headers.is_empty() {
+            self.remote_write_headers =
+                get(cfg, "remote-write-headers", strings)?.unwrap_or_default();
+        }
+        if self.remote_write_template.is_none() {
+            self.remote_write_template = get(cfg, "remote-write-template", |v| {
+                v.as_str().map(str::to_owned)
+            })?;
+        }
+        if self.labels.is_empty() {
+            self.labels = get(cfg, "labels", strings)?.unwrap_or_default();
+        }
+        if self.title.is_none() {
+            self.title = get(cfg, "title", |v| v.as_str().map(str::to_owned))?;
+        }
+        if self.webhooks.is_empty() {
+            self.webhooks = get(cfg, "webhooks", strings)?.unwrap_or_default();
+        }
+        if self.record_separator.is_none() {
+            self.record_separator =
+                get(cfg, "record-separator", |v| v.as_str().map(str::to_owned))?;
+        }
+        if self.graphite_prefix.is_none() {
+            self.graphite_prefix = get(cfg, "graphite-prefix", |v| v.as_str().map(str::to_owned))?;
+        }
+        if self.speed.is_none() {
+            self.speed = get(cfg, "speed", |v| {
+                match v {
+                    Value::Integer(i) => Some(*i as f64),
+                    Value::Float(f) => Some(*f),
+                    _ => None,
+                }
+                .filter(|&s| s > 0.0)
+            })?;
+        }
+        if self.retry.is_none() {
+            self.retry = get(cfg, "retry", |v| match v {
+                Value::Integer(_) => unsigned(v).map(|n| Retry {
+                    attempts: n as u32,
+                    delay: Duration::from_secs(1),
+                }),
+                _ => v.as_str().and_then(|s| parse_retry(s).ok()),
+            })?;
+        }
+        if self.pipe.is_none() {
+            self.pipe = get(cfg, "pipe", |v| v.as_str().map(str::to_owned))?;
+        }
+        if self.overlap.is_none() {
+            self.overlap = get(cfg, "overlap", |v| {
+                v.as_str().and_then(|s| Overlap::from_str(s, true).ok())
+            })?;
+        }
+        if self.output.is_none() {
+            self.output = get(cfg, "output", |v| {
+                v.as_str()
+                    .and_then(|s| OutputFormat::from_str(s, true).ok())
+            })?;
+        }
+        if self.precision.is_none() {
+            self.precision = get(cfg, "precision", |v| unsigned(v).map(|p| p as usize))?;
+        }
+        // `true` for the separator of the locale, or the separator
+        if self.grouping.is_none() {
+            self.grouping = get(cfg, "grouping", |v| match v {
+                Value::Bool(true) => Some(Some(None)),
+                Value::Bool(false) => Some(None),
+                Value::String(sep) if sep.chars().count() == 1 => Some(Some(sep.chars().next())),
+                _ => None,
+            })?
+            .flatten();
+        }
+        if self.rate_unit.is_none() {
+            self.rate_unit = get(cfg, "rate-unit", |v| {
+                v.as_str().and_then(|s| RateUnit::from_str(s, true).ok())
+            })?;
+        }
+        if self.focus_timeout.is_none() {
+            self.focus_timeout = get(cfg, "focus-timeout", |v| unsigned(v).map(|f| f as usize))?;
+        }
+        if self.style.is_none() {
+            self.style = get(cfg, "style", |v| v.as_str().map(str::to_owned))?;
+        }
+        if self.theme.is_none() {
+            self.theme = get(cfg, "theme", |v| v.as_str().map(str::to_owned))?;
+        }
+        if self.color.is_none() {
+            self.color = get(cfg, "color", |v| {
+                v.as_str().and_then(|s| ColorMode::from_str(s, true).ok())
+            })?;
+        }
+        flag(&mut self.given, &mut self.no_banner, cfg, "no-banner")?;
+        flag(&mut self.given, &mut self.no_clear, cfg, "no-clear")?;
+        flag(&mut self.given, &mut self.no_color, cfg, "no-color")?;
+        flag(&mut self.given, &mut self.quiet, cfg, "quiet")?;
+        flag(&mut self.given, &mut self.differences, cfg, "differences")?;
+        flag(&mut self.given, &mut self.sequential, cfg, "sequential")?;
+        flag(&mut self.given, &mut self.follow, cfg, "follow")?;
+        flag(&mut self.given, &mut self.on_change, cfg, "on-change")?;
+        flag(&mut self.given, &mut self.keep_last, cfg, "keep-last")?;
+        flag(
+            &mut self.given,
+            &mut self.auto_interval,
+            cfg,
+            "auto-interval",
+        )?;
+        flag(&mut self.given, &mut self.precise, cfg, "precise")?;
+        flag(&mut self.given, &mut self.beep, cfg, "beep")?;
+        flag(
+            &mut self.given,
+            &mut self.fail_on_alert,
+            cfg,
+            "fail-on-alert",
+        )?;
+        flag(
+            &mut self.given,
+            &mut self.fail_on_firing,
+            cfg,
+            "fail-on-firing",
+        )?;
+        flag(&mut self.given, &mut self.notify, cfg, "notify")?;
+        flag(&mut self.given, &mut self.show_stderr, cfg, "show-stderr")?;
+        flag(&mut self.given, &mut self.exec, cfg, "exec")?;
+        flag(&mut self.given, &mut self.pty, cfg, "pty")?;
+        flag(&mut self.given, &mut self.errexit, cfg, "errexit")?;
+        flag(&mut self.given, &mut self.blink, cfg, "blink")?;
+        flag(&mut self.given, &mut self.mouse, cfg, "mouse")?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::{CommandFactory, FromArgMatches};
+
+    #[test]
+    fn test_parse_retry() {
+        assert_eq!(
+            parse_retry("3"),
+            Ok(Retry {
+                attempts: 3,
+                delay: Duration::from_secs(1)
+            })
+        );
+        assert_eq!(
+            parse_retry("2:0.5"),
+            Ok(Retry {
+                attempts: 2,
+                delay: Duration::from_millis(500)
+            })
+        );
+        assert!(parse_retry("x").is_err());
+        assert!(parse_retry("2:-1").is_err());
+    }
+
+    #[test]
+    fn test_merge() {
+        let cfg = Config::parse(
+            r#"
+interval = "250ms"
+style = "delta"
+no-color = true
+mouse = true
+record-separator = "---"
+webhooks = ["http://a/hook", "http://b/hook"]
+"#,
+        )
+        .unwrap();
+        let mut opts = Options::parse_from(["dwatch", "--style", "fancy", "uptime"]);
+        opts.merge(&cfg).unwrap();
+        assert_eq!(opts.interval, Some(Duration::from_millis(250)));
+        // the command line takes precedence
+        assert_eq!(opts.style.as_deref(), Some("fancy"));
+        assert!(opts.no_color && opts.mouse);
+        assert_eq!(opts.record_separator.as_deref(), Some("---"));
+        assert_eq!(opts.webhooks, ["http://a/hook", "http://b/hook"]);
+
+        // invalid values are reported, unless overridden
+        let cfg = Config::parse("interval = \"fast\"").unwrap();
+        assert!(opts.merge(&cfg).is_ok());
+        assert!(Options::parse_from(["dwatch", "uptime"])
+            .merge(&cfg)
+            .is_err());
+    }
+
+    #[test]
+    fn test_watch_flags() {
+        // the flags of GNU watch
+        let opts = Options::parse_from(["dwatch", "-n", "0.5", "-dtbegpx", "ls", "/tmp"]);
+        assert_eq!(opts.interval, Some(Duration::from_millis(500)));
+        assert!(opts.differences && opts.no_banner && opts.beep && opts.errexit);
+        assert!(opts.chgexit && opts.precise && opts.exec);
+        assert_eq!(opts.commands, ["ls", "/tmp"]);
+        assert!(Options::parse_from(["dwatch", "--no-title", "uptime"]).no_banner);
+    }
+
+    #[test]
+    fn test_parse_command() {
+        let spec = |command: &str, label: Option<&str>, secs: Option<u64>| CommandSpec {
+            command: command.to_owned(),
+            label: label.map(str::to_owned),
+            interval: secs.map(Duration::from_secs),
+        };
+        assert_eq!(parse_command("uptime"), Ok(spec("uptime", None, None)));
+        assert_eq!(
+            parse_command("10s:temp=sensors"),
+            Ok(spec("sensors", Some("temp"), Some(10)))
+        );
+        assert_eq!(
+            parse_command("rx/tx=ip -s link show eth0"),
+            Ok(spec("ip -s link show eth0", Some("rx/tx"), None))
+        );
+        assert_eq!(
+            parse_command("2: awk -v n=1 '{print n}' f"),
+            Ok(spec("awk -v n=1 '{print n}' f", None, Some(2)))
+        );
+        assert_eq!(
+            parse_command("curl http://localhost:8080/"),
+            Ok(spec("curl http://localhost:8080/", None, None))
+        );
+        assert!(parse_command("5s:").is_err());
+        assert!(parse_command("label=").is_err());
+    }
+
+    #[test]
+    fn test_read_commands_file() {
+        let path = std::env::temp_dir().join(format!("dwatch-test-{}.txt", std::process::id()));
+        std::fs::write(&path, "# dashboard\nload=uptime\n\n  5s:df -h\n").unwrap();
+        let specs = read_commands_file(&path).unwrap();
+        assert_eq!(specs.len(), 2);
+        assert_eq!(specs[0].label.as_deref(), Some("load"));
+        assert_eq!(specs[1].command, "df -h");
+        assert_eq!(specs[1].interval, Some(Duration::from_secs(5)));
+
+        std::fs::write(&path, "uptime\n1s:\n").unwrap();
+        let err = read_commands_file(&path).unwrap_err().to_string();
+        assert!(err.ends_with(":2: no command in '1s:'"), "{}", err);
+        std::fs::remove_file(&path).unwrap();
+
+        let opts = Options::parse_from(["dwatch", "--commands-file", "dashboard.txt"]);
+        assert_eq!(opts.commands_file, Some("dashboard.txt".into()));
+        assert!(Options::try_parse_from(["dwatch", "--commands-file", "f", "uptime"]).is_err());
+    }
+
+    #[test]
+    fn test_cmds() {
+        let opts = Options::parse_from([
+            "dwatch",
+            "--cmd",
+            "ip -s link | head",
+            "--cmd",
+            "10s:sensors",
+        ]);
+        assert_eq!(opts.cmds.len(), 2);
+        assert_eq!(opts.cmds[0].command, "ip -s link | head");
+        assert_eq!(opts.cmds[1].command, "sensors");
+        assert_eq!(opts.cmds[1].interval, Some(Duration::from_secs(10)));
+        assert!(opts.commands.is_empty());
+        assert!(Options::try_parse_from(["dwatch", "--cmd", "1s:"]).is_err());
+        assert!(Options::try_parse_from(["dwatch", "--cmd", "ss -s", "uptime"]).is_err());
+
+        assert_eq!(
+            split_label("rx/tx=ip -s link show eth0"),
+            (Some("rx/tx"), "ip -s link show eth0")
+        );
+        assert_eq!(split_label("dd if=/dev/zero"), (None, "dd if=/dev/zero"));
+        assert_eq!(split_label("a=$(b=c)"), (Some("a"), "$(b=c)"));
+        assert_eq!(split_label("=uptime"), (None, "=uptime"));
+    }
+
+    #[test]
+    fn test_verbose() {
+        let opts = Options::parse_from(["dwatch", "-v", "--verbose-log", "v.log", "uptime"]);
+        assert!(opts.verbose && opts.verbose_log == Some("v.log".into()));
+        assert!(Options::try_parse_from(["dwatch", "--verbose-log", "v.log", "uptime"]).is_err());
+    }
+
+    #[test]
+    fn test_grouping() {
+        assert_eq!(Options::parse_from(["dwatch", "uptime"]).grouping, None);
+        let opts = Options::parse_from(["dwatch", "--grouping", "uptime"]);
+        assert_eq!(opts.grouping, Some(None));
+        assert_eq!(opts.commands, ["uptime"]);
+        let opts = Options::parse_from(["dwatch", "--grouping=.", "uptime"]);
+        assert_eq!(opts.grouping, Some(Some('.')));
+
+        let mut opts = Options::parse_from(["dwatch", "uptime"]);
+        opts.merge(&Config::parse("grouping = \" \"").unwrap())
+            .unwrap();
+        assert_eq!(opts.grouping, Some(Some(' ')));
+        let mut opts = Options::parse_from(["dwatch", "uptime"]);
+        opts.merge(&Config::parse("grouping = true").unwrap())
+            .unwrap();
+        assert_eq!(opts.grouping, Some(None));
+        let mut opts = Options::parse_from(["dwatch", "uptime"]);
+        assert!(opts
+            .merge(&Config::parse("grouping = \"ab\"").unwrap())
+            .is_err());
+    }
+
+    #[test]
+    fn test_quiet() {
+        let opts = Options::parse_from(["dwatch", "-q", "uptime"]);
+        assert!(opts.quiet);
+
+        let mut opts = Options::parse_from(["dwatch", "uptime"]);
+        opts.merge(&Config::parse("quiet = true").unwrap()).unwrap();
+        assert!(opts.quiet);
+    }
+
+    #[test]
+    fn test_merge_flags() {
+        let args = Options::command().get_matches_from(["dwatch", "-q", "uptime"]);
+        let mut opts = Options::from_arg_matches(&args).unwrap();
+        opts.set_given(&args);
+        assert!(opts.given.contains("quiet") && !opts.given.contains("follow"));
+        let cfg = Config::parse("quiet = false\nfollow = true").unwrap();
+        opts.merge(&cfg).unwrap();
+        assert!(opts.quiet && opts.follow);
+
+        // a flag turned off in the environment (e.g. DWATCH_QUIET=0) stays off
+        let mut opts = Options::parse_from(["dwatch", "uptime"]);
+        opts.given.insert("quiet".to_owned());
+        opts.merge(&Config::parse("quiet = true").unwrap()).unwrap();
+        assert!(!opts.quiet);
+
+        // a profile comes before the configuration, even to turn a flag off
+        let mut opts = Options::parse_from(["dwatch", "uptime"]);
+        opts.merge(&Config::parse("quiet = false").unwrap())
+            .unwrap();
+        opts.merge(&Config::parse("quiet = true").unwrap()).unwrap();
+        assert!(!opts.quiet);
+    }
+
+    #[test]
+    fn test_headless() {
+        let opts = Options::parse_from(["dwatch", "--headless", "--log", "f.log", "uptime"]);
+        assert!(opts.headless && opts.log == Some("f.log".into()));
+        assert!(Options::try_parse_from(["dwatch", "--log", "f.log", "uptime"]).is_err());
+    }
+
+    #[test]
+    fn test_env() {
+        // variables are named after the long options
+        let cmd = Cli::command();
+        let names: Vec<_> = cmd
+            .get_arguments()
+            .filter_map(|arg| Some((arg.get_long()?, arg.get_env()?.to_str()?)))
+            .collect();
+        assert!(names.contains(&("interval", "DWATCH_INTERVAL")));
+        assert!(names.contains(&("color", "DWATCH_COLOR")));
+        for (long, name) in names {
+            assert_eq!(
+                name,
+                format!("DWATCH_{}", long.to_uppercase().replace('-', "_"))
+            );
+        }
+    }
+
+    #[test]
+    fn test_actions() {
+        let cli = Cli::parse_from(["dwatch", "-m", "uptime", "date"]);
+        assert!(cli.action.is_none());
+        assert_eq!(cli.options.commands, ["uptime", "date"]);
+
+        let cli = Cli::parse_from(["dwatch", "record", "s.dwr", "--count", "3", "uptime"]);
+        let options = cli.action.unwrap().into_options().unwrap();
+        assert_eq!(options.record, Some(PathBuf::from("s.dwr")));
+        assert_eq!(
+            (options.count, options.commands),
+            (Some(3), vec!["uptime".into()])
+        );
+
+        let cli = Cli::parse_from(["dwatch", "replay", "--speed", "2", "s.dwr"]);
+        let options = cli.action.unwrap().into_options().unwrap();
+        assert_eq!(
+            (options.replay, options.speed),
+            (Some("s.dwr".into()), Some(2.0))
+        );
+
+        let cli = Cli::parse_from(["dwatch", "export", "--format", "csv", "s.dwr"]);
+        let options = cli.action.unwrap().into_options().unwrap();
+        assert!(options.export && options.output.is_none());
+        assert_eq!(options.log_csv, Some(PathBuf::from("-")));
+
+        // commands are told apart from the actions by the options before them
+        let cli = Cli::parse_from(["dwatch", "run", "styles"]);
+        assert_eq!(
+            cli.action.unwrap().into_options().unwrap().commands,
+            ["styles"]
+        );
+        assert!(Cli::try_parse_from(["dwatch", "--count", "1", "styles"]).is_ok());
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("2"), Ok(Duration::from_secs(2)));
+        assert_eq!(parse_duration("0.2"), Ok(Duration::from_millis(200)));
+        assert_eq!(parse_duration("250ms"), Ok(Duration::from_millis(250)));
+        assert_eq!(parse_duration("1.5s"), Ok(Duration::from_millis(1500)));
+        assert_eq!(parse_duration("1m"), Ok(Duration::from_secs(60)));
+        assert!(parse_duration("0").is_err());
+        assert!(parse_duration("0.5ms").is_err());
+        assert!(parse_duration("0.0001").is_err());
+        assert_eq!(parse_duration("1ms"), Ok(Duration::from_millis(1)));
+        assert!(parse_duration("-1").is_err());
+        assert!(parse_duration("fast").is_err());
+    }
+
+    #[test]
+    fn test_parse_timeout() {
+        assert_eq!(parse_timeout("0"), Ok(Duration::ZERO));
+        assert_eq!(parse_timeout("0.5"), Ok(Duration::from_millis(500)));
+        assert_eq!(parse_timeout("250ms"), Ok(Duration::from_millis(250)));
+        assert!(parse_timeout("-1").is_err());
+    }
+}
@@ -0,0 +1,64 @@
+//! Regular expressions, as POSIX extended regular expressions (the `grep -E` syntax) compiled by
+//! the C library.
+
+use std::ffi::CString;
+
+use anyhow::{anyhow, Result};
+
+pub struct Regex {
+    inner: Box<libc::regex_t>,
+}
+
+impl Regex {
+    pub fn new(pattern: &str) -> Result<Self> {
+        let source =
+            CString::new(pattern).map_err(|_| anyhow!("invalid regex '{}': NUL byte", pattern))?;
+        // SAFETY: regex_t is plain data initialized by regcomp, regerror writes at most buf.len()
+        // bytes (NUL terminated).
+        unsafe {
+            let mut inner = Box::new(std::mem::zeroed::<libc::regex_t>());
+            let rc = libc::regcomp(
+                &mut *inner,
+                source.as_ptr(),
+                libc::REG_EXTENDED | libc::REG_NOSUB,
+            );
+            if rc != 0 {
+                let mut buf = [0 as libc::c_char; 256];
+                libc::regerror(rc, &*inner, buf.as_mut_ptr(), buf.len());
+                let reason = std::ffi::CStr::from_ptr(buf.as_ptr()).to_string_lossy();
+                return Err(anyhow!("invalid regex '{}': {}", pattern, reason));
+            }
+            Ok(Self { inner })
+        }
+    }
+
+    /// Whether the pattern matches anywhere in the text.
+    pub fn is_match(&self, text: &str) -> bool {
+        let Ok(text) = CString::new(text.replace('\0', "")) else {
+            return false;
+        };
+        // SAFETY: the regex was compiled by regcomp, no match offsets are requested.
+        unsafe { libc::regexec(&*self.inner, text.as_ptr(), 0, std::ptr::null_mut(), 0) == 0 }
+    }
+}
+
+impl Drop for Regex {
+    fn drop(&mut self) {
+        // SAFETY: the regex was compiled by regcomp, and is not used afterwards.
+        unsafe { libc::regfree(&mut *self.inner) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_regex() {
+        let re = Regex::new("^(Ready|Up) [0-9]+$").unwrap();
+        assert!(re.is_match("Ready 3"));
+        assert!(re.is_match("Up 10"));
+        assert!(!re.is_match("Not Ready 3"));
+        assert!(Regex::new("a(b").is_err());
+    }
+}
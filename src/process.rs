@@ -0,0 +1,680 @@
+//! Execution of the watched commands, on a pool of long-lived worker threads.
+
+use std::{
+    fs::File,
+    io::{ErrorKind, Read, Write},
+    os::fd::{AsFd, AsRawFd, OwnedFd},
+    os::unix::process::CommandExt,
+    os::unix::process::ExitStatusExt,
+    process::{Child, Command, ExitStatus, Output, Stdio},
+    sync::{
+        atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering},
+        mpsc, Arc, Condvar, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+
+use crate::pty;
+
+/// Interval at which commands are polled for completion.
+pub const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// How long the output of a killed command is still read.
+const KILL_GRACE: Duration = Duration::from_millis(100);
+
+type Task = Box<dyn FnOnce() + Send>;
+
+/// Worker threads running the commands. Workers are only added when all of them are busy, and
+/// then stay around for the next frames.
+struct Pool {
+    sender: Mutex<mpsc::Sender<Task>>,
+    receiver: Arc<Mutex<mpsc::Receiver<Task>>>,
+    /// Workers waiting for a task, and not yet promised one.
+    idle: Arc<AtomicUsize>,
+}
+
+impl Pool {
+    fn new() -> Self {
+        let (sender, receiver) = mpsc::channel();
+        Self {
+            sender: Mutex::new(sender),
+            receiver: Arc::new(Mutex::new(receiver)),
+            idle: Arc::default(),
+        }
+    }
+
+    fn submit(&self, task: Task) {
+        // claim an idle worker, or add one
+        if self
+            .idle
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| n.checked_sub(1))
+            .is_err()
+        {
+            let (receiver, idle) = (Arc::clone(&self.receiver), Arc::clone(&self.idle));
+            std::thread::spawn(move || loop {
+                let task = receiver.lock().unwrap().recv();
+                match task {
+                    Ok(task) => task(),
+                    Err(_) => break,
+                }
+                idle.fetch_add(1, Ordering::AcqRel);
+            });
+        }
+        let _ = self.sender.lock().unwrap().send(task);
+    }
+}
+
+lazy_static! {
+    static ref POOL: Pool = Pool::new();
+}
+
+/// How many times a failed command is run again, and how long to wait before each attempt.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Retry {
+    pub attempts: u32,
+    pub delay: Duration,
+}
+
+/// An output stream of a command, and what was read from it.
+struct Pipe {
+    file: File,
+    data: Vec<u8>,
+    open: bool,
+}
+
+impl Pipe {
+    fn new(file: File) -> Self {
+        Self {
+            file,
+            data: Vec::new(),
+            open: true,
+        }
+    }
+}
+
+/// Standard output and error of a command (merged into the output under a pseudo-terminal), or
+/// the standard input of dwatch.
+struct Streams {
+    pipes: Vec<Pipe>,
+}
+
+/// A started command, with its output streams.
+struct Attempt {
+    child: Child,
+    streams: Streams,
+}
+
+impl Attempt {
+    /// Start the command, in a pseudo-terminal if requested.
+    fn spawn(cmd: &mut Command, pty: bool) -> Result<Self> {
+        if pty {
+            let (child, master) = pty::spawn(cmd)?;
+            return Ok(Self {
+                child,
+                streams: Streams {
+                    pipes: vec![Pipe::new(master)],
+                },
+            });
+        }
+
+        cmd.stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .process_group(0);
+        let mut child = cmd.spawn()?;
+        let stdout = OwnedFd::from(child.stdout.take().unwrap());
+        let stderr = OwnedFd::from(child.stderr.take().unwrap());
+        Ok(Self {
+            child,
+            streams: Streams {
+                pipes: vec![Pipe::new(stdout.into()), Pipe::new(stderr.into())],
+            },
+        })
+    }
+
+    /// Wait for the command to complete, with the given output.
+    fn complete(mut self, block: Block) -> Result<Output> {
+        Ok(Output {
+            status: self.child.wait()?,
+            stdout: block.stdout,
+            stderr: block.stderr,
+        })
+    }
+
+    /// Read the output until the command (and the processes it started) closed it, killing them
+    /// after the timeout, then wait for the command to complete.
+    fn wait(mut self, timeout: Option<Duration>) -> Result<Output> {
+        let deadline = timeout.map(|t| Instant::now() + t);
+        let mut killed = false;
+
+        while self.streams.is_open() {
+            let wait = match deadline {
+                // once killed, the output could only be kept open by processes that escaped the
+                // group, which are given a moment to be reaped
+                _ if killed => Some(KILL_GRACE),
+                Some(deadline) => Some(
+                    deadline.saturating_duration_since(Instant::now()) + Duration::from_millis(1),
+                ),
+                None => None,
+            };
+            if !self.streams.read(wait)? {
+                if killed {
+                    break;
+                }
+                if deadline.is_some_and(|d| Instant::now() >= d) {
+                    kill_group(self.child.id());
+                    killed = true;
+                }
+            }
+        }
+
+        let block = self.streams.take();
+        self.complete(block)
+    }
+}
+
+impl Streams {
+    /// Whether something may still be output (by the command or some process it started).
+    fn is_open(&self) -> bool {
+        self.pipes.iter().any(|p| p.open)
+    }
+
+    /// Wait up to `timeout` (forever if none) for some output, and read it. Returns false if
+    /// nothing came in time.
+    fn read(&mut self, timeout: Option<Duration>) -> Result<bool> {
+        let mut fds: Vec<libc::pollfd> = self
+            .pipes
+            .iter()
+            .map(|pipe| libc::pollfd {
+                // negative descriptors are ignored
+                fd: if pipe.open { pipe.file.as_raw_fd() } else { -1 },
+                events: libc::POLLIN,
+                revents: 0,
+            })
+            .collect();
+        let wait_ms = timeout.map_or(-1, |t| t.as_millis().min(i32::MAX as u128) as i32);
+        // SAFETY: fds is a valid array of fds.len() pollfd structures.
+        let ready = unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, wait_ms) };
+        if ready < 0 {
+            let e = std::io::Error::last_os_error();
+            return match e.kind() {
+                ErrorKind::Interrupted => Ok(true),
+                _ => Err(e.into()),
+            };
+        }
+
+        let mut buf = [0u8; 4096];
+        for (pipe, fd) in self.pipes.iter_mut().zip(&fds) {
+            if fd.revents == 0 {
+                continue;
+            }
+            match pipe.file.read(&mut buf) {
+                Ok(0) => pipe.open = false,
+                Ok(n) => pipe.data.extend_from_slice(&buf[..n]),
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                // EIO is how a pseudo-terminal signals that its slave side was closed
+                Err(_) => pipe.open = false,
+            }
+        }
+        Ok(ready > 0)
+    }
+
+    /// Take what was read so far.
+    fn take(&mut self) -> Block {
+        let mut data = self.pipes.iter_mut().map(|p| std::mem::take(&mut p.data));
+        Block {
+            stdout: data.next().unwrap_or_default(),
+            stderr: data.next().unwrap_or_default(),
+        }
+    }
+
+    /// Read until the streams are closed (or `killed` is set), splitting the output in blocks:
+    /// records ended by a line made of the separator if one is given, else the output up to a
+    /// pause of at least `gap`. Each block is left in the feed, and `notify` is called. Returns
+    /// the last block, including what followed the last separator.
+    fn split(
+        &mut self,
+        separator: Option<&[u8]>,
+        gap: Duration,
+        killed: &AtomicBool,
+        feed: &Feed,
+        notify: &dyn Fn(),
+    ) -> Result<Block> {
+        let mut last = Block::default();
+        let publish = |block: &Block| {
+            *feed.0.lock().unwrap() = Some(block.clone());
+            notify();
+        };
+        let mut pending = false;
+
+        while self.is_open() && !killed.load(Ordering::Relaxed) {
+            let by_pause = pending && separator.is_none();
+            if self.read(Some(if by_pause { gap } else { KILL_GRACE }))? {
+                pending = self.pipes.iter().any(|p| !p.data.is_empty());
+                let Some(separator) = separator else {
+                    continue;
+                };
+                let mut record = None;
+                while let Some(r) = cut_record(&mut self.pipes[0].data, separator) {
+                    record = Some(r);
+                }
+                if let Some(stdout) = record {
+                    last = Block {
+                        stdout,
+                        stderr: Vec::new(),
+                    };
+                    publish(&last);
+                }
+            } else if by_pause {
+                last = self.take();
+                pending = false;
+                publish(&last);
+            }
+        }
+
+        if pending {
+            last = self.take();
+        }
+        Ok(last)
+    }
+}
+
+/// Cut the first record ended by a line made of the separator off the data.
+fn cut_record(data: &mut Vec<u8>, separator: &[u8]) -> Option<Vec<u8>> {
+    let mut start = 0;
+    while let Some(len) = data[start..].iter().position(|&b| b == b'\n') {
+        let line = &data[start..start + len];
+        if line == separator || line.strip_suffix(b"\r") == Some(separator) {
+            let record = data[..start].to_vec();
+            data.drain(..start + len + 1);
+            return Some(record);
+        }
+        start += len + 1;
+    }
+    None
+}
+
+/// Output of a command in follow mode, up to a pause.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Block {
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+}
+
+/// The last block output by a followed command, not yet displayed.
+#[derive(Clone, Default)]
+pub struct Feed(Arc<Mutex<Option<Block>>>);
+
+impl Feed {
+    pub fn take(&self) -> Option<Block> {
+        self.0.lock().unwrap().take()
+    }
+}
+
+/// Where a worker leaves the output of a job, along with how long it ran.
+#[derive(Default)]
+struct Slot {
+    output: Mutex<Option<(Result<Output>, Duration)>>,
+    ready: Condvar,
+}
+
+/// A command running in the background.
+pub struct Job {
+    /// Process (and group) of the current attempt, 0 for a function.
+    pid: Arc<AtomicU32>,
+    killed: Arc<AtomicBool>,
+    slot: Arc<Slot>,
+}
+
+impl Job {
+    /// Start the command built by `make`, in a pseudo-terminal if requested. After the timeout,
+    /// the command is killed along with the processes it started, and whatever it output until
+    /// then is collected. A failed command is run again as set by `retry`, the output of the last
+    /// attempt is collected.
+    pub fn start(
+        mut make: impl FnMut() -> Command + Send + 'static,
+        pty: bool,
+        timeout: Option<Duration>,
+        retry: Retry,
+    ) -> Result<Self> {
+        let attempt = Attempt::spawn(&mut make(), pty)?;
+        let pid = Arc::new(AtomicU32::new(attempt.child.id()));
+        let killed = Arc::new(AtomicBool::new(false));
+
+        let run = {
+            let (pid, killed) = (Arc::clone(&pid), Arc::clone(&killed));
+            move || -> Result<Output> {
+                let mut attempt = attempt;
+                let mut left = retry.attempts;
+                loop {
+                    let output = attempt.wait(timeout)?;
+                    if output.status.success() || left == 0 || killed.load(Ordering::Relaxed) {
+                        return Ok(output);
+                    }
+                    left -= 1;
+
+                    let resume = Instant::now() + retry.delay;
+                    while Instant::now() < resume {
+                        if killed.load(Ordering::Relaxed) {
+                            return Ok(output);
+                        }
+                        std::thread::sleep(POLL_INTERVAL);
+                    }
+                    attempt = Attempt::spawn(&mut make(), pty)?;
+                    pid.store(attempt.child.id(), Ordering::Relaxed);
+                    // killed while starting the attempt
+                    if killed.load(Ordering::Relaxed) {
+                        kill_group(attempt.child.id());
+                    }
+                }
+            }
+        };
+
+        Ok(Self::submit(pid, killed, run))
+    }
+
+    /// Start the command for good, its output being split in blocks at each pause of at least
+    /// `gap`: the last one is left in the returned feed, and `notify` is called. The job completes
+    /// when the command exits, with its last block as output.
+    pub fn follow(
+        cmd: &mut Command,
+        pty: bool,
+        gap: Duration,
+        notify: impl Fn() + Send + 'static,
+    ) -> Result<(Self, Feed)> {
+        let mut attempt = Attempt::spawn(cmd, pty)?;
+        let pid = Arc::new(AtomicU32::new(attempt.child.id()));
+        let killed = Arc::new(AtomicBool::new(false));
+        let feed = Feed::default();
+
+        let run = {
+            let (killed, feed) = (Arc::clone(&killed), feed.clone());
+            move || -> Result<Output> {
+                let last = attempt.streams.split(None, gap, &killed, &feed, &notify)?;
+                attempt.complete(last)
+            }
+        };
+
+        Ok((Self::submit(pid, killed, run), feed))
+    }
+
+    /// Read the standard input of dwatch for good, split in records ended by a line made of the
+    /// separator, or at pauses of at least `gap` without one, as `follow` does with the output of
+    /// a command. The job completes at the end of the input.
+    pub fn stdin(
+        separator: Option<String>,
+        gap: Duration,
+        notify: impl Fn() + Send + 'static,
+    ) -> Result<(Self, Feed)> {
+        let input = File::from(std::io::stdin().as_fd().try_clone_to_owned()?);
+        let mut streams = Streams {
+            pipes: vec![Pipe::new(input)],
+        };
+        let killed = Arc::new(AtomicBool::new(false));
+        let feed = Feed::default();
+
+        let run = {
+            let (killed, feed) = (Arc::clone(&killed), feed.clone());
+            move || -> Result<Output> {
+                let separator = separator.as_deref().map(str::as_bytes);
+                let last = streams.split(separator, gap, &killed, &feed, &notify)?;
+                Ok(Output {
+                    status: ExitStatus::from_raw(0),
+                    stdout: last.stdout,
+                    stderr: last.stderr,
+                })
+            }
+        };
+
+        Ok((Self::submit(Arc::default(), killed, run), feed))
+    }
+
+    /// Run the function on the pool, as a job that cannot be killed.
+    pub fn call(run: impl FnOnce() -> Result<Output> + Send + 'static) -> Self {
+        Self::submit(Arc::default(), Arc::default(), run)
+    }
+
+    fn submit(
+        pid: Arc<AtomicU32>,
+        killed: Arc<AtomicBool>,
+        run: impl FnOnce() -> Result<Output> + Send + 'static,
+    ) -> Self {
+        let slot = Arc::new(Slot::default());
+        let done = Arc::clone(&slot);
+        let started = Instant::now();
+        POOL.submit(Box::new(move || {
+            let output = run();
+            *done.output.lock().unwrap() = Some((output, started.elapsed()));
+            done.ready.notify_all();
+        }));
+        Self { pid, killed, slot }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.slot.output.lock().unwrap().is_some()
+    }
+
+    /// Kill the command and the processes it started (no further attempt is made), its output is
+    /// still collected by `wait`.
+    pub fn kill(&self) {
+        self.killed.store(true, Ordering::Relaxed);
+        let pid = self.pid.load(Ordering::Relaxed);
+        // jobs not running a command have no process
+        if pid != 0 && !self.is_finished() {
+            kill_group(pid);
+        }
+    }
+
+    pub fn wait(self) -> Result<Output> {
+        self.wait_timed().0
+    }
+
+    /// Wait for the job to complete, returning its output and how long it ran (all attempts
+    /// included).
+    pub fn wait_timed(self) -> (Result<Output>, Duration) {
+        let mut output = self.slot.output.lock().unwrap();
+        loop {
+            if let Some(output) = output.take() {
+                return output;
+            }
+            output = self.slot.ready.wait(output).unwrap();
+        }
+    }
+}
+
+/// Run the shell command with the input as its standard input, returning its standard output
+/// (whatever its exit status, e.g. that of a grep matching nothing).
+pub fn filter(cmd: &str, input: &[u8]) -> Result<Vec<u8>> {
+    let mut child = Command::new("sh")
+        .args(["-c", cmd])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()?;
+    // written from another thread, the filter may output as much as it reads
+    let mut stdin = child.stdin.take().unwrap();
+    let input = input.to_vec();
+    let writer = std::thread::spawn(move || stdin.write_all(&input));
+    let output = child.wait_with_output()?;
+    // a filter that does not read its whole input closes it early
+    let _ = writer.join();
+    Ok(output.stdout)
+}
+
+/// Run a hook, a shell command reacting to what dwatch observed, in the background with the given
+/// environment. Its output is discarded and its exit status ignored.
+pub fn hook(cmd: &str, env: Vec<(&'static str, String)>) {
+    let mut command = Command::new("sh");
+    command
+        .args(["-c", cmd])
+        .envs(env)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null());
+    POOL.submit(Box::new(move || {
+        let _ = command.status();
+    }));
+}
+
+/// The command leads its own process group (or session), which is killed as a whole.
+fn kill_group(pid: u32) {
+    // SAFETY: kill has no memory safety requirements.
+    unsafe {
+        libc::kill(-(pid as libc::pid_t), libc::SIGKILL);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sh(script: &str) -> impl FnMut() -> Command + Send + 'static {
+        let script = script.to_owned();
+        move || {
+            let mut cmd = Command::new("sh");
+            cmd.args(["-c", &script]);
+            cmd
+        }
+    }
+
+    fn run(script: &str, pty: bool, timeout: Option<Duration>) -> Result<Output> {
+        Job::start(sh(script), pty, timeout, Retry::default())?.wait()
+    }
+
+    #[test]
+    fn test_pool() {
+        let pool = Pool::new();
+        let (tx, rx) = mpsc::channel();
+        for _ in 0..2 {
+            let tx = tx.clone();
+            pool.submit(Box::new(move || {
+                tx.send(std::thread::current().id()).unwrap()
+            }));
+            rx.recv().unwrap();
+            while pool.idle.load(Ordering::Acquire) == 0 {
+                std::thread::sleep(POLL_INTERVAL);
+            }
+        }
+        // the second task ran on the worker started for the first one
+        assert_eq!(pool.idle.load(Ordering::Acquire), 1);
+    }
+
+    #[test]
+    fn test_output() {
+        let output = run("echo out; echo err >&2", false, None).unwrap();
+        assert!(output.status.success());
+        assert_eq!(output.stdout, b"out\n");
+        assert_eq!(output.stderr, b"err\n");
+
+        let output = run("test -t 1 && echo tty", true, None).unwrap();
+        assert!(output.status.success());
+        assert_eq!(output.stdout, b"tty\n");
+    }
+
+    #[test]
+    fn test_timeout() {
+        for pty in [false, true] {
+            let start = Instant::now();
+            let timeout = Some(Duration::from_millis(200));
+            // the background sleep keeps the output open, unless it is killed as well
+            let output = run("echo early; sleep 10 & sleep 10", pty, timeout).unwrap();
+            assert!(!output.status.success());
+            assert_eq!(output.stdout, b"early\n");
+            assert!(start.elapsed() < Duration::from_secs(5));
+        }
+    }
+
+    #[test]
+    fn test_kill() {
+        let job = Job::start(sh("echo early; sleep 10"), false, None, Retry::default()).unwrap();
+        std::thread::sleep(Duration::from_millis(100));
+        assert!(!job.is_finished());
+        job.kill();
+        let output = job.wait().unwrap();
+        assert!(!output.status.success());
+        assert_eq!(output.stdout, b"early\n");
+    }
+
+    #[test]
+    fn test_follow() {
+        let (tx, rx) = mpsc::channel();
+        let (job, feed) = Job::follow(
+            &mut sh("echo 1; echo 2; sleep 0.3; echo 3")(),
+            false,
+            Duration::from_millis(100),
+            move || tx.send(()).unwrap(),
+        )
+        .unwrap();
+        rx.recv().unwrap();
+        assert_eq!(feed.take().map(|b| b.stdout), Some(b"1\n2\n".to_vec()));
+        assert_eq!(feed.take(), None);
+
+        let output = job.wait().unwrap();
+        assert!(output.status.success());
+        assert_eq!(output.stdout, b"3\n");
+    }
+
+    #[test]
+    fn test_cut_record() {
+        let mut data = b"a 1\n---\nb 2\r\n---\r\nc".to_vec();
+        assert_eq!(cut_record(&mut data, b"---"), Some(b"a 1\n".to_vec()));
+        assert_eq!(cut_record(&mut data, b"---"), Some(b"b 2\r\n".to_vec()));
+        assert_eq!(cut_record(&mut data, b"---"), None);
+        assert_eq!(data, b"c");
+    }
+
+    #[test]
+    fn test_filter() {
+        assert_eq!(filter("grep eth0", b"lo 1\neth0 2\n").unwrap(), b"eth0 2\n");
+        assert_eq!(filter("grep wlan0", b"lo 1\n").unwrap(), b"");
+        assert_eq!(filter("head -1", &b"x\n".repeat(100_000)).unwrap(), b"x\n");
+    }
+
+    #[test]
+    fn test_hook() {
+        let file = std::env::temp_dir().join(format!("dwatch-hook-{}", std::process::id()));
+        let _ = std::fs::remove_file(&file);
+        hook(
+            &format!("echo $DWATCH_CHANGED > {}", file.display()),
+            vec![("DWATCH_CHANGED", "1 3".to_owned())],
+        );
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while std::fs::read_to_string(&file).map_or(true, |s| !s.ends_with('\n'))
+            && Instant::now() < deadline
+        {
+            std::thread::sleep(POLL_INTERVAL);
+        }
+        assert_eq!(std::fs::read_to_string(&file).unwrap(), "1 3\n");
+        let _ = std::fs::remove_file(&file);
+    }
+
+    #[test]
+    fn test_retry() {
+        let file = std::env::temp_dir().join(format!("dwatch-retry-{}", std::process::id()));
+        let _ = std::fs::remove_file(&file);
+        // fails twice, then succeeds
+        let script = format!(
+            "echo x >> {0}; test $(wc -l < {0}) -ge 3 && echo ok",
+            file.display()
+        );
+        let retry = Retry {
+            attempts: 1,
+            delay: Duration::from_millis(10),
+        };
+        let output = Job::start(sh(&script), false, None, retry)
+            .unwrap()
+            .wait()
+            .unwrap();
+        assert!(!output.status.success());
+
+        let output = Job::start(sh(&script), false, None, retry)
+            .unwrap()
+            .wait()
+            .unwrap();
+        assert!(output.status.success());
+        assert_eq!(output.stdout, b"ok\n");
+        let _ = std::fs::remove_file(&file);
+    }
+}
@@ -0,0 +1,113 @@
+//! Prometheus exposition endpoint (`--prometheus`), serving the numbers of the last frame as
+//! metrics to scrapers.
+
+use std::{
+    io::{Read, Write},
+    net::{TcpListener, TcpStream},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use anyhow::{Context as _, Result};
+
+/// Time given to a scraper to send its request.
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Handle on the served metrics, replaced at every frame.
+pub struct Exporter {
+    metrics: Arc<Mutex<String>>,
+}
+
+impl Exporter {
+    /// Replace the served metrics, in the text exposition format.
+    pub fn publish(&self, metrics: String) {
+        *self.metrics.lock().unwrap() = metrics;
+    }
+}
+
+/// Listen on an address such as `:9101` (all interfaces) or `127.0.0.1:9101`.
+pub fn serve(addr: &str) -> Result<Exporter> {
+    let bind = match addr.strip_prefix(':') {
+        Some(port) => format!("0.0.0.0:{}", port),
+        None => addr.to_owned(),
+    };
+    let listener =
+        TcpListener::bind(&bind).with_context(|| format!("cannot listen on {}", addr))?;
+    Ok(spawn(listener))
+}
+
+/// Answer the scrapers connecting to the listener from a thread, one at a time.
+fn spawn(listener: TcpListener) -> Exporter {
+    let metrics = Arc::new(Mutex::new(String::new()));
+    let served = Arc::clone(&metrics);
+    std::thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            // a scraper going away is no concern of dwatch
+            let _ = respond(stream, &served);
+        }
+    });
+    Exporter { metrics }
+}
+
+fn respond(mut stream: TcpStream, metrics: &Mutex<String>) -> std::io::Result<()> {
+    stream.set_read_timeout(Some(READ_TIMEOUT))?;
+    let mut request = Vec::new();
+    let mut buf = [0u8; 1024];
+    while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+        let n = stream.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        request.extend_from_slice(&buf[..n]);
+    }
+
+    let request = String::from_utf8_lossy(&request);
+    let mut words = request.split_whitespace();
+    let (method, path) = (words.next(), words.next().unwrap_or_default());
+    let path = path.split('?').next().unwrap_or_default();
+    let (status, body) = match (method, path) {
+        (Some("GET"), "/" | "/metrics") => ("200 OK", metrics.lock().unwrap().clone()),
+        (Some("GET"), _) => ("404 Not Found", String::new()),
+        _ => ("405 Method Not Allowed", String::new()),
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )?;
+    stream.flush()
+}
+
+/// Escape a label value.
+pub fn label(value: &str) -> String {
+    value
+        .replace('\\', r"\\")
+        .replace('"', "\\\"")
+        .replace('\n', r"\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http;
+
+    #[test]
+    fn test_label() {
+        assert_eq!(label("echo \"a\\b\"\n"), r#"echo \"a\\b\"\n"#);
+    }
+
+    #[test]
+    fn test_serve() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let exporter = spawn(listener);
+        exporter.publish("dwatch_value 1\n".to_owned());
+
+        let url = format!("http://{}/metrics", addr);
+        assert_eq!(http::get(&url, None).unwrap(), b"dwatch_value 1\n");
+        let url = format!("http://{}/other", addr);
+        assert!(http::get(&url, None).is_err());
+    }
+}
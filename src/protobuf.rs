@@ -0,0 +1,91 @@
+//! Just enough Protocol Buffers to encode the messages dwatch streams over `--grpc`, whose schema
+//! is in `proto/dwatch.proto`.
+
+/// A message being encoded, field by field.
+#[derive(Default)]
+pub struct Message {
+    bytes: Vec<u8>,
+}
+
+const VARINT: u64 = 0;
+const FIXED64: u64 = 1;
+const LENGTH_DELIMITED: u64 = 2;
+
+impl Message {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn tag(&mut self, field: u32, wire_type: u64) {
+        varint(&mut self.bytes, (field as u64) << 3 | wire_type);
+    }
+
+    /// An `int64`, `uint64` or `uint32` field.
+    pub fn uint(mut self, field: u32, value: u64) -> Self {
+        self.tag(field, VARINT);
+        varint(&mut self.bytes, value);
+        self
+    }
+
+    /// A `sint64` field, zigzag encoded.
+    pub fn sint(mut self, field: u32, value: i64) -> Self {
+        self.tag(field, VARINT);
+        varint(&mut self.bytes, ((value << 1) ^ (value >> 63)) as u64);
+        self
+    }
+
+    pub fn double(mut self, field: u32, value: f64) -> Self {
+        self.tag(field, FIXED64);
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+        self
+    }
+
+    pub fn bytes(mut self, field: u32, value: &[u8]) -> Self {
+        self.tag(field, LENGTH_DELIMITED);
+        varint(&mut self.bytes, value.len() as u64);
+        self.bytes.extend_from_slice(value);
+        self
+    }
+
+    pub fn string(self, field: u32, value: &str) -> Self {
+        self.bytes(field, value.as_bytes())
+    }
+
+    /// An embedded message field.
+    pub fn message(self, field: u32, message: Message) -> Self {
+        self.bytes(field, &message.bytes)
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+}
+
+fn varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_message() {
+        let message = Message::new()
+            .uint(1, 150)
+            .sint(2, -2)
+            .string(3, "ab")
+            .message(4, Message::new().double(1, 1.0));
+        assert_eq!(
+            message.into_bytes(),
+            [
+                0x08, 0x96, 0x01, 0x10, 0x03, 0x1a, 0x02, b'a', b'b', 0x22, 0x09, 0x09, 0, 0, 0, 0,
+                0, 0, 0xf0, 0x3f
+            ]
+        );
+    }
+}
@@ -0,0 +1,136 @@
+//! Run commands under a pseudo-terminal, for the tools that format their output differently when
+//! it is not a terminal (`--pty`).
+
+use std::{
+    fs::File,
+    os::fd::{AsRawFd, FromRawFd, OwnedFd},
+    os::unix::process::CommandExt,
+    process::{Child, Command, Stdio},
+};
+
+use anyhow::{anyhow, Result};
+
+/// Size of the terminal dwatch runs in, 80x24 when stdout is not a terminal.
+pub fn window_size() -> libc::winsize {
+    // SAFETY: TIOCGWINSZ only writes the winsize structure passed in.
+    unsafe {
+        let mut ws: libc::winsize = std::mem::zeroed();
+        if libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut ws) != 0 || ws.ws_col == 0 {
+            ws.ws_row = 24;
+            ws.ws_col = 80;
+        }
+        ws
+    }
+}
+
+/// Open a pseudo-terminal pair with the size of the real terminal, returning (master, slave).
+fn open() -> Result<(File, OwnedFd)> {
+    // SAFETY: the descriptors are checked and owned right away, ptsname_r writes at most
+    // buf.len() bytes, and the termios and winsize structures are owned locally.
+    unsafe {
+        let master = libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY | libc::O_CLOEXEC);
+        if master < 0 {
+            return Err(anyhow!("pty: {}", std::io::Error::last_os_error()));
+        }
+        let master = File::from_raw_fd(master);
+
+        let mut buf = [0 as libc::c_char; 128];
+        if libc::grantpt(master.as_raw_fd()) != 0
+            || libc::unlockpt(master.as_raw_fd()) != 0
+            || libc::ptsname_r(master.as_raw_fd(), buf.as_mut_ptr(), buf.len()) != 0
+        {
+            return Err(anyhow!("pty: {}", std::io::Error::last_os_error()));
+        }
+
+        let slave = libc::open(
+            buf.as_ptr(),
+            libc::O_RDWR | libc::O_NOCTTY | libc::O_CLOEXEC,
+        );
+        if slave < 0 {
+            return Err(anyhow!("pty: {}", std::io::Error::last_os_error()));
+        }
+        let slave = OwnedFd::from_raw_fd(slave);
+
+        // plain newlines, as if the output went to a pipe
+        let mut tio: libc::termios = std::mem::zeroed();
+        if libc::tcgetattr(slave.as_raw_fd(), &mut tio) == 0 {
+            tio.c_oflag &= !libc::ONLCR;
+            libc::tcsetattr(slave.as_raw_fd(), libc::TCSANOW, &tio);
+        }
+        libc::ioctl(slave.as_raw_fd(), libc::TIOCSWINSZ, &window_size());
+
+        Ok((master, slave))
+    }
+}
+
+/// Remove the escape sequences (colors, cursor movements) a program emits for a terminal, dwatch
+/// applies its own styling.
+pub fn strip_escapes(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\x1b' => match chars.next() {
+                // CSI: parameters up to a final byte
+                Some('[') => {
+                    for c in chars.by_ref() {
+                        if ('\x40'..='\x7e').contains(&c) {
+                            break;
+                        }
+                    }
+                }
+                // OSC: up to BEL or ST
+                Some(']') => {
+                    while let Some(c) = chars.next() {
+                        if c == '\x07' || (c == '\x1b' && chars.next_if_eq(&'\\').is_some()) {
+                            break;
+                        }
+                    }
+                }
+                _ => {}
+            },
+            '\r' => {}
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// Start the command with a pseudo-terminal as its controlling terminal and standard output (and
+/// error), returning the master side to read the output from. The command leads a new session.
+pub fn spawn(cmd: &mut Command) -> Result<(Child, File)> {
+    let (master, slave) = open()?;
+
+    cmd.stdin(Stdio::null())
+        .stdout(Stdio::from(slave.try_clone()?))
+        .stderr(Stdio::from(slave.try_clone()?));
+    // SAFETY: setsid and ioctl are async-signal-safe.
+    unsafe {
+        cmd.pre_exec(|| {
+            if libc::setsid() < 0 || libc::ioctl(libc::STDOUT_FILENO, libc::TIOCSCTTY, 0) < 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+    let child = cmd.spawn();
+    // reads from the master end once the child (and its descendants) closed the terminal, so no
+    // copy of the slave side can be kept here, including those held by the command
+    cmd.stdout(Stdio::null()).stderr(Stdio::null());
+    drop(slave);
+
+    Ok((child?, master))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_strip_escapes() {
+        assert_eq!(
+            strip_escapes("\x1b[01;34mdir\x1b[0m 42\r\n\x1b]0;title\x07x"),
+            "dir 42\nx"
+        );
+    }
+}
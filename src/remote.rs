@@ -0,0 +1,173 @@
+//! Commands run elsewhere than on the local host (`--host`, `--docker`, `--pod`), wrapped into the
+//! command that runs them there, and recognized back to label their output.
+
+use crate::shell;
+
+/// Options of ssh that take an argument.
+const SSH_ARG_OPTIONS: &str = "BbcDEeFIiJLlmOopQRSWw";
+
+/// Options of docker exec that take an argument.
+const DOCKER_ARG_OPTIONS: &str = "euw";
+const DOCKER_ARG_LONG_OPTIONS: [&str; 5] = [
+    "--env",
+    "--env-file",
+    "--user",
+    "--workdir",
+    "--detach-keys",
+];
+
+/// Options of kubectl exec that take an argument.
+const KUBECTL_ARG_OPTIONS: [&str; 8] = [
+    "-n",
+    "--namespace",
+    "-c",
+    "--container",
+    "--context",
+    "--kubeconfig",
+    "--cluster",
+    "--user",
+];
+
+/// Command running `cmd` on the host over ssh. Batch mode keeps ssh from prompting for a password
+/// in the middle of the display, keys (or an agent) must be set up.
+pub fn ssh(host: &str, cmd: &str) -> String {
+    format!(
+        "ssh -o BatchMode=yes {} {}",
+        shell::quote(host),
+        shell::quote(cmd)
+    )
+}
+
+/// Command running `cmd` in a container, through a shell as it would be run locally.
+pub fn docker(container: &str, cmd: &str) -> String {
+    format!(
+        "docker exec {} sh -c {}",
+        shell::quote(container),
+        shell::quote(cmd)
+    )
+}
+
+/// Command running `cmd` in a Kubernetes pod, given as `[NAMESPACE/]POD`.
+pub fn kubectl(pod: &str, cmd: &str) -> String {
+    let namespace = match pod.split_once('/') {
+        Some((namespace, _)) => format!("-n {} ", shell::quote(namespace)),
+        None => String::new(),
+    };
+    let pod = pod.rsplit('/').next().unwrap_or(pod);
+    format!(
+        "kubectl exec {}{} -- sh -c {}",
+        namespace,
+        shell::quote(pod),
+        shell::quote(cmd)
+    )
+}
+
+/// Where a command runs, if not locally: the host of an ssh command, the container of a docker
+/// one, the pod of a kubectl one (`namespace/pod`), or the host and what runs on it
+/// (`host/container`).
+pub fn target(cmd: &str) -> Option<String> {
+    let argv = shell::split(cmd);
+    let (program, args) = argv.split_first()?;
+    match program.as_str() {
+        "ssh" => {
+            let idx = operand(args, |opt| {
+                opt.strip_prefix('-')
+                    .and_then(|o| o.find(|c| SSH_ARG_OPTIONS.contains(c)).map(|i| i + 2))
+                    == Some(opt.len())
+            })?;
+            // the remote command is run by a shell, from its arguments joined
+            let remote = args[idx + 1..].join(" ");
+            Some(match target(&remote) {
+                Some(inner) => format!("{}/{}", args[idx], inner),
+                None => args[idx].clone(),
+            })
+        }
+        "docker" if args.first().is_some_and(|a| a == "exec") => {
+            let args = &args[1..];
+            let idx = operand(args, |opt| {
+                DOCKER_ARG_LONG_OPTIONS.contains(&opt)
+                    || (opt.len() == 2 && opt[1..].chars().all(|c| DOCKER_ARG_OPTIONS.contains(c)))
+            })?;
+            Some(args[idx].clone())
+        }
+        "kubectl" if args.first().is_some_and(|a| a == "exec") => {
+            let args = &args[1..];
+            let idx = operand(args, |opt| KUBECTL_ARG_OPTIONS.contains(&opt))?;
+            let namespace =
+                args[..idx]
+                    .iter()
+                    .enumerate()
+                    .find_map(|(i, opt)| match opt.as_str() {
+                        "-n" | "--namespace" => args.get(i + 1).map(String::as_str),
+                        _ => opt.strip_prefix("--namespace="),
+                    });
+            Some(match namespace {
+                Some(namespace) => format!("{}/{}", namespace, args[idx]),
+                None => args[idx].clone(),
+            })
+        }
+        _ => None,
+    }
+}
+
+/// Index of the first argument that is not an option, `takes_arg` telling the options whose
+/// argument is the next word (rather than attached).
+fn operand(args: &[String], takes_arg: impl Fn(&str) -> bool) -> Option<usize> {
+    let mut idx = 0;
+    while let Some(arg) = args.get(idx) {
+        if !arg.starts_with('-') || arg == "-" {
+            return Some(idx);
+        }
+        idx += if takes_arg(arg) { 2 } else { 1 };
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ssh() {
+        let cmd = ssh("alice@db1", "cat /proc/net/dev | grep eth0");
+        assert_eq!(
+            cmd,
+            "ssh -o BatchMode=yes alice@db1 'cat /proc/net/dev | grep eth0'"
+        );
+        assert_eq!(target(&cmd).as_deref(), Some("alice@db1"));
+        assert_eq!(
+            target("ssh -p 2222 -4v -lroot web2 uptime").as_deref(),
+            Some("web2")
+        );
+        assert_eq!(target("cat /proc/net/dev"), None);
+    }
+
+    #[test]
+    fn test_docker() {
+        let cmd = docker("web", "cat /proc/net/dev");
+        assert_eq!(cmd, "docker exec web sh -c 'cat /proc/net/dev'");
+        assert_eq!(target(&cmd).as_deref(), Some("web"));
+        assert_eq!(
+            target("docker exec -u root --env=A=1 -i db ps").as_deref(),
+            Some("db")
+        );
+        assert_eq!(target(&ssh("h1", &cmd)).as_deref(), Some("h1/web"));
+        assert_eq!(target("docker ps"), None);
+    }
+
+    #[test]
+    fn test_kubectl() {
+        let cmd = kubectl("prod/api-0", "cat /proc/net/dev");
+        assert_eq!(
+            cmd,
+            "kubectl exec -n prod api-0 -- sh -c 'cat /proc/net/dev'"
+        );
+        assert_eq!(target(&cmd).as_deref(), Some("prod/api-0"));
+        assert_eq!(
+            target("kubectl exec -c app --namespace=dev web -- ps").as_deref(),
+            Some("dev/web")
+        );
+        assert_eq!(target(&kubectl("db-0", "ps")).as_deref(), Some("db-0"));
+        assert_eq!(target("kubectl top pods"), None);
+    }
+}
@@ -0,0 +1,272 @@
+//! Recorded sessions (`dwatch record`): the raw output of the commands at every frame, with its
+//! timestamp, independent of how it is rendered. They are played back with `dwatch replay`, and
+//! their numbers written out with `dwatch export`.
+//!
+//! The file starts with a `dwatch-session 1` line, followed by blocks made of a header line and
+//! length-prefixed strings (a `<length>` line, the bytes and a newline):
+//! - `commands <n>`: the commands watched from then on, whenever they change,
+//! - `frame <unix time in ms> <n>`: the outputs of the commands at a frame, in order.
+
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+    rc::Rc,
+    time::Duration,
+};
+
+use anyhow::{anyhow, Context as _, Result};
+
+const MAGIC: &str = "dwatch-session 1";
+
+pub struct Recorder {
+    out: BufWriter<File>,
+    commands: Vec<String>,
+}
+
+impl Recorder {
+    /// Start recording to a file, replacing it.
+    pub fn create(path: &Path) -> Result<Self> {
+        let file =
+            File::create(path).with_context(|| format!("cannot create {}", path.display()))?;
+        let mut out = BufWriter::new(file);
+        writeln!(out, "{}", MAGIC)?;
+        Ok(Self {
+            out,
+            commands: Vec::new(),
+        })
+    }
+
+    /// Append the outputs of the commands at a frame.
+    pub fn frame<'a>(
+        &mut self,
+        timestamp: Duration,
+        commands: &[String],
+        outputs: impl ExactSizeIterator<Item = &'a str>,
+    ) -> Result<()> {
+        if commands != self.commands {
+            self.commands = commands.to_vec();
+            writeln!(self.out, "commands {}", commands.len())?;
+            for cmd in commands {
+                write_string(&mut self.out, cmd)?;
+            }
+        }
+        writeln!(
+            self.out,
+            "frame {} {}",
+            timestamp.as_millis(),
+            outputs.len()
+        )?;
+        for output in outputs {
+            write_string(&mut self.out, output)?;
+        }
+        // a session cut short (killed, crashed) keeps its frames
+        self.out.flush()?;
+        Ok(())
+    }
+}
+
+fn write_string(out: &mut impl Write, s: &str) -> Result<()> {
+    writeln!(out, "{}", s.len())?;
+    out.write_all(s.as_bytes())?;
+    writeln!(out)?;
+    Ok(())
+}
+
+/// A recorded frame.
+pub struct Frame {
+    pub timestamp: Duration,
+    pub commands: Rc<Vec<String>>,
+    pub outputs: Vec<String>,
+}
+
+/// Read the frames of a session file.
+pub fn load(path: &Path) -> Result<Vec<Frame>> {
+    let data = std::fs::read(path).with_context(|| format!("cannot read {}", path.display()))?;
+    parse(&data).with_context(|| format!("invalid session file {}", path.display()))
+}
+
+fn parse(mut data: &[u8]) -> Result<Vec<Frame>> {
+    if read_line(&mut data)? != MAGIC {
+        return Err(anyhow!("not a dwatch session"));
+    }
+    let mut commands = Rc::new(Vec::new());
+    let mut frames = Vec::new();
+    while !data.is_empty() {
+        let header = read_line(&mut data)?;
+        let words: Vec<&str> = header.split(' ').collect();
+        let number = |s: &str| {
+            s.parse::<u64>()
+                .map_err(|_| anyhow!("invalid header '{}'", header))
+        };
+        match words[..] {
+            ["commands", n] => {
+                commands = Rc::new(
+                    (0..number(n)?)
+                        .map(|_| read_string(&mut data))
+                        .collect::<Result<_>>()?,
+                );
+            }
+            ["frame", millis, n] => frames.push(Frame {
+                timestamp: Duration::from_millis(number(millis)?),
+                commands: Rc::clone(&commands),
+                outputs: (0..number(n)?)
+                    .map(|_| read_string(&mut data))
+                    .collect::<Result<_>>()?,
+            }),
+            _ => return Err(anyhow!("invalid header '{}'", header)),
+        }
+    }
+    if frames.is_empty() {
+        return Err(anyhow!("no frames recorded"));
+    }
+    Ok(frames)
+}
+
+fn read_line(data: &mut &[u8]) -> Result<String> {
+    let eol = data
+        .iter()
+        .position(|&b| b == b'\n')
+        .ok_or_else(|| anyhow!("truncated session"))?;
+    let line = String::from_utf8_lossy(&data[..eol]).into_owned();
+    *data = &data[eol + 1..];
+    Ok(line)
+}
+
+fn read_string(data: &mut &[u8]) -> Result<String> {
+    let header = read_line(data)?;
+    let len: usize = header
+        .parse()
+        .map_err(|_| anyhow!("invalid length '{}'", header))?;
+    let bytes = data
+        .get(..len)
+        .filter(|_| data.get(len) == Some(&b'\n'))
+        .ok_or_else(|| anyhow!("truncated session"))?;
+    let s = String::from_utf8_lossy(bytes).into_owned();
+    *data = &data[len + 1..];
+    Ok(s)
+}
+
+/// Playback of the frames of a session, one at a time.
+pub struct Player {
+    frames: Vec<Frame>,
+    /// index of the frame shown last
+    shown: Option<usize>,
+}
+
+impl Player {
+    pub fn new(frames: Vec<Frame>) -> Self {
+        Self {
+            frames,
+            shown: None,
+        }
+    }
+
+    /// Commands of the first frame.
+    pub fn commands(&self) -> Vec<String> {
+        self.frames[0].commands.to_vec()
+    }
+
+    /// Move to the frame following the one shown, or `seek` frames away from it (within the
+    /// session), and return it along with whether it directly follows the one shown, i.e.
+    /// whether deltas can be computed against it.
+    pub fn advance(&mut self, seek: i64) -> (&Frame, bool) {
+        let last = self.frames.len() as i64 - 1;
+        let target = match self.shown {
+            None => seek.clamp(0, last),
+            Some(shown) if seek == 0 => (shown as i64 + 1).min(last),
+            Some(shown) => (shown as i64 + seek).clamp(0, last),
+        } as usize;
+        let consecutive = self.shown.is_some_and(|shown| shown + 1 == target);
+        self.shown = Some(target);
+        (&self.frames[target], consecutive)
+    }
+
+    /// Recorded time between the frame shown and the previous one.
+    pub fn elapsed(&self) -> Option<Duration> {
+        let shown = self.shown?;
+        let previous = self.frames.get(shown.checked_sub(1)?)?;
+        Some(
+            self.frames[shown]
+                .timestamp
+                .saturating_sub(previous.timestamp),
+        )
+    }
+
+    /// Recorded time between the frame shown and the next one, none at the end of the session.
+    pub fn gap(&self) -> Option<Duration> {
+        let shown = self.shown?;
+        let next = self.frames.get(shown + 1)?;
+        Some(next.timestamp.saturating_sub(self.frames[shown].timestamp))
+    }
+
+    /// Position of the frame shown (from 1) and number of frames.
+    pub fn position(&self) -> (usize, usize) {
+        (self.shown.map_or(0, |s| s + 1), self.frames.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_recorder() {
+        let path = std::env::temp_dir().join(format!("dwatch-test-{}.dwr", std::process::id()));
+        let mut recorder = Recorder::create(&path).unwrap();
+        let commands = ["uptime".to_owned()];
+        recorder
+            .frame(
+                Duration::from_millis(1500),
+                &commands,
+                ["up 1\n"].into_iter(),
+            )
+            .unwrap();
+        recorder
+            .frame(
+                Duration::from_millis(2500),
+                &commands,
+                ["up 2\n"].into_iter(),
+            )
+            .unwrap();
+
+        let data = std::fs::read_to_string(&path).unwrap();
+        let frames = load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            data,
+            "dwatch-session 1\ncommands 1\n6\nuptime\nframe 1500 1\n5\nup 1\n\nframe 2500 1\n5\nup 2\n\n"
+        );
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[1].timestamp, Duration::from_millis(2500));
+        assert_eq!(*frames[1].commands, commands);
+        assert_eq!(frames[1].outputs, ["up 2\n"]);
+    }
+
+    #[test]
+    fn test_parse() {
+        assert!(parse(b"dwatch-session 1\n").is_err());
+        assert!(parse(b"dwatch-session 1\nframe 10 1\n5\nup\n").is_err());
+        assert!(parse(b"something else\n").is_err());
+    }
+
+    #[test]
+    fn test_player() {
+        let frame = |ms| Frame {
+            timestamp: Duration::from_millis(ms),
+            commands: Rc::new(vec!["uptime".to_owned()]),
+            outputs: vec![ms.to_string()],
+        };
+        let mut player = Player::new(vec![frame(0), frame(1000), frame(3000), frame(4000)]);
+        assert_eq!(player.advance(0).0.outputs, ["0"]);
+        assert_eq!(player.gap(), Some(Duration::from_secs(1)));
+        let (frame, consecutive) = player.advance(0);
+        assert_eq!((frame.outputs[0].as_str(), consecutive), ("1000", true));
+        assert_eq!(player.elapsed(), Some(Duration::from_secs(1)));
+        let (frame, consecutive) = player.advance(2);
+        assert_eq!((frame.outputs[0].as_str(), consecutive), ("4000", false));
+        assert_eq!(player.gap(), None);
+        assert_eq!(player.position(), (4, 4));
+        assert_eq!(player.advance(-10).0.outputs, ["0"]);
+    }
+}
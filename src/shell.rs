@@ -0,0 +1,77 @@
+//! Minimal POSIX shell word handling, used to run commands without a shell (`--exec`) while
+//! still showing and editing them as a single command line.
+
+/// Quote an argument so that `split` turns it back into a single word.
+pub fn quote(arg: &str) -> String {
+    let plain = |c: char| c.is_ascii_alphanumeric() || "-_./:=,+%@".contains(c);
+    if !arg.is_empty() && arg.chars().all(plain) {
+        arg.to_owned()
+    } else {
+        format!("'{}'", arg.replace('\'', r"'\''"))
+    }
+}
+
+/// Join arguments into a command line, quoting them as needed.
+pub fn join(args: &[String]) -> String {
+    args.iter().map(|a| quote(a)).collect::<Vec<_>>().join(" ")
+}
+
+/// Split a command line into words, honouring single and double quotes and backslash escapes.
+/// No expansion of any kind is performed.
+pub fn split(line: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut word: Option<String> = None;
+    let mut chars = line.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => words.extend(word.take()),
+            '\'' => {
+                let w = word.get_or_insert_with(String::new);
+                w.extend(chars.by_ref().take_while(|&c| c != '\''));
+            }
+            '"' => {
+                let w = word.get_or_insert_with(String::new);
+                while let Some(c) = chars.next() {
+                    match c {
+                        '"' => break,
+                        '\\' => match chars.next() {
+                            Some(e @ ('"' | '\\' | '$' | '`')) => w.push(e),
+                            Some(e) => w.extend(['\\', e]),
+                            None => w.push('\\'),
+                        },
+                        c => w.push(c),
+                    }
+                }
+            }
+            '\\' => word.get_or_insert_with(String::new).extend(chars.next()),
+            c => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    words.extend(word);
+    words
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split() {
+        assert_eq!(split("  ls -l  /tmp "), vec!["ls", "-l", "/tmp"]);
+        assert_eq!(
+            split(r#"grep 'a b' "c \"d\"" e\ f ''"#),
+            vec!["grep", "a b", "c \"d\"", "e f", ""]
+        );
+    }
+
+    #[test]
+    fn test_join() {
+        let args: Vec<String> = ["grep", "it's", "a b", "", "-x"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        assert_eq!(join(&args), r"grep 'it'\''s' 'a b' '' -x");
+        assert_eq!(split(&join(&args)), args);
+    }
+}
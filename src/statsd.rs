@@ -0,0 +1,94 @@
+//! StatsD (and DogStatsD) emitter for `--statsd`, pushing the numbers of every frame over UDP.
+
+use std::net::UdpSocket;
+
+use anyhow::{anyhow, Context as _, Result};
+
+/// Largest datagram sent, so that packets fit the MTU of common networks.
+const MAX_DATAGRAM: usize = 1432;
+
+pub struct Client {
+    socket: UdpSocket,
+}
+
+impl Client {
+    /// Send metric lines such as `dwatch.rx:10|g`, packed into as few datagrams as possible.
+    /// Metrics are fire and forget: a missing agent does not concern dwatch.
+    pub fn send(&self, metrics: &[String]) {
+        for datagram in pack(metrics) {
+            let _ = self.socket.send(datagram.as_bytes());
+        }
+    }
+}
+
+/// Prepare to send metrics to a `host:port` address.
+pub fn connect(addr: &str) -> Result<Client> {
+    let socket = UdpSocket::bind("0.0.0.0:0")
+        .or_else(|_| UdpSocket::bind("[::]:0"))
+        .context("statsd")?;
+    socket
+        .connect(addr)
+        .map_err(|e| anyhow!("statsd {}: {}", addr, e))?;
+    Ok(Client { socket })
+}
+
+/// Join metric lines with newlines into datagrams of at most `MAX_DATAGRAM` bytes (a longer
+/// line is sent alone).
+fn pack(metrics: &[String]) -> Vec<String> {
+    let mut datagrams = Vec::new();
+    let mut current = String::new();
+    for metric in metrics {
+        if !current.is_empty() && current.len() + 1 + metric.len() > MAX_DATAGRAM {
+            datagrams.push(std::mem::take(&mut current));
+        }
+        if !current.is_empty() {
+            current.push('\n');
+        }
+        current.push_str(metric);
+    }
+    if !current.is_empty() {
+        datagrams.push(current);
+    }
+    datagrams
+}
+
+/// Turn a label into a metric name component: runs of characters other than letters, digits,
+/// `-` and `_` become a single `_`, and leading or trailing ones are dropped.
+pub fn name(label: &str) -> String {
+    let mut out = String::with_capacity(label.len());
+    for c in label.chars() {
+        if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+            out.push(c);
+        } else if !out.is_empty() && !out.ends_with('_') {
+            out.push('_');
+        }
+    }
+    out.trim_end_matches('_').to_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_name() {
+        assert_eq!(name("eth0: "), "eth0");
+        assert_eq!(name(", tx bytes "), "tx_bytes");
+        assert_eq!(name("  "), "");
+    }
+
+    #[test]
+    fn test_send() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let client = connect(&server.local_addr().unwrap().to_string()).unwrap();
+
+        let metrics: Vec<_> = (0..100).map(|i| format!("dwatch.n{}:{}|g", i, i)).collect();
+        assert!(pack(&metrics).len() > 1);
+        assert!(pack(&metrics).iter().all(|d| d.len() <= MAX_DATAGRAM));
+
+        client.send(&metrics[..2]);
+        let mut buf = [0u8; MAX_DATAGRAM];
+        let n = server.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"dwatch.n0:0|g\ndwatch.n1:1|g");
+    }
+}
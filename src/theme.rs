@@ -0,0 +1,133 @@
+use ansi_term::{Colour, Style};
+
+/// Palette used by the writers, one entry per rendering role.
+#[derive(Debug, Clone)]
+pub struct Theme {
+    pub name: &'static str,
+    /// absolute values
+    pub value: Style,
+    /// raw deltas
+    pub delta: Style,
+    /// rates of plain counters
+    pub rate: Style,
+    /// rates of bit/byte counters
+    pub throughput: Style,
+    /// min/max statistics
+    pub stats: Style,
+    /// standard error of the commands
+    pub stderr: Style,
+    /// headers of the output of remote commands
+    pub section: Style,
+    /// values breaching an alert, whatever their writer
+    pub alert: Style,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self {
+            name: "default",
+            value: Colour::Blue.normal(),
+            delta: Colour::Red.normal(),
+            rate: Colour::Red.bold(),
+            throughput: Colour::Green.bold(),
+            stats: Colour::Black.bold(),
+            stderr: Colour::Yellow.normal(),
+            section: Style::new().bold().underline(),
+            alert: Colour::White.on(Colour::Red).bold(),
+        }
+    }
+}
+
+lazy_static! {
+    static ref THEMES: Vec<Theme> = vec![
+        Theme::default(),
+        Theme {
+            name: "solarized-dark",
+            value: Colour::RGB(0x26, 0x8b, 0xd2).normal(),
+            delta: Colour::RGB(0xcb, 0x4b, 0x16).normal(),
+            rate: Colour::RGB(0xdc, 0x32, 0x2f).bold(),
+            throughput: Colour::RGB(0x85, 0x99, 0x00).bold(),
+            stats: Colour::RGB(0x93, 0xa1, 0xa1).normal(),
+            stderr: Colour::RGB(0xb5, 0x89, 0x00).normal(),
+            section: Colour::RGB(0x6c, 0x71, 0xc4).bold(),
+            alert: Colour::RGB(0xfd, 0xf6, 0xe3)
+                .on(Colour::RGB(0xdc, 0x32, 0x2f))
+                .bold(),
+        },
+        Theme {
+            name: "solarized-light",
+            value: Colour::RGB(0x26, 0x8b, 0xd2).normal(),
+            delta: Colour::RGB(0xcb, 0x4b, 0x16).normal(),
+            rate: Colour::RGB(0xdc, 0x32, 0x2f).bold(),
+            throughput: Colour::RGB(0x85, 0x99, 0x00).bold(),
+            stats: Colour::RGB(0x58, 0x6e, 0x75).normal(),
+            stderr: Colour::RGB(0xb5, 0x89, 0x00).normal(),
+            section: Colour::RGB(0x6c, 0x71, 0xc4).bold(),
+            alert: Colour::RGB(0xfd, 0xf6, 0xe3)
+                .on(Colour::RGB(0xdc, 0x32, 0x2f))
+                .bold(),
+        },
+        Theme {
+            name: "high-contrast",
+            value: Colour::White.bold(),
+            delta: Colour::Yellow.bold(),
+            rate: Colour::Red.bold(),
+            throughput: Colour::Green.bold(),
+            stats: Colour::Cyan.normal(),
+            stderr: Colour::Purple.bold(),
+            section: Colour::White.bold().underline(),
+            alert: Colour::White.on(Colour::Red).bold(),
+        },
+        Theme {
+            name: "none",
+            value: Style::new(),
+            delta: Style::new(),
+            rate: Style::new().bold(),
+            throughput: Style::new().bold(),
+            stats: Style::new().dimmed(),
+            stderr: Style::new().italic(),
+            section: Style::new().bold(),
+            alert: Style::new().reverse().bold(),
+        },
+    ];
+}
+
+impl Theme {
+    pub fn by_name(name: &str) -> Option<Theme> {
+        THEMES.iter().find(|t| t.name == name).cloned()
+    }
+
+    /// The same palette in reverse video, used to highlight the focused value.
+    pub fn reversed(&self) -> Theme {
+        Theme {
+            name: self.name,
+            value: self.value.reverse(),
+            delta: self.delta.reverse(),
+            rate: self.rate.reverse(),
+            throughput: self.throughput.reverse(),
+            stats: self.stats.reverse(),
+            stderr: self.stderr.reverse(),
+            section: self.section.reverse(),
+            alert: self.alert.reverse(),
+        }
+    }
+
+    /// A palette without any styling, for text styled as a whole afterwards.
+    pub fn plain() -> Theme {
+        Theme {
+            name: "plain",
+            value: Style::new(),
+            delta: Style::new(),
+            rate: Style::new(),
+            throughput: Style::new(),
+            stats: Style::new(),
+            stderr: Style::new(),
+            section: Style::new(),
+            alert: Style::new(),
+        }
+    }
+
+    pub fn names() -> Vec<&'static str> {
+        THEMES.iter().map(|t| t.name).collect()
+    }
+}
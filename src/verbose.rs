@@ -0,0 +1,76 @@
+//! Debug log of `--verbose`: when and why frames are rendered, what the runs of the commands took,
+//! which lines had their deltas restarted and what became of the style map, one event per line
+//! on the standard error or in the file given by `--verbose-log`.
+//!
+//! ```text
+//!      2.004 schedule: frame 3, interval 1s
+//!      2.011 command: 'ip -s link' ✓ in 6ms
+//!      2.012 parse: 'ip -s link' line 4: 6 numbers, deltas restarted (new line)
+//! ```
+
+use std::{
+    fmt,
+    io::Write,
+    path::Path,
+    sync::{Mutex, OnceLock},
+    time::Instant,
+};
+
+use anyhow::{Context as _, Result};
+
+struct Log {
+    start: Instant,
+    out: Mutex<Box<dyn Write + Send>>,
+}
+
+static LOG: OnceLock<Log> = OnceLock::new();
+
+/// Log the events from now on, to a file (appended to) or to the standard error.
+pub fn init(path: Option<&Path>) -> Result<()> {
+    let out: Box<dyn Write + Send> = match path {
+        Some(path) => Box::new(
+            std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .with_context(|| format!("cannot open {}", path.display()))?,
+        ),
+        None => Box::new(std::io::stderr()),
+    };
+    let _ = LOG.set(Log {
+        start: Instant::now(),
+        out: Mutex::new(out),
+    });
+    Ok(())
+}
+
+pub fn enabled() -> bool {
+    LOG.get().is_some()
+}
+
+/// Log an event of the given kind, timed from the start of the session.
+pub fn write(kind: &str, message: fmt::Arguments) {
+    if let Some(log) = LOG.get() {
+        let mut out = log.out.lock().unwrap();
+        let _ = writeln!(
+            out,
+            "{:>10.3} {}: {}",
+            log.start.elapsed().as_secs_f64(),
+            kind,
+            message
+        );
+        let _ = out.flush();
+    }
+}
+
+/// Log an event, e.g. `verbose::event!("schedule", "frame {}", n)`: the message is not even
+/// formatted unless `--verbose` is given.
+macro_rules! event {
+    ($kind:expr, $($arg:tt)*) => {
+        if $crate::verbose::enabled() {
+            $crate::verbose::write($kind, format_args!($($arg)*));
+        }
+    };
+}
+
+pub(crate) use event;
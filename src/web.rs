@@ -0,0 +1,354 @@
+//! Web dashboard (`--serve`): a small page showing the current frame, its numbers with their
+//! deltas and sparklines, kept up to date with server-sent events.
+//!
+//! The state is also exposed read-only as JSON, for scripts polling a running dwatch:
+//! - `/api/values`: the numbers of the last frame,
+//! - `/api/history/<key>`: the last samples of a number, by the key given in `/api/values`,
+//! - `/api/alerts`: the alerts, and whether they are firing.
+
+use std::{
+    collections::{BTreeMap, VecDeque},
+    io::{Read, Write},
+    net::{TcpListener, TcpStream},
+    sync::{
+        mpsc::{self, Sender},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use anyhow::{Context as _, Result};
+
+use crate::json;
+
+/// Time given to a browser to send its request, or to take an event.
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Samples kept for each number by `/api/history`.
+const HISTORY_LEN: usize = 1000;
+
+/// A frame published to the dashboard.
+pub struct Update {
+    /// The frame as sent to the browsers, with the output of the commands.
+    pub frame: String,
+    /// The numbers of the frame, served by `/api/values`.
+    pub values: String,
+    /// The numbers of the frame, by key.
+    pub samples: Vec<(String, i64)>,
+    pub timestamp: Duration,
+}
+
+/// State served to the browsers and the API, as JSON documents.
+#[derive(Default)]
+struct Shared {
+    /// Last frame, sent first to the browsers connecting.
+    last: Mutex<String>,
+    /// Browsers following the events.
+    clients: Mutex<Vec<Sender<String>>>,
+    values: Mutex<String>,
+    /// Last samples of the numbers of the last frame, by key.
+    history: Mutex<BTreeMap<String, VecDeque<(Duration, i64)>>>,
+    alerts: Mutex<String>,
+}
+
+/// Handle on the dashboard, to which every frame is published.
+pub struct Dashboard {
+    shared: Arc<Shared>,
+}
+
+impl Dashboard {
+    /// Send a frame to the browsers, forgetting those that went away, and serve it from the API.
+    pub fn publish(&self, update: Update) {
+        self.shared
+            .clients
+            .lock()
+            .unwrap()
+            .retain(|tx| tx.send(update.frame.clone()).is_ok());
+        *self.shared.last.lock().unwrap() = update.frame;
+        *self.shared.values.lock().unwrap() = update.values;
+
+        // the numbers gone from the output are forgotten
+        let mut history = self.shared.history.lock().unwrap();
+        let mut previous = std::mem::take(&mut *history);
+        for (key, value) in update.samples {
+            let mut samples = previous.remove(&key).unwrap_or_default();
+            if samples.len() == HISTORY_LEN {
+                samples.pop_front();
+            }
+            samples.push_back((update.timestamp, value));
+            history.insert(key, samples);
+        }
+    }
+
+    /// Replace the alerts served by the API.
+    pub fn publish_alerts(&self, alerts: String) {
+        *self.shared.alerts.lock().unwrap() = alerts;
+    }
+}
+
+/// Listen on an address such as `:8080` (all interfaces) or `127.0.0.1:8080`.
+pub fn serve(addr: &str) -> Result<Dashboard> {
+    let bind = match addr.strip_prefix(':') {
+        Some(port) => format!("0.0.0.0:{}", port),
+        None => addr.to_owned(),
+    };
+    let listener =
+        TcpListener::bind(&bind).with_context(|| format!("cannot listen on {}", addr))?;
+    Ok(spawn(listener))
+}
+
+/// Answer the browsers connecting to the listener, each from its own thread since the events
+/// are streamed for as long as the page is open.
+fn spawn(listener: TcpListener) -> Dashboard {
+    // until the first frame
+    let shared = Arc::new(Shared {
+        values: Mutex::new("null".to_owned()),
+        alerts: Mutex::new("[]".to_owned()),
+        ..Default::default()
+    });
+    let served = Arc::clone(&shared);
+    std::thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let shared = Arc::clone(&served);
+            // a browser going away is no concern of dwatch
+            std::thread::spawn(move || respond(stream, &shared));
+        }
+    });
+    Dashboard { shared }
+}
+
+fn respond(mut stream: TcpStream, shared: &Shared) -> std::io::Result<()> {
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+    let mut request = Vec::new();
+    let mut buf = [0u8; 1024];
+    while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+        let n = stream.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        request.extend_from_slice(&buf[..n]);
+    }
+
+    let request = String::from_utf8_lossy(&request);
+    let mut words = request.split_whitespace();
+    let (method, path) = (words.next(), words.next().unwrap_or_default());
+    let path = path.split('?').next().unwrap_or_default();
+    const JSON: &str = "application/json";
+    let (status, content_type, body) = match (method, path) {
+        (Some("GET"), "/events") => return stream_events(stream, shared),
+        (Some("GET"), "/") => ("200 OK", "text/html; charset=utf-8", PAGE.to_owned()),
+        (Some("GET"), "/api/values") => ("200 OK", JSON, shared.values.lock().unwrap().clone()),
+        (Some("GET"), "/api/alerts") => ("200 OK", JSON, shared.alerts.lock().unwrap().clone()),
+        (Some("GET"), path) => match path
+            .strip_prefix("/api/history/")
+            .and_then(|key| history(shared, key))
+        {
+            Some(samples) => ("200 OK", JSON, samples),
+            None => ("404 Not Found", "text/plain", String::new()),
+        },
+        _ => ("405 Method Not Allowed", "text/plain", String::new()),
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    )?;
+    stream.flush()
+}
+
+/// The samples of the number with the given key, as a JSON object.
+fn history(shared: &Shared, key: &str) -> Option<String> {
+    let history = shared.history.lock().unwrap();
+    let samples = history.get(key)?.iter().map(|(timestamp, value)| {
+        json::object([
+            (
+                "timestamp",
+                json::number(timestamp.as_millis() as f64 / 1000.0),
+            ),
+            ("value", value.to_string()),
+        ])
+    });
+    Some(json::object([
+        ("key", json::string(key)),
+        ("samples", json::array(samples)),
+    ]))
+}
+
+/// Send the last frame and then every new one, until the browser goes away.
+fn stream_events(mut stream: TcpStream, shared: &Shared) -> std::io::Result<()> {
+    let (tx, rx) = mpsc::channel();
+    let last = shared.last.lock().unwrap().clone();
+    shared.clients.lock().unwrap().push(tx);
+
+    write!(
+        stream,
+        "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\nConnection: close\r\n\r\n"
+    )?;
+    let frames = (!last.is_empty()).then_some(last).into_iter().chain(rx);
+    for frame in frames {
+        write!(stream, "data: {}\n\n", frame)?;
+        stream.flush()?;
+    }
+    Ok(())
+}
+
+const PAGE: &str = r##"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>dwatch</title>
+<style>
+body { font-family: sans-serif; margin: 1em; background: #fafafa; }
+pre { background: #fff; border: 1px solid #ddd; padding: .5em; overflow-x: auto; }
+table { border-collapse: collapse; }
+td, th { padding: .15em .6em; text-align: right; border-bottom: 1px solid #eee; }
+td.key, th.key { text-align: left; font-family: monospace; }
+.up { color: #080; } .down { color: #c00; }
+#status { color: #888; font-size: small; }
+</style>
+</head>
+<body>
+<div id="status">connecting...</div>
+<div id="frame"></div>
+<script>
+const HISTORY = 60;
+const history = new Map();
+
+function el(tag, props, children) {
+  const e = Object.assign(document.createElement(tag), props || {});
+  (children || []).forEach(c => e.append(c));
+  return e;
+}
+
+function sparkline(values) {
+  const w = 120, h = 20, min = Math.min(...values), max = Math.max(...values);
+  const points = values.map((v, i) =>
+    (i * w / Math.max(values.length - 1, 1)).toFixed(1) + "," +
+    (max == min ? h / 2 : h - (v - min) * h / (max - min)).toFixed(1)).join(" ");
+  const svg = document.createElementNS("http://www.w3.org/2000/svg", "svg");
+  svg.setAttribute("width", w);
+  svg.setAttribute("height", h);
+  const line = document.createElementNS("http://www.w3.org/2000/svg", "polyline");
+  line.setAttribute("points", points);
+  line.setAttribute("fill", "none");
+  line.setAttribute("stroke", "#36c");
+  svg.append(line);
+  return svg;
+}
+
+function render(frame) {
+  const seen = new Set();
+  const sections = frame.commands.map(c => {
+    const rows = frame.values.filter(v => v.command == c.command).map(v => {
+      const key = v.command + "\n" + v.line + "\n" + v.index;
+      seen.add(key);
+      const values = (history.get(key) || []).concat([v.value]).slice(-HISTORY);
+      history.set(key, values);
+      const cls = v.delta > 0 ? "up" : v.delta < 0 ? "down" : "";
+      return el("tr", {}, [
+        el("td", {className: "key", textContent: v.line_key}),
+        el("td", {textContent: v.index + 1}),
+        el("td", {textContent: v.value}),
+        el("td", {className: cls, textContent: v.delta == null ? "" : v.delta}),
+        el("td", {textContent: v.rate == null ? "" : v.rate.toFixed(2)}),
+        el("td", {}, [sparkline(values)]),
+      ]);
+    });
+    const head = el("tr", {}, ["line", "column", "value", "delta", "rate", "history"]
+      .map((t, i) => el("th", {className: i ? "" : "key", textContent: t})));
+    return el("section", {}, [
+      el("h3", {textContent: c.command}),
+      el("pre", {textContent: c.output}),
+      el("table", {}, rows.length ? [head].concat(rows) : []),
+    ]);
+  });
+  for (const key of history.keys()) {
+    if (!seen.has(key)) history.delete(key);
+  }
+  document.getElementById("frame").replaceChildren(...sections);
+  document.getElementById("status").textContent =
+    "updated " + new Date(frame.timestamp * 1000).toLocaleTimeString();
+}
+
+const events = new EventSource("events");
+events.onmessage = e => render(JSON.parse(e.data));
+events.onerror = () => document.getElementById("status").textContent = "disconnected, retrying...";
+</script>
+</body>
+</html>
+"##;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http;
+    use std::io::{BufRead, BufReader};
+
+    fn update(frame: &str) -> Update {
+        Update {
+            frame: frame.to_owned(),
+            values: String::new(),
+            samples: Vec::new(),
+            timestamp: Duration::ZERO,
+        }
+    }
+
+    #[test]
+    fn test_api() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let dashboard = spawn(listener);
+        for (t, samples) in [
+            (1, vec![("0.0.0", 10), ("0.1.0", 5)]),
+            (2, vec![("0.0.0", 12)]),
+        ] {
+            dashboard.publish(Update {
+                frame: String::new(),
+                values: format!("{{\"timestamp\":{}}}", t),
+                samples: samples
+                    .into_iter()
+                    .map(|(k, v)| (k.to_owned(), v))
+                    .collect(),
+                timestamp: Duration::from_secs(t),
+            });
+        }
+        dashboard.publish_alerts("[]".to_owned());
+
+        let get = |path: &str| {
+            http::get(&format!("http://{}{}", addr, path), None)
+                .map(|body| String::from_utf8(body).unwrap())
+        };
+        assert_eq!(get("/api/values").unwrap(), r#"{"timestamp":2}"#);
+        assert_eq!(get("/api/alerts").unwrap(), "[]");
+        assert_eq!(
+            get("/api/history/0.0.0").unwrap(),
+            r#"{"key":"0.0.0","samples":[{"timestamp":1,"value":10},{"timestamp":2,"value":12}]}"#
+        );
+        assert!(get("/api/history/0.1.0").is_err());
+    }
+
+    #[test]
+    fn test_serve() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let dashboard = spawn(listener);
+        dashboard.publish(update("{\"n\":1}"));
+
+        let page = http::get(&format!("http://{}/", addr), None).unwrap();
+        assert!(String::from_utf8(page).unwrap().contains("EventSource"));
+        assert!(http::get(&format!("http://{}/other", addr), None).is_err());
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.write_all(b"GET /events HTTP/1.1\r\n\r\n").unwrap();
+        let mut lines = BufReader::new(stream).lines().map(Result::unwrap);
+        assert_eq!(lines.next().unwrap(), "HTTP/1.1 200 OK");
+        assert!(lines.any(|l| l == "data: {\"n\":1}"));
+        // the stream is registered before the last frame is sent
+        dashboard.publish(update("{\"n\":2}"));
+        assert!(lines.any(|l| l == "data: {\"n\":2}"));
+    }
+}